@@ -7,6 +7,7 @@ use ark_poly::reveal;
 use ark_std::{end_timer, start_timer};
 use log::debug;
 use mpc_algebra::boolean_field::MpcBooleanField;
+use mpc_algebra::store::PreprocessingOpt;
 use mpc_algebra::{
     share, AdditiveFieldShare, BitAdd, BitDecomposition, BitwiseLessThan, EqualityZero, LessThan,
     LogicalOperations, MpcField, Reveal, UniformBitRand,
@@ -25,6 +26,9 @@ struct Opt {
     /// Input file
     #[structopt(parse(from_os_str))]
     input: PathBuf,
+
+    #[structopt(flatten)]
+    preprocessing: PreprocessingOpt,
 }
 
 type F = ark_bls12_377::Fr;
@@ -395,6 +399,9 @@ fn main() {
     let opt = Opt::from_args();
     println!("{:?}", opt);
     Net::init_from_file(opt.input.to_str().unwrap(), opt.id);
+    opt.preprocessing
+        .load::<F>()
+        .expect("Failed to load preprocessing");
 
     println!("Test started");
     test_add();
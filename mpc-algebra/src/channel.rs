@@ -1,85 +1,529 @@
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
-use mpc_net::MpcNet;
+use log::error;
+use mpc_net::{MpcMultiNet as Net, MpcNet, NetError, NetResult, Security};
 use rand::RngCore;
 use sha2::{Digest, Sha256};
-use std::cell::Cell;
+use std::panic::AssertUnwindSafe;
 
 /// A trait for MPC networks that can serialize and deserialize.
+///
+/// The `try_` methods report network failures, and values from a peer that do not deserialize
+/// (as [NetError::MalformedFrame]); the others panic on either.
+///
+/// To send values of several types in one round, send a tuple of them (up to four), or put them
+/// in a [Bundle].
 pub trait MpcSerNet: MpcNet {
-    /// Broadcast a value to each other.
-    fn broadcast<T: CanonicalSerialize + CanonicalDeserialize>(out: &T) -> Vec<T> {
-        let mut bytes_out = Vec::new();
-        out.serialize(&mut bytes_out).unwrap();
-        let bytes_in = Self::broadcast_bytes(&bytes_out);
+    /// Broadcast a value to each other. A cheating party may send different values to different
+    /// peers; see [MpcSerNet::try_consistent_broadcast].
+    #[track_caller]
+    fn try_broadcast<T: CanonicalSerialize + CanonicalDeserialize>(out: &T) -> NetResult<Vec<T>> {
+        let bytes_in = Self::try_broadcast_bytes(&serialize(out))?;
         bytes_in
             .into_iter()
-            .map(|b| T::deserialize(&b[..]).unwrap())
+            .enumerate()
+            .map(|(i, b)| deserialize(i, &b))
             .collect()
     }
 
-    fn send_to_king<T: CanonicalDeserialize + CanonicalSerialize>(out: &T) -> Option<Vec<T>> {
-        let mut bytes_out = Vec::new();
-        out.serialize(&mut bytes_out).unwrap();
-        Self::send_bytes_to_king(&bytes_out).map(|bytes_in| {
+    /// Broadcast a value such that all parties that succeed received the same values. A party that
+    /// sends different values to different peers makes the others fail with
+    /// [NetError::Equivocation]. This costs a second round, in which everyone echoes a digest of
+    /// each message they received.
+    #[track_caller]
+    fn try_consistent_broadcast<T: CanonicalSerialize + CanonicalDeserialize>(
+        out: &T,
+    ) -> NetResult<Vec<T>> {
+        let bytes_in = Self::try_consistent_broadcast_bytes(&serialize(out))?;
+        bytes_in
+            .into_iter()
+            .enumerate()
+            .map(|(i, b)| deserialize(i, &b))
+            .collect()
+    }
+
+    /// Like [MpcSerNet::try_consistent_broadcast], but for raw bytes.
+    #[track_caller]
+    fn try_consistent_broadcast_bytes(bytes: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let bytes_in = Self::try_broadcast_bytes(bytes)?;
+        let digests: Vec<u8> = bytes_in.iter().flat_map(|b| EchoHash::digest(b)).collect();
+        let echoes = Self::try_broadcast_bytes(&digests)?;
+        let digest_len = digests.len() / bytes_in.len();
+        for (witness, echo) in echoes.iter().enumerate() {
+            if echo.len() != digests.len() {
+                return Err(NetError::MalformedFrame {
+                    peer: witness,
+                    reason: format!("echoed {} digest bytes, not {}", echo.len(), digests.len()),
+                });
+            }
+            let seen = echo.chunks(digest_len).zip(digests.chunks(digest_len));
+            if let Some(sender) = seen.map(|(theirs, ours)| theirs == ours).position(|eq| !eq) {
+                return Err(NetError::Equivocation { sender, witness });
+            }
+        }
+        Ok(bytes_in)
+    }
+
+    #[track_caller]
+    fn try_send_to_king<T: CanonicalDeserialize + CanonicalSerialize>(
+        out: &T,
+    ) -> NetResult<Option<Vec<T>>> {
+        Self::try_send_bytes_to_king(&serialize(out))?
+            .map(|bytes_in| {
+                bytes_in
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, b)| deserialize(i, &b))
+                    .collect()
+            })
+            .transpose()
+    }
+
+    #[track_caller]
+    fn try_recieve_from_king<T: CanonicalSerialize + CanonicalDeserialize>(
+        out: Option<Vec<T>>,
+    ) -> NetResult<T> {
+        let king = Self::king();
+        let bytes_in =
+            Self::try_recv_bytes_from_king(out.map(|outs| outs.iter().map(serialize).collect()))?;
+        deserialize(king, &bytes_in)
+    }
+
+    /// Broadcast a value that no party can choose based on the others': everyone commits to
+    /// their value first, then opens it. A party whose opening does not match its commitment makes
+    /// the others fail with [NetError::BadOpening]. That takes two rounds, plus an echo round for
+    /// the commitments in a [Security::Production] session.
+    #[track_caller]
+    fn try_atomic_broadcast<T: CanonicalDeserialize + CanonicalSerialize>(
+        out: &T,
+    ) -> NetResult<Vec<T>> {
+        let (opening, commitment) = commit(&serialize(out));
+        // exchange commitments; in production they must be consistent, or a party could open
+        // differently to different peers
+        let all_commits = match Self::security() {
+            Security::Insecure => Self::try_broadcast_bytes(&commitment[..])?,
+            Security::Production => Self::try_consistent_broadcast_bytes(&commitment[..])?,
+        };
+        // exchange (data || randomness)
+        let all_data = Self::try_broadcast_bytes(&opening)?;
+        let opened = all_data
+            .into_iter()
+            .zip(&all_commits)
+            .enumerate()
+            .map(|(i, (d, c))| open(i, c, d))
+            .collect::<NetResult<Vec<_>>>()?;
+        opened
+            .into_iter()
+            .enumerate()
+            .map(|(i, d)| deserialize(i, &d))
+            .collect()
+    }
+
+    #[track_caller]
+    fn try_king_compute<T: CanonicalDeserialize + CanonicalSerialize>(
+        x: &T,
+        f: impl Fn(Vec<T>) -> Vec<T>,
+    ) -> NetResult<T> {
+        let king_response = Self::try_send_to_king(x)?.map(f);
+        Self::try_recieve_from_king(king_response)
+    }
+
+    /// Send a value to party `to` only.
+    #[track_caller]
+    fn try_send_to<T: CanonicalSerialize>(to: usize, out: &T) -> NetResult<()> {
+        Self::try_send_bytes_to(to, &serialize(out))
+    }
+
+    /// Receive the next value party `from` sent us with [MpcSerNet::send_to].
+    #[track_caller]
+    fn try_recv_from<T: CanonicalDeserialize>(from: usize) -> NetResult<T> {
+        deserialize(from, &Self::try_recv_bytes_from(from)?)
+    }
+
+    /// Swap values with party `peer`.
+    #[track_caller]
+    fn try_exchange<T: CanonicalSerialize + CanonicalDeserialize>(
+        peer: usize,
+        out: &T,
+    ) -> NetResult<T> {
+        deserialize(peer, &Self::try_exchange_bytes(peer, &serialize(out))?)
+    }
+
+    /// Broadcast a [Bundle]; returns what each party put in theirs, ours included.
+    #[track_caller]
+    fn try_broadcast_bundle(out: &Bundle) -> NetResult<Vec<Unbundle>> {
+        let bytes_in = Self::try_broadcast_bytes(&out.bytes)?;
+        Ok(bytes_in
+            .into_iter()
+            .enumerate()
+            .map(|(peer, bytes)| Unbundle::new(peer, bytes))
+            .collect())
+    }
+
+    /// Send a [Bundle] to the king, who gets everyone's.
+    #[track_caller]
+    fn try_send_bundle_to_king(out: &Bundle) -> NetResult<Option<Vec<Unbundle>>> {
+        Ok(Self::try_send_bytes_to_king(&out.bytes)?.map(|bytes_in| {
             bytes_in
                 .into_iter()
-                .map(|b| T::deserialize(&b[..]).unwrap())
+                .enumerate()
+                .map(|(peer, bytes)| Unbundle::new(peer, bytes))
                 .collect()
-        })
+        }))
+    }
+
+    /// Receive a [Bundle] from the king, who provides one for each party.
+    #[track_caller]
+    fn try_recv_bundle_from_king(out: Option<Vec<Bundle>>) -> NetResult<Unbundle> {
+        let bytes_out = out.map(|outs| outs.into_iter().map(|b| b.bytes).collect());
+        let king = Self::king();
+        Ok(Unbundle::new(
+            king,
+            Self::try_recv_bytes_from_king(bytes_out)?,
+        ))
+    }
+
+    /// Swap [Bundle]s with party `peer`.
+    #[track_caller]
+    fn try_exchange_bundle(peer: usize, out: &Bundle) -> NetResult<Unbundle> {
+        Ok(Unbundle::new(
+            peer,
+            Self::try_exchange_bytes(peer, &out.bytes)?,
+        ))
+    }
+
+    /// Like [MpcSerNet::try_atomic_broadcast], for a [Bundle].
+    #[track_caller]
+    fn try_atomic_broadcast_bundle(out: &Bundle) -> NetResult<Vec<Unbundle>> {
+        Ok(Self::try_atomic_broadcast(&out.bytes)?
+            .into_iter()
+            .enumerate()
+            .map(|(peer, bytes)| Unbundle::new(peer, bytes))
+            .collect())
+    }
+
+    /// A random seed that no party can bias: everyone contributes randomness with
+    /// [MpcSerNet::try_atomic_broadcast], and the seed hashes all of it.
+    #[track_caller]
+    fn try_joint_coin() -> NetResult<[u8; 32]> {
+        Ok(coin_seed(Self::try_atomic_broadcast(&coin_part())?))
     }
 
+    /// Like [MpcSerNet::try_broadcast], but panics on failure.
+    #[track_caller]
+    fn broadcast<T: CanonicalSerialize + CanonicalDeserialize>(out: &T) -> Vec<T> {
+        Self::try_broadcast(out).unwrap_or_else(|e| panic!("broadcast failed: {}", e))
+    }
+
+    /// Like [MpcSerNet::try_consistent_broadcast], but [abort]s on failure.
+    #[track_caller]
+    fn consistent_broadcast<T: CanonicalSerialize + CanonicalDeserialize>(out: &T) -> Vec<T> {
+        Self::try_consistent_broadcast(out).unwrap_or_else(|e| abort(e))
+    }
+
+    /// Like [MpcSerNet::try_send_to_king], but panics on failure.
+    #[track_caller]
+    fn send_to_king<T: CanonicalDeserialize + CanonicalSerialize>(out: &T) -> Option<Vec<T>> {
+        Self::try_send_to_king(out).unwrap_or_else(|e| panic!("send to king failed: {}", e))
+    }
+
+    /// Like [MpcSerNet::try_recieve_from_king], but panics on failure.
+    #[track_caller]
     fn recieve_from_king<T: CanonicalSerialize + CanonicalDeserialize>(out: Option<Vec<T>>) -> T {
-        let bytes_in = Self::recv_bytes_from_king(out.map(|outs| {
-            outs.iter()
-                .map(|out| {
-                    let mut bytes_out = Vec::new();
-                    out.serialize(&mut bytes_out).unwrap();
-                    bytes_out
-                })
-                .collect()
-        }));
-        T::deserialize(&bytes_in[..]).unwrap()
+        Self::try_recieve_from_king(out)
+            .unwrap_or_else(|e| panic!("receive from king failed: {}", e))
     }
 
+    /// Like [MpcSerNet::try_atomic_broadcast], but [abort]s on failure.
+    #[track_caller]
     fn atomic_broadcast<T: CanonicalDeserialize + CanonicalSerialize>(out: &T) -> Vec<T> {
-        let mut bytes_out = Vec::new();
-        out.serialize(&mut bytes_out).unwrap();
-        let ser_len = bytes_out.len();
-        bytes_out.resize(ser_len + COMMIT_RAND_BYTES, 0);
-        rand::thread_rng().fill_bytes(&mut bytes_out[ser_len..]);
-        let commitment = CommitHash::new().chain(&bytes_out).finalize();
-        // exchange commitments
-        let all_commits = Self::broadcast_bytes(&commitment[..]);
-        // exchange (data || randomness)
-        let all_data = Self::broadcast_bytes(&bytes_out);
-        let self_id = Self::party_id();
-        for i in 0..all_commits.len() {
-            if i != self_id {
-                // check other commitment
-                assert_eq!(
-                    &all_commits[i][..],
-                    &CommitHash::new().chain(&all_data[i]).finalize()[..]
-                );
-            }
-        }
-        all_data
-            .into_iter()
-            .map(|d| T::deserialize(&d[..ser_len]).unwrap())
-            .collect()
+        Self::try_atomic_broadcast(out).unwrap_or_else(|e| abort(e))
     }
 
+    /// Like [MpcSerNet::try_send_to], but panics on failure.
+    #[track_caller]
+    fn send_to<T: CanonicalSerialize>(to: usize, out: &T) {
+        Self::try_send_to(to, out).unwrap_or_else(|e| panic!("send to party {} failed: {}", to, e))
+    }
+
+    /// Like [MpcSerNet::try_recv_from], but panics on failure.
+    #[track_caller]
+    fn recv_from<T: CanonicalDeserialize>(from: usize) -> T {
+        Self::try_recv_from(from)
+            .unwrap_or_else(|e| panic!("receive from party {} failed: {}", from, e))
+    }
+
+    /// Like [MpcSerNet::try_exchange], but panics on failure.
+    #[track_caller]
+    fn exchange<T: CanonicalSerialize + CanonicalDeserialize>(peer: usize, out: &T) -> T {
+        Self::try_exchange(peer, out)
+            .unwrap_or_else(|e| panic!("exchange with party {} failed: {}", peer, e))
+    }
+
+    /// Like [MpcSerNet::try_king_compute], but panics on failure.
+    #[track_caller]
     fn king_compute<T: CanonicalDeserialize + CanonicalSerialize>(
         x: &T,
         f: impl Fn(Vec<T>) -> Vec<T>,
     ) -> T {
-        let king_response = Self::send_to_king(x).map(f);
-        Self::recieve_from_king(king_response)
+        <Self as MpcSerNet>::try_king_compute(x, f)
+            .unwrap_or_else(|e| panic!("king compute failed: {}", e))
     }
 }
 
 impl<N: MpcNet> MpcSerNet for N {}
 
-const ALLOW_CHEATING: Cell<bool> = Cell::new(true);
+/// Give up on the protocol because a check on what the peers sent failed, or the network did.
+///
+/// This unwinds like a panic, so it can be used where no error can be returned, e.g. deep inside
+/// a prover. [catch_abort] turns it back into the error, e.g. to exclude [NetError::cheater].
+#[track_caller]
+pub fn abort(e: NetError) -> ! {
+    error!("Party {} aborts: {}", Net::party_id(), e);
+    std::panic::panic_any(e)
+}
+
+/// Run `f`, returning the error it [abort]ed with, if any. Other panics propagate.
+pub fn catch_abort<R>(f: impl FnOnce() -> R) -> NetResult<R> {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).or_else(|payload| match payload.downcast() {
+        Ok(e) => Err(*e),
+        Err(payload) => std::panic::resume_unwind(payload),
+    })
+}
+
+/// Values of different types, sent together as one message; see e.g.
+/// [MpcSerNet::try_broadcast_bundle].
+///
+/// ```
+/// # use ark_bls12_377::{Fr, G1Projective};
+/// # use mpc_algebra::channel::{Bundle, MpcSerNet};
+/// # use mpc_net::{local::simulate, MpcMultiNet as Net};
+/// simulate(2, |id| {
+///     let out = Bundle::new()
+///         .with(&vec![Fr::from(id as u64); 3])
+///         .with(&vec![id as u8; 32])
+///         .with(&G1Projective::default());
+///     for mut theirs in Net::try_broadcast_bundle(&out).unwrap() {
+///         let evals: Vec<Fr> = theirs.take().unwrap();
+///         let commitment: Vec<u8> = theirs.take().unwrap();
+///         let point: G1Projective = theirs.take().unwrap();
+///         assert_eq!(evals, vec![Fr::from(theirs.peer() as u64); 3]);
+///         theirs.finish().unwrap();
+///     }
+/// });
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bundle {
+    bytes: Vec<u8>,
+}
+
+impl Bundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `x`.
+    pub fn push<T: CanonicalSerialize>(&mut self, x: &T) -> &mut Self {
+        x.serialize(&mut self.bytes).unwrap();
+        self
+    }
+
+    /// Like [Bundle::push], by value.
+    pub fn with<T: CanonicalSerialize>(mut self, x: &T) -> Self {
+        self.push(x);
+        self
+    }
+
+    /// The values pushed so far, as sent.
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// A [Bundle] received from a party. Its values come out in the order they went in.
+#[derive(Debug, Clone)]
+pub struct Unbundle {
+    peer: usize,
+    bytes: Vec<u8>,
+    /// Bytes read so far.
+    pos: usize,
+    /// Values taken so far.
+    taken: usize,
+}
+
+impl Unbundle {
+    pub(crate) fn new(peer: usize, bytes: Vec<u8>) -> Self {
+        Self {
+            peer,
+            bytes,
+            pos: 0,
+            taken: 0,
+        }
+    }
+
+    /// The party that sent this.
+    pub fn peer(&self) -> usize {
+        self.peer
+    }
+
+    /// The next value, which must be a `T`. Fails with [NetError::MalformedFrame] if it does not
+    /// deserialize.
+    pub fn take<T: CanonicalDeserialize>(&mut self) -> NetResult<T> {
+        let mut rest = &self.bytes[self.pos..];
+        let len = rest.len();
+        let x = T::deserialize(&mut rest).map_err(|e| NetError::MalformedFrame {
+            peer: self.peer,
+            reason: format!("value {} of a bundle: {}", self.taken, e),
+        })?;
+        self.pos += len - rest.len();
+        self.taken += 1;
+        Ok(x)
+    }
+
+    /// Check that every value has been taken.
+    pub fn finish(self) -> NetResult<()> {
+        if self.pos == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(NetError::MalformedFrame {
+                peer: self.peer,
+                reason: format!(
+                    "{} bytes left in a bundle after {} values",
+                    self.bytes.len() - self.pos,
+                    self.taken
+                ),
+            })
+        }
+    }
+}
+
+/// Digests of what each party broadcast over a series of rounds, to check at once later that
+/// every party received the same: a [MpcSerNet::try_consistent_broadcast] whose echo round is
+/// deferred, e.g. into the round of a MAC check.
+#[derive(Clone, Default)]
+pub struct Echoes {
+    /// A running digest of each party's values.
+    seen: Vec<EchoHash>,
+}
+
+impl Echoes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `vals`, the values received from each party in a broadcast.
+    pub fn record<T: CanonicalSerialize>(&mut self, vals: &[T]) {
+        if self.seen.is_empty() {
+            self.seen = vec![EchoHash::new(); vals.len()];
+        }
+        for (seen, val) in self.seen.iter_mut().zip(vals) {
+            let bytes = serialize(val);
+            seen.update((bytes.len() as u64).to_le_bytes());
+            seen.update(&bytes);
+        }
+    }
+
+    /// Add our digest of everything recorded to `out`.
+    pub fn push_to(&self, out: &mut Bundle) {
+        out.push(&self.digests());
+    }
+
+    /// Check the digests of every party, next in `theirs`, against ours. Fails with
+    /// [NetError::Equivocation] if a party saw different values than we did.
+    pub fn check(&self, theirs: &mut [Unbundle]) -> NetResult<()> {
+        let ours = self.digests();
+        for t in theirs {
+            let echo: Vec<u8> = t.take()?;
+            if echo.len() != ours.len() {
+                return Err(NetError::MalformedFrame {
+                    peer: t.peer(),
+                    reason: format!("echoed {} digest bytes, not {}", echo.len(), ours.len()),
+                });
+            }
+            let digest_len = <EchoHash as Digest>::output_size();
+            let seen = echo.chunks(digest_len).zip(ours.chunks(digest_len));
+            if let Some(sender) = seen.map(|(theirs, ours)| theirs == ours).position(|eq| !eq) {
+                return Err(NetError::Equivocation {
+                    sender,
+                    witness: t.peer(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn digests(&self) -> Vec<u8> {
+        self.seen
+            .iter()
+            .flat_map(|seen| seen.clone().finalize())
+            .collect()
+    }
+}
+
+fn serialize<T: CanonicalSerialize>(x: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    x.serialize(&mut bytes).unwrap();
+    bytes
+}
+
+/// Deserialize a value received from party `peer`.
+fn deserialize<T: CanonicalDeserialize>(peer: usize, bytes: &[u8]) -> NetResult<T> {
+    T::deserialize(bytes).map_err(|e| NetError::MalformedFrame {
+        peer,
+        reason: e.to_string(),
+    })
+}
+
+/// Commit to `bytes`: returns the opening, i.e. `bytes` followed by fresh randomness, and the
+/// commitment to send ahead of it.
+pub(crate) fn commit(bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut opening = bytes.to_vec();
+    opening.resize(bytes.len() + COMMIT_RAND_BYTES, 0);
+    rand::thread_rng().fill_bytes(&mut opening[bytes.len()..]);
+    let commitment = CommitHash::digest(&opening).to_vec();
+    (opening, commitment)
+}
+
+/// The bytes party `peer` committed to with [commit], if its `opening` matches its `commitment`.
+/// Fails with [NetError::BadOpening] otherwise.
+pub(crate) fn open(peer: usize, commitment: &[u8], mut opening: Vec<u8>) -> NetResult<Vec<u8>> {
+    if commitment != &CommitHash::digest(&opening)[..] {
+        return Err(NetError::BadOpening { peer });
+    }
+    // Values may serialize to different lengths; the randomness is always last.
+    let len = opening
+        .len()
+        .checked_sub(COMMIT_RAND_BYTES)
+        .ok_or_else(|| NetError::MalformedFrame {
+            peer,
+            reason: "opening is shorter than the commitment randomness".to_owned(),
+        })?;
+    opening.truncate(len);
+    Ok(opening)
+}
+
+/// This party's random part of a joint coin; see [MpcSerNet::try_joint_coin].
+pub(crate) fn coin_part() -> Vec<u8> {
+    let mut mine = vec![0u8; COMMIT_RAND_BYTES];
+    rand::thread_rng().fill_bytes(&mut mine);
+    mine
+}
+
+/// The seed of a joint coin, from every party's part.
+pub(crate) fn coin_seed(parts: impl IntoIterator<Item = Vec<u8>>) -> [u8; 32] {
+    let mut hash = CoinHash::new();
+    for part in parts {
+        hash.update(&part);
+    }
+    hash.finalize().into()
+}
+
+/// Settle every check the session's protocols still owe, e.g. the SPDZ MAC check, before
+/// anything computed in it leaves: is shown to the user, written out, or decides what to do next.
+/// [abort]s if a check fails; see [MpcNet::try_checkpoint].
+#[track_caller]
+pub fn checkpoint() {
+    Net::try_checkpoint().unwrap_or_else(|e| abort(e))
+}
 
 /// Number of randomness bytes to use in the commitment scheme
 const COMMIT_RAND_BYTES: usize = 32;
@@ -87,14 +531,253 @@ const COMMIT_RAND_BYTES: usize = 32;
 /// The hash function to use for the commitment
 type CommitHash = Sha256;
 
+/// The hash function whose digests are echoed in a consistent broadcast
+type EchoHash = Sha256;
+
+/// The hash function that combines the randomness of a joint coin
+type CoinHash = Sha256;
+
+/// May this party take shortcuts that break security, e.g. use a global MAC key? Only in an
+/// [Security::Insecure] session; see [MpcNet::security].
 #[inline]
 pub fn can_cheat() -> bool {
-    ALLOW_CHEATING.get()
+    Net::security() == Security::Insecure
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use mpc_net::{
+        local::{simulate, LocalTransport},
+        King, MpcMultiNet as Net, MpcSession,
+    };
+    use std::sync::Arc;
 
     #[test]
     fn test_sernet() {}
+
+    #[test]
+    fn point_to_point() {
+        let outputs = simulate(3, |id| {
+            // Everyone hands party 0 a private value, and parties 1 and 2 swap theirs.
+            let to_zero = if id == 0 {
+                Some((1..3).map(Net::recv_from::<u64>).collect::<Vec<_>>())
+            } else {
+                Net::send_to(0, &(10 * id as u64));
+                None
+            };
+            let swapped = (id != 0).then(|| Net::exchange(3 - id, &(id as u64)));
+            (to_zero, swapped)
+        });
+        assert_eq!(outputs[0], (Some(vec![10, 20]), None));
+        assert_eq!(outputs[1], (None, Some(2)));
+        assert_eq!(outputs[2], (None, Some(1)));
+    }
+
+    #[test]
+    fn consistent_broadcast_catches_a_false_echo() {
+        let results = simulate(3, |id| {
+            if id == 2 {
+                // Receive honestly, then claim to have seen something else from party 1.
+                let bytes_in = Net::broadcast_bytes(&[2u8; 8]);
+                let mut digests: Vec<u8> =
+                    bytes_in.iter().flat_map(|b| EchoHash::digest(b)).collect();
+                digests[32] ^= 1;
+                Net::broadcast_bytes(&digests);
+                None
+            } else {
+                Some(Net::try_consistent_broadcast(&(id as u64)))
+            }
+        });
+        for r in [&results[0], &results[1]] {
+            assert!(matches!(
+                r,
+                Some(Err(NetError::Equivocation {
+                    sender: 1,
+                    witness: 2
+                }))
+            ));
+        }
+        let agreed = simulate(3, |id| Net::consistent_broadcast(&(id as u64)));
+        assert!(agreed.iter().all(|vals| vals == &[0, 1, 2]));
+    }
+
+    #[test]
+    fn deferred_echoes_catch_an_equivocation() {
+        let results = simulate(3, |id| {
+            let mut echoes = Echoes::new();
+            let mut vals = Net::broadcast(&(id as u64));
+            echoes.record(&vals);
+            if id == 2 {
+                // Claim party 1 sent something else in the second broadcast.
+                vals[1] += 1;
+            }
+            echoes.record(&vals);
+            let mut out = Bundle::new();
+            echoes.push_to(&mut out);
+            let mut theirs = Net::try_atomic_broadcast_bundle(&out).unwrap();
+            echoes.check(&mut theirs)
+        });
+        for r in &results[..2] {
+            assert!(matches!(
+                r,
+                Err(NetError::Equivocation {
+                    sender: 1,
+                    witness: 2
+                })
+            ));
+        }
+        assert!(matches!(
+            results[2],
+            Err(NetError::Equivocation {
+                sender: 1,
+                witness: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn atomic_broadcast_names_a_bad_opening() {
+        let results = simulate(3, |id| {
+            if id == 2 {
+                // Commit to one value, then open another.
+                let commitment = CommitHash::digest(&[0u8; 8 + COMMIT_RAND_BYTES]);
+                Net::broadcast_bytes(&commitment);
+                Net::broadcast_bytes(&[1u8; 8 + COMMIT_RAND_BYTES]);
+                None
+            } else {
+                Some(Net::try_atomic_broadcast(&(id as u64)))
+            }
+        });
+        for r in [&results[0], &results[1]] {
+            let e = r.as_ref().unwrap().as_ref().unwrap_err();
+            assert!(matches!(e, NetError::BadOpening { peer: 2 }));
+            assert_eq!(e.cheater(), Some(2));
+        }
+        let caught = simulate(2, |id| catch_abort(|| Net::atomic_broadcast(&(id as u64))));
+        assert!(caught.iter().all(|r| r.as_ref().unwrap() == &[0, 1]));
+    }
+
+    #[test]
+    fn atomic_broadcast_echoes_only_in_production() {
+        for (security, rounds) in [(Security::Insecure, 2), (Security::Production, 3)] {
+            let outputs: Vec<_> = std::thread::scope(|s| {
+                let handles: Vec<_> = LocalTransport::mesh(3)
+                    .into_iter()
+                    .map(|t| {
+                        let session =
+                            Arc::new(MpcSession::from_transport(t).with_security(security));
+                        s.spawn(move || {
+                            session.enter(|| {
+                                let round = session.round();
+                                let all = Net::atomic_broadcast(&(session.party_id() as u64));
+                                (all, session.round() - round)
+                            })
+                        })
+                    })
+                    .collect();
+                handles.into_iter().map(|h| h.join().unwrap()).collect()
+            });
+            for (all, used) in outputs {
+                assert_eq!(all, vec![0, 1, 2]);
+                assert_eq!(used, rounds);
+            }
+        }
+    }
+
+    #[test]
+    fn mixed_values_in_one_round() {
+        use ark_ec::ProjectiveCurve;
+        use mpc_net::MpcSession;
+        type F = ark_bls12_377::Fr;
+        type G = ark_bls12_377::G1Projective;
+        let outputs = simulate(3, |id| {
+            let evals = vec![F::from(id as u64); id + 1];
+            let point = G::prime_subgroup_generator().mul([id as u64]);
+            let session = MpcSession::current().unwrap();
+            let round = session.round();
+            let tuples = Net::try_broadcast(&(evals.clone(), vec![id as u8; 4], point)).unwrap();
+            let mut bundle = Bundle::new();
+            bundle.push(&evals).push(&point).push(&(id as u64));
+            let bundles = Net::try_broadcast_bundle(&bundle).unwrap();
+            let from_king = Net::try_send_bundle_to_king(&bundle).unwrap().map(|all| {
+                all.into_iter()
+                    .map(|mut theirs| Bundle::new().with(&theirs.take::<Vec<F>>().unwrap()))
+                    .collect()
+            });
+            let mut back = Net::try_recv_bundle_from_king(from_king).unwrap();
+            assert_eq!(back.take::<Vec<F>>().unwrap(), evals);
+            back.finish().unwrap();
+            // One round each for the tuple and the bundle, two for the king.
+            assert_eq!(session.round() - round, 4);
+            (tuples, bundles)
+        });
+        for (tuples, bundles) in outputs {
+            for (j, (tuple, mut bundle)) in tuples.into_iter().zip(bundles).enumerate() {
+                let point = G::prime_subgroup_generator().mul([j as u64]);
+                assert_eq!(
+                    tuple,
+                    (vec![F::from(j as u64); j + 1], vec![j as u8; 4], point)
+                );
+                assert_eq!(bundle.take::<Vec<F>>().unwrap(), tuple.0);
+                assert_eq!(bundle.take::<G>().unwrap(), point);
+                // The wrong type, or too many values, is an error naming the sender.
+                assert!(bundle.clone().take::<(u64, u64)>().is_err());
+                assert_eq!(bundle.take::<u64>().unwrap(), j as u64);
+                assert!(matches!(
+                    bundle.clone().take::<u8>(),
+                    Err(NetError::MalformedFrame { peer, .. }) if peer == j
+                ));
+                bundle.finish().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_value_is_reported() {
+        // Party 1 sends a truncated u64; everyone else sends a whole one.
+        let results = simulate(3, |id| {
+            if id == 1 {
+                Net::broadcast_bytes(&[1u8; 4]);
+                None
+            } else {
+                Some(Net::try_broadcast(&(id as u64)))
+            }
+        });
+        for r in [&results[0], &results[2]] {
+            assert!(matches!(
+                r,
+                Some(Err(NetError::MalformedFrame { peer: 1, .. }))
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_king_value_blames_the_king() {
+        // Party 2 is king, and hands everyone a truncated u64.
+        let results: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = LocalTransport::mesh(3)
+                .into_iter()
+                .map(|t| {
+                    let session = Arc::new(MpcSession::from_transport(t).with_king(King::fixed(2)));
+                    s.spawn(move || {
+                        session.enter(|| match Net::am_king() {
+                            true => {
+                                Net::recv_bytes_from_king(Some(vec![vec![1u8; 4]; 3]));
+                                None
+                            }
+                            false => Some(Net::try_recieve_from_king::<u64>(None)),
+                        })
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for r in &results[..2] {
+            assert!(matches!(
+                r,
+                Some(Err(NetError::MalformedFrame { peer: 2, .. }))
+            ));
+        }
+    }
 }
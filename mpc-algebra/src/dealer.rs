@@ -0,0 +1,227 @@
+//! A trusted dealer of SPDZ preprocessing material.
+//!
+//! **Insecure, for development only.** The dealer samples the MAC key and every masked value
+//! itself, so whoever runs it can read every party's inputs and forge any MAC. It stands in for
+//! the offline phase, so that the online phase and applications run on correctly authenticated
+//! material while real offline protocols mature.
+use std::path::{Path, PathBuf};
+
+use ark_ff::Field;
+use rand::Rng;
+
+use crate::share::spdz::{InputMask, SpdzFieldShare};
+use crate::store::{Counts, Material, PreprocessingStore, StoreError, StoreResult};
+
+/// Where party `party_id` keeps its store in `dir`.
+pub fn store_path(dir: impl AsRef<Path>, party_id: usize) -> PathBuf {
+    dir.as_ref().join(format!("party{}.prep", party_id))
+}
+
+/// Additive shares of `x`, one per party.
+fn split<F: Field, R: Rng>(x: F, n_parties: usize, rng: &mut R) -> Vec<F> {
+    let mut parts: Vec<F> = (1..n_parties).map(|_| F::rand(rng)).collect();
+    parts.push(x - parts.iter().sum::<F>());
+    parts
+}
+
+/// SPDZ shares of `x` under the MAC key `alpha`, one per party.
+fn authenticate<F: Field, R: Rng>(
+    x: F,
+    alpha: F,
+    n_parties: usize,
+    rng: &mut R,
+) -> Vec<SpdzFieldShare<F>> {
+    split(x, n_parties, rng)
+        .into_iter()
+        .zip(split(alpha * x, n_parties, rng))
+        .map(|(share, mac)| SpdzFieldShare::from_parts(share, mac))
+        .collect()
+}
+
+/// Deal `counts` of each kind of material under the MAC key `alpha`, and `counts.input_masks`
+/// input masks owned by each party. Returns each party's part.
+pub fn deal<F: Field, R: Rng>(
+    alpha: F,
+    n_parties: usize,
+    counts: Counts,
+    rng: &mut R,
+) -> Vec<Material<F>> {
+    let mut parts: Vec<_> = (0..n_parties).map(|_| Material::new(n_parties)).collect();
+    for _ in 0..counts.triples {
+        let (a, b) = (F::rand(rng), F::rand(rng));
+        let xs = authenticate(a, alpha, n_parties, rng);
+        let ys = authenticate(b, alpha, n_parties, rng);
+        let zs = authenticate(a * b, alpha, n_parties, rng);
+        for (part, triple) in parts.iter_mut().zip(xs.into_iter().zip(ys).zip(zs)) {
+            let ((x, y), z) = triple;
+            part.triples.push((x, y, z));
+        }
+    }
+    for _ in 0..counts.inv_pairs {
+        let r = loop {
+            let r = F::rand(rng);
+            if !r.is_zero() {
+                break r;
+            }
+        };
+        // `inv` masks `a` by the first share, opens `ra`, and scales the second by `(ra)^-1`; the
+        // pair's shape is [crate::BeaverSource]'s, and both halves must be the same `r`.
+        for (part, s) in parts.iter_mut().zip(authenticate(r, alpha, n_parties, rng)) {
+            part.inv_pairs.push((s, s));
+        }
+    }
+    for _ in 0..counts.bits {
+        let b = F::from(rng.gen::<bool>());
+        for (part, s) in parts.iter_mut().zip(authenticate(b, alpha, n_parties, rng)) {
+            part.bits.push(s);
+        }
+    }
+    for owner in 0..n_parties {
+        for _ in 0..counts.input_masks {
+            let r = F::rand(rng);
+            let shares = authenticate(r, alpha, n_parties, rng);
+            for (id, (part, share)) in parts.iter_mut().zip(shares).enumerate() {
+                let value = (id == owner).then_some(r);
+                part.input_masks[owner].push(InputMask { share, value });
+            }
+        }
+    }
+    parts
+}
+
+/// Deal `counts` of material into each of `n_parties` parties' stores in `dir`. If the stores
+/// exist, the dealer recovers their MAC key and appends; otherwise it samples a key and creates
+/// them.
+pub fn deal_to_stores<F: Field, R: Rng>(
+    dir: impl AsRef<Path>,
+    n_parties: usize,
+    counts: Counts,
+    rng: &mut R,
+) -> StoreResult<Vec<PreprocessingStore<F>>> {
+    let paths: Vec<_> = (0..n_parties).map(|id| store_path(&dir, id)).collect();
+    let mut stores = match paths.iter().filter(|p| p.exists()).count() {
+        0 => {
+            let alpha = F::rand(rng);
+            let keys = split(alpha, n_parties, rng);
+            paths
+                .iter()
+                .zip(keys)
+                .enumerate()
+                .map(|(id, (path, key))| PreprocessingStore::create(path, id, n_parties, key))
+                .collect::<StoreResult<Vec<_>>>()?
+        }
+        n if n == n_parties => paths
+            .iter()
+            .enumerate()
+            .map(|(id, path)| PreprocessingStore::open(path, id, n_parties))
+            .collect::<StoreResult<Vec<_>>>()?,
+        n => {
+            return Err(StoreError::Mismatch(format!(
+                "{} of {} parties already have stores",
+                n, n_parties
+            )))
+        }
+    };
+    let alpha = stores.iter().map(PreprocessingStore::mac_share).sum();
+    for (store, part) in stores.iter_mut().zip(deal(alpha, n_parties, counts, rng)) {
+        store.append(&part)?;
+    }
+    Ok(stores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boolean_field::MpcBooleanField;
+    use crate::malicious_majority::MpcField;
+    use crate::share::spdz::mac_check;
+    use crate::TripleStore;
+    use crate::{BitDecomposition, Reveal, UniformBitRand};
+    use ark_ff::{One, UniformRand, Zero};
+    use mpc_net::{local::simulate, MpcMultiNet as Net, MpcNet};
+
+    type F = ark_bls12_377::Fr;
+
+    /// A fresh directory for the stores of test `name`.
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mpc-dealer-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn dealt_stores_run_the_online_phase() {
+        let dir = dir("online");
+        let counts = Counts {
+            triples: 3,
+            inv_pairs: 1,
+            bits: 4,
+            input_masks: 2,
+        };
+        let rng = &mut ark_std::test_rng();
+        deal_to_stores::<F, _>(&dir, 3, counts, rng).unwrap();
+        // Dealing again appends under the same key.
+        let stores = deal_to_stores::<F, _>(&dir, 3, counts, rng).unwrap();
+        assert_eq!(stores[2].left().triples, 6);
+        drop(stores);
+
+        let results = simulate(3, |id| {
+            let mut store = PreprocessingStore::<F>::open(store_path(&dir, id), id, 3).unwrap();
+            // Run on the second deal.
+            store.take(counts).unwrap();
+            store.take(counts).unwrap().load(store.mac_share());
+            let rng = &mut ark_std::test_rng();
+            let x = MpcField::<F>::king_share(F::from(6u64), rng);
+            let y = MpcField::<F>::king_share(F::from(7u64), rng);
+            let product = (x * y).reveal();
+            let quotient = (x / y).reveal();
+            let bits: Vec<F> = (0..counts.bits)
+                .map(|_| {
+                    MpcBooleanField::<F, SpdzFieldShare<F>>::bit_rand(rng)
+                        .field()
+                        .reveal()
+                })
+                .collect();
+            (product, quotient, bits, mac_check())
+        });
+        for (product, quotient, bits, checked) in results {
+            assert_eq!(product, F::from(42u64));
+            assert_eq!(quotient, F::from(6u64) / F::from(7u64));
+            assert!(bits.iter().all(|b| b.is_zero() || b.is_one()));
+            checked.unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dealt_bits_decompose() {
+        let dir = dir("bits");
+        let counts = Counts {
+            triples: 1300,
+            inv_pairs: 0,
+            bits: 2 * 253,
+            input_masks: 1,
+        };
+        deal_to_stores::<F, _>(&dir, 3, counts, &mut ark_std::test_rng()).unwrap();
+        let results = simulate(3, |id| {
+            let mut store = PreprocessingStore::<F>::open(store_path(&dir, id), id, 3).unwrap();
+            store.take(counts).unwrap().load(store.mac_share());
+            let x = MpcField::<F>::rand(&mut ark_std::test_rng());
+            let bits = x.bit_decomposition();
+            let value = x.reveal();
+            let bits: Vec<F> = bits.into_iter().map(|b| b.field().reveal()).collect();
+            let store = Net::attached::<TripleStore<SpdzFieldShare<F>>>().unwrap();
+            (value, bits, store.bits_left(), mac_check())
+        });
+        for (value, bits, bits_left, checked) in results {
+            let recomposed = bits.iter().rev().fold(F::zero(), |acc, b| acc.double() + b);
+            assert_eq!(recomposed, value);
+            // Spent from the store, a modulus-length batch at a time.
+            assert_eq!((counts.bits - bits_left) % 253, 0);
+            assert!(bits_left < counts.bits);
+            checked.unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -13,6 +13,10 @@ pub use r1cs_helper::*;
 
 pub mod channel;
 
+pub mod store;
+
+pub mod dealer;
+
 pub mod honest_but_curious {
     use super::{
         share::additive::*,
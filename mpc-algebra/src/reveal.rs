@@ -1,5 +1,6 @@
 #![macro_use]
 use ark_std::{collections::BTreeMap, marker::PhantomData, rc::Rc};
+use mpc_net::NetResult;
 use rand::Rng;
 
 /// A type should implement [Reveal] if it represents the MPC abstraction of some base type.
@@ -17,6 +18,11 @@ pub trait Reveal: Sized {
 
     /// Reveal shared data, yielding plain data.
     fn reveal(self) -> Self::Base;
+    /// Like [Reveal::reveal], but a failed check on the values the other parties open (see
+    /// [mpc_net::NetError::MacCheck]) is returned rather than [crate::channel::abort]ing.
+    fn try_reveal(self) -> NetResult<Self::Base> {
+        Ok(self.reveal())
+    }
     /// Construct a share of the sum of the `b` over all machines in the protocol.
     fn from_add_shared(b: Self::Base) -> Self;
     /// Lift public data (same in all machines) into shared data.
@@ -101,6 +107,9 @@ impl<T: Reveal> Reveal for Vec<T> {
     fn reveal(self) -> Self::Base {
         self.into_iter().map(|x| x.reveal()).collect()
     }
+    fn try_reveal(self) -> NetResult<Self::Base> {
+        self.into_iter().map(|x| x.try_reveal()).collect()
+    }
     fn from_public(other: Self::Base) -> Self {
         other
             .into_iter()
@@ -139,6 +148,9 @@ where
     fn reveal(self) -> Self::Base {
         self.into_iter().map(|x| x.reveal()).collect()
     }
+    fn try_reveal(self) -> NetResult<Self::Base> {
+        self.into_iter().map(|x| x.try_reveal()).collect()
+    }
     fn from_public(other: Self::Base) -> Self {
         other.into_iter().map(|x| Reveal::from_public(x)).collect()
     }
@@ -170,6 +182,9 @@ impl<T: Reveal> Reveal for Option<T> {
     fn reveal(self) -> Self::Base {
         self.map(|x| x.reveal())
     }
+    fn try_reveal(self) -> NetResult<Self::Base> {
+        self.map(|x| x.try_reveal()).transpose()
+    }
     fn from_public(other: Self::Base) -> Self {
         other.map(|x| <T as Reveal>::from_public(x))
     }
@@ -217,6 +232,9 @@ impl<A: Reveal, B: Reveal> Reveal for (A, B) {
     fn reveal(self) -> Self::Base {
         (self.0.reveal(), self.1.reveal())
     }
+    fn try_reveal(self) -> NetResult<Self::Base> {
+        Ok((self.0.try_reveal()?, self.1.try_reveal()?))
+    }
     fn from_public(other: Self::Base) -> Self {
         (
             <A as Reveal>::from_public(other.0),
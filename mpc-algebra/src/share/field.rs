@@ -8,6 +8,8 @@ use core::panic;
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
 
+use mpc_net::NetResult;
+
 use crate::{BeaverSource, Reveal};
 
 pub trait FieldShare<F: Field>:
@@ -40,6 +42,11 @@ pub trait FieldShare<F: Field>:
         selfs.into_iter().map(|s| s.open()).collect()
     }
 
+    /// Like [FieldShare::batch_open], but a failed check is returned; see [Reveal::try_reveal].
+    fn try_batch_open(selfs: impl IntoIterator<Item = Self>) -> NetResult<Vec<F>> {
+        Ok(Self::batch_open(selfs))
+    }
+
     fn add(&mut self, other: &Self) -> &mut Self;
 
     fn sub(&mut self, other: &Self) -> &mut Self {
@@ -8,6 +8,8 @@ use ark_serialize::{
 use std::fmt::Debug;
 use std::hash::Hash;
 
+use mpc_net::NetResult;
+
 use crate::Reveal;
 
 use super::field::FieldShare;
@@ -43,6 +45,11 @@ pub trait GroupShare<G: Group>:
         selfs.into_iter().map(|s| s.open()).collect()
     }
 
+    /// Like [GroupShare::batch_open], but a failed check is returned; see [Reveal::try_reveal].
+    fn try_batch_open(selfs: impl IntoIterator<Item = Self>) -> NetResult<Vec<G>> {
+        Ok(Self::batch_open(selfs))
+    }
+
     fn add(&mut self, other: &Self) -> &mut Self;
 
     fn sub(&mut self, other: &Self) -> &mut Self {
@@ -1,6 +1,6 @@
 #![macro_use]
 use derivative::Derivative;
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use ark_ec::{group::Group, AffineCurve, PairingEngine, ProjectiveCurve};
 use ark_ff::bytes::{FromBytes, ToBytes};
@@ -10,14 +10,19 @@ use ark_serialize::{
     CanonicalSerializeWithFlags, Flags, SerializationError,
 };
 
+use std::any::Any;
 use std::cmp::Ord;
+use std::collections::VecDeque;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::hash::Hash;
 use std::io::{self, Read, Write};
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
 
-use crate::channel::{can_cheat, MpcSerNet};
-use mpc_net::{MpcMultiNet as Net, MpcNet};
+use crate::channel::{
+    abort, can_cheat, coin_part, coin_seed, commit, open, Bundle, Echoes, MpcSerNet, Unbundle,
+};
+use mpc_net::{MpcMultiNet as Net, MpcNet, NetError, NetResult};
 
 use super::additive::{AdditiveFieldShare, AdditiveGroupShare, MulFieldShare};
 use super::field::{DenseOrSparsePolynomial, DensePolynomial, ExtFieldShare, FieldShare};
@@ -27,23 +32,461 @@ use super::pairing::{AffProjShare, PairingShare};
 use super::{BeaverSource, PanicBeaverSource};
 use crate::Reveal;
 
+/// This party's SPDZ preprocessing for the field `F`: its share αᵢ of the MAC key α, and the input
+/// masks [Reveal::king_share] spends to share values and [UniformRand::rand] to share random ones.
+///
+/// Without it, an [mpc_net::Security::Insecure] session uses the public key α = 1, held by the first king,
+/// and fabricates MACs with [mac]. Once it is loaded, no party knows α: MACs only come from
+/// this material and the input protocol, and [mac] panics. Group elements are input with the
+/// same masks, scaled by a public base.
+#[derive(Debug)]
+pub struct SpdzMaterial<F: Field> {
+    mac_share: F,
+    /// The unspent masks of each party, in the order they are spent.
+    input_masks: Mutex<Vec<VecDeque<InputMask<F>>>>,
+}
+
+/// A random value `r`, shared with a MAC, that its owner also knows in the clear. The owner inputs
+/// `x` with it by sending `x - r` to everyone.
+#[derive(Debug, Clone, Copy)]
+pub struct InputMask<F: Field> {
+    pub share: SpdzFieldShare<F>,
+    /// `r`, for the owner only.
+    pub value: Option<F>,
+}
+
+impl<F: Field> SpdzMaterial<F> {
+    /// Material with MAC key share `mac_share` and no input masks, for `n_parties` parties.
+    pub fn new(mac_share: F, n_parties: usize) -> Self {
+        Self {
+            mac_share,
+            input_masks: Mutex::new(vec![VecDeque::new(); n_parties]),
+        }
+    }
+
+    /// Add `masks` owned by party `owner`.
+    pub fn with_input_masks(
+        self,
+        owner: usize,
+        masks: impl IntoIterator<Item = InputMask<F>>,
+    ) -> Self {
+        self.input_masks.lock().expect("Poisoned SpdzMaterial")[owner].extend(masks);
+        self
+    }
+
+    /// Attach this material to the current session, replacing any loaded before. Do so after the
+    /// network is initialized, and at the same point on every party.
+    pub fn load(self) {
+        Net::attach(self)
+    }
+
+    /// Input masks of party `owner` left unspent.
+    pub fn input_masks_left(&self, owner: usize) -> usize {
+        self.input_masks.lock().expect("Poisoned SpdzMaterial")[owner].len()
+    }
+
+    /// Spend the next `n` input masks of party `owner`. Panics if there are not that many left.
+    fn take_input_masks(&self, owner: usize, n: usize) -> Vec<InputMask<F>> {
+        let mut masks = self.input_masks.lock().expect("Poisoned SpdzMaterial");
+        let left = masks[owner].len();
+        if left < n {
+            drop(masks);
+            panic!(
+                "Out of input masks of party {}: need {}, have {}",
+                owner, n, left
+            );
+        }
+        masks[owner].drain(..n).collect()
+    }
+}
+
+/// The material loaded into the current session for `F`, if any.
+fn material<F: Field>() -> Option<Arc<SpdzMaterial<F>>> {
+    Net::attached::<SpdzMaterial<F>>()
+}
+
+/// This party's share of the MAC key, from its [SpdzMaterial].
+///
+/// Without material, an insecure session uses a public key of one, held by the first king rather
+/// than the current one, since it must not move when the king rotates. A production session
+/// panics instead.
 #[inline]
 pub fn mac_share<F: Field>() -> F {
-    if Net::am_king() {
-        F::one()
+    match material::<F>() {
+        Some(material) => material.mac_share,
+        None if can_cheat() => {
+            if Net::party_id() == Net::first_king() {
+                F::one()
+            } else {
+                F::zero()
+            }
+        }
+        None => panic!("No SPDZ MAC key share is loaded in this production session"),
+    }
+}
+
+/// Fail with [NetError::MacCheck] on the opened value at `index`, unless its check `passed`.
+fn check_mac(index: usize, passed: bool) -> NetResult<()> {
+    if passed {
+        Ok(())
     } else {
-        F::zero()
+        Err(NetError::MacCheck { index })
     }
 }
 
 #[inline]
-/// A huge cheat. Useful for importing shares.
+/// A huge cheat. Useful for importing shares. Panics in a [mpc_net::Security::Production] session, and once
+/// a [SpdzMaterial] is loaded, since then nobody knows the key.
 pub fn mac<F: Field>() -> F {
-    if can_cheat() {
-        F::one()
+    if !can_cheat() {
+        panic!("Attempted to grab the MAC secret in a production session")
+    }
+    if material::<F>().is_some() {
+        panic!("Attempted to grab the MAC secret, but it is secret-shared in this session")
+    }
+    F::one()
+}
+
+/// The input protocol (_Pragmatic MPC_ 6.6.1): the king inputs `values`, spending one of its
+/// input masks each; the other parties' `values` are ignored. In a
+/// [mpc_net::Security::Production] session the king sends its offsets by consistent broadcast,
+/// so a king that tells parties different offsets is caught, and blamed, at once.
+fn input<F: Field>(material: &SpdzMaterial<F>, values: Vec<F>) -> Vec<SpdzFieldShare<F>> {
+    let king = Net::king();
+    let masks = material.take_input_masks(king, values.len());
+    let offsets: Option<Vec<F>> = Net::am_king().then(|| {
+        values
+            .iter()
+            .zip(&masks)
+            .map(|(x, mask)| *x - mask.value.expect("An input mask without its value"))
+            .collect()
+    });
+    masks
+        .into_iter()
+        .zip(send_offsets(offsets, values.len()))
+        .map(|(mask, offset)| {
+            let mut share = mask.share;
+            share.shift(&offset);
+            share
+        })
+        .collect()
+}
+
+/// Hand the king's `offsets` for `n` inputs to everyone, as [input] describes.
+fn send_offsets<T: Clone + CanonicalSerialize + CanonicalDeserialize>(
+    offsets: Option<Vec<T>>,
+    n: usize,
+) -> Vec<T> {
+    let king = Net::king();
+    let offsets: Vec<T> = if can_cheat() {
+        Net::recieve_from_king(offsets.map(|o| vec![o; Net::n_parties()]))
     } else {
-        panic!("Attempted to grab the MAC secret while cheating was not allowed")
+        let mut all = Net::consistent_broadcast(&offsets.unwrap_or_default());
+        all.swap_remove(king)
+    };
+    if offsets.len() != n {
+        abort(NetError::MalformedFrame {
+            peer: king,
+            reason: format!("{} input offsets, expected {}", offsets.len(), n),
+        });
+    }
+    offsets
+}
+
+/// The public base group inputs mask with: an input mask `r` of the scalar field masks `r·B`.
+/// Any fixed element will do in a prime-order group, so every party derives the same one.
+fn input_base<G: Group>() -> G {
+    G::rand(&mut StdRng::seed_from_u64(0))
+}
+
+/// Like [input], for group elements: the king spends its input masks `r`, scaled to `r·B` by
+/// [input_base].
+fn input_group<G: Group, M: Msm<G, G::ScalarField>>(
+    material: &SpdzMaterial<G::ScalarField>,
+    values: Vec<G>,
+) -> Vec<SpdzGroupShare<G, M>> {
+    let base = input_base::<G>();
+    let masks = material.take_input_masks(Net::king(), values.len());
+    let offsets: Option<Vec<G>> = Net::am_king().then(|| {
+        values
+            .iter()
+            .zip(&masks)
+            .map(|(x, mask)| *x - base.mul(&mask.value.expect("An input mask without its value")))
+            .collect()
+    });
+    masks
+        .into_iter()
+        .zip(send_offsets(offsets, values.len()))
+        .map(|(mask, offset)| {
+            let mut share = SpdzGroupShare::scale_pub_group(base, &mask.share);
+            share.shift(&offset);
+            share
+        })
+        .collect()
+}
+
+/// Check the MACs of every value opened in this session since the last check (_Pragmatic MPC_
+/// 6.6.2): all parties check a random linear combination of them, with coefficients from a joint
+/// coin. If it fails, they find the first bad value with one more round, and fail with
+/// [NetError::MacCheck] naming its index among the values checked.
+///
+/// That takes three rounds: one to open the coin, whose parts were committed to during the last
+/// check, one to commit to the combinations and to the parts of the next coin, and one to open
+/// the combinations. The first check of a session commits to its coin in a fourth. The same rounds
+/// echo a digest of the shares each party received in those openings, and of the commitments,
+/// which fails with [NetError::Equivocation] if a party sent different ones to different peers.
+///
+/// [Reveal::reveal] and [FieldShare::batch_open] leave the check to this, so an opened value must
+/// not leave the session (be shown, written out, or decide what to reveal next) before it is
+/// checked. The session runs this at each [crate::channel::checkpoint], and when it is closed or
+/// dropped. Channels split off a session share its log of values opened so far: check before
+/// splitting.
+pub fn mac_check() -> NetResult<()> {
+    let log = match Net::attached::<MacLog>() {
+        Some(log) => log,
+        None => return Ok(()),
+    };
+    let mut pending = std::mem::take(&mut *log.pending.lock().expect("Poisoned MacLog"));
+    if pending.checks.is_empty() {
+        return Ok(());
+    }
+    let coin = match log.coin.lock().expect("Poisoned MacLog").take() {
+        Some(coin) => coin,
+        None => {
+            let (opening, commitment) = commit(&coin_part());
+            let commitments = Net::try_broadcast_bytes(&commitment)?;
+            pending.echoes.record(&commitments);
+            Committed {
+                opening,
+                commitments,
+            }
+        }
+    };
+
+    let mut out = Bundle::new();
+    pending.echoes.push_to(&mut out);
+    out.push(&coin.opening);
+    let mut theirs = Net::try_broadcast_bundle(&out)?;
+    pending.echoes.check(&mut theirs)?;
+    let parts = opened(&mut theirs, &coin.commitments)?;
+    let coins = &mut StdRng::from_seed(coin_seed(parts));
+
+    let mut combined = Bundle::new();
+    for checks in &pending.checks {
+        checks.combine(coins, &mut combined);
+    }
+    let (opening, commitment) = commit(combined.bytes());
+    let (next, next_commitment) = commit(&coin_part());
+    let mut theirs =
+        Net::try_broadcast_bundle(&Bundle::new().with(&commitment).with(&next_commitment))?;
+    let (commitments, next_commitments) = theirs
+        .iter_mut()
+        .map(|t| Ok((t.take::<Vec<u8>>()?, t.take::<Vec<u8>>()?)))
+        .collect::<NetResult<(Vec<_>, Vec<_>)>>()?;
+    theirs.into_iter().try_for_each(Unbundle::finish)?;
+    let mut echoes = Echoes::new();
+    echoes.record(&commitments);
+    echoes.record(&next_commitments);
+    *log.coin.lock().expect("Poisoned MacLog") = Some(Committed {
+        opening: next,
+        commitments: next_commitments,
+    });
+
+    let mut out = Bundle::new();
+    echoes.push_to(&mut out);
+    out.push(&opening);
+    let mut theirs = Net::try_broadcast_bundle(&out)?;
+    echoes.check(&mut theirs)?;
+    let mut theirs: Vec<_> = opened(&mut theirs, &commitments)?
+        .into_iter()
+        .enumerate()
+        .map(|(peer, bytes)| Unbundle::new(peer, bytes))
+        .collect();
+    let mut failed = Vec::new();
+    for checks in &pending.checks {
+        if !checks.passes(&mut theirs)? {
+            failed.push(checks);
+        }
+    }
+    theirs.into_iter().try_for_each(Unbundle::finish)?;
+    if failed.is_empty() {
+        return Ok(());
+    }
+    let mut out = Bundle::new();
+    for checks in &failed {
+        checks.each(&mut out);
+    }
+    let mut theirs = Net::try_atomic_broadcast_bundle(&out)?;
+    let index = failed
+        .iter()
+        .map(|checks| checks.first_failure(&mut theirs))
+        .collect::<NetResult<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .min();
+    // A party can lie about its combination alone; blame the first value checked then.
+    let index = index.unwrap_or_else(|| failed.iter().map(|c| c.first()).min().unwrap());
+    Err(NetError::MacCheck { index })
+}
+
+/// Take the last value of each bundle in `theirs`, an opening of the matching `commitments`, and
+/// open it.
+fn opened(theirs: &mut [Unbundle], commitments: &[Vec<u8>]) -> NetResult<Vec<Vec<u8>>> {
+    let openings = theirs
+        .iter_mut()
+        .map(|t| t.take::<Vec<u8>>())
+        .collect::<NetResult<Vec<_>>>()?;
+    let opened = openings
+        .into_iter()
+        .zip(commitments)
+        .enumerate()
+        .map(|(peer, (opening, commitment))| open(peer, commitment, opening))
+        .collect();
+    theirs.iter().cloned().try_for_each(Unbundle::finish)?;
+    opened
+}
+
+/// The values opened in a session since the last [mac_check], with this party's share
+/// dᵢ = αᵢx − mᵢ of each one's check.
+#[derive(Default)]
+struct MacLog {
+    pending: Mutex<Pending>,
+    /// This party's part of the coin of the next check, and every party's commitment to theirs.
+    coin: Mutex<Option<Committed>>,
+}
+
+struct Committed {
+    opening: Vec<u8>,
+    commitments: Vec<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Values opened so far.
+    opened: usize,
+    /// The shares received in those openings.
+    echoes: Echoes,
+    /// The checks of each type of value, in the order first opened.
+    checks: Vec<Box<dyn Unchecked>>,
+}
+
+/// The checks of the values of one type opened since the last [mac_check].
+trait Unchecked: Send {
+    fn as_any(&mut self) -> &mut dyn Any;
+    /// The index of the first value, among all those checked together.
+    fn first(&self) -> usize;
+    /// Add Σ rⱼdⱼ over this party's checks to `out`, with each rⱼ drawn from `coins`.
+    fn combine(&self, coins: &mut StdRng, out: &mut Bundle);
+    /// Whether the combinations of all parties, next in `theirs`, sum to zero.
+    fn passes(&self, theirs: &mut [Unbundle]) -> NetResult<bool>;
+    /// Add every check to `out`.
+    fn each(&self, out: &mut Bundle);
+    /// The index of the first value whose checks, next in `theirs`, do not sum to zero.
+    fn first_failure(&self, theirs: &mut [Unbundle]) -> NetResult<Option<usize>>;
+}
+
+/// Checks dᵢ in `T`, combined with coefficients in `S`.
+struct Checks<T, S> {
+    /// The index of each value among all those opened since the last check, and its dᵢ.
+    checks: Vec<(usize, T)>,
+    scale: fn(T, &S) -> T,
+}
+
+impl<T, S> Unchecked for Checks<T, S>
+where
+    T: CanonicalSerialize + CanonicalDeserialize + Zero + Copy + Send + 'static,
+    S: Field,
+{
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+    fn first(&self) -> usize {
+        self.checks[0].0
+    }
+    fn combine(&self, coins: &mut StdRng, out: &mut Bundle) {
+        let combination = self.checks.iter().fold(T::zero(), |acc, (_, d)| {
+            acc + (self.scale)(*d, &S::rand(coins))
+        });
+        out.push(&combination);
     }
+    fn passes(&self, theirs: &mut [Unbundle]) -> NetResult<bool> {
+        let mut sum = T::zero();
+        for t in theirs {
+            sum = sum + t.take::<T>()?;
+        }
+        Ok(sum.is_zero())
+    }
+    fn each(&self, out: &mut Bundle) {
+        out.push(&self.checks.iter().map(|(_, d)| *d).collect::<Vec<T>>());
+    }
+    fn first_failure(&self, theirs: &mut [Unbundle]) -> NetResult<Option<usize>> {
+        let mut sums = vec![T::zero(); self.checks.len()];
+        for t in theirs {
+            let ds: Vec<T> = t.take()?;
+            if ds.len() != sums.len() {
+                return Err(NetError::MalformedFrame {
+                    peer: t.peer(),
+                    reason: format!("{} MAC checks, expected {}", ds.len(), sums.len()),
+                });
+            }
+            for (sum, d) in sums.iter_mut().zip(ds) {
+                *sum = *sum + d;
+            }
+        }
+        Ok(self
+            .checks
+            .iter()
+            .zip(sums)
+            .find(|(_, sum)| !sum.is_zero())
+            .map(|((i, _), _)| *i))
+    }
+}
+
+/// The session's [MacLog], attached on first use, when the session is also told to run
+/// [mac_check] at its checkpoints.
+fn mac_log() -> Arc<MacLog> {
+    Net::attached::<MacLog>().unwrap_or_else(|| {
+        Net::attach(MacLog::default());
+        Net::on_checkpoint(mac_check);
+        Net::attached().unwrap()
+    })
+}
+
+/// Log the shares `received` from each party in an opening, and this party's checks `ds` of the
+/// values opened, for the next [mac_check]. `scale` multiplies a check by a coefficient.
+fn defer_checks<V, T, S>(received: &[V], ds: Vec<T>, scale: fn(T, &S) -> T) -> NetResult<()>
+where
+    V: CanonicalSerialize,
+    T: CanonicalSerialize + CanonicalDeserialize + Zero + Copy + Send + 'static,
+    S: Field,
+{
+    let log = mac_log();
+    let mut pending = log.pending.lock().expect("Poisoned MacLog");
+    let Pending {
+        opened,
+        echoes,
+        checks,
+    } = &mut *pending;
+    echoes.record(received);
+    let i = match checks
+        .iter_mut()
+        .position(|c| c.as_any().is::<Checks<T, S>>())
+    {
+        Some(i) => i,
+        None => {
+            checks.push(Box::new(Checks::<T, S> {
+                checks: Vec::new(),
+                scale,
+            }));
+            checks.len() - 1
+        }
+    };
+    let checks = checks[i].as_any().downcast_mut::<Checks<T, S>>().unwrap();
+    for d in ds {
+        checks.checks.push((*opened, d));
+        *opened += 1;
+    }
+    Ok(())
 }
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -52,6 +495,21 @@ pub struct SpdzFieldShare<T> {
     mac: AdditiveFieldShare<T>,
 }
 
+impl<F: Field> SpdzFieldShare<F> {
+    /// This party's share of a value and of its MAC, e.g. from preprocessing.
+    pub fn from_parts(share: F, mac: F) -> Self {
+        Self {
+            sh: AdditiveFieldShare::from_add_shared(share),
+            mac: AdditiveFieldShare::from_add_shared(mac),
+        }
+    }
+
+    /// This party's share of the value and of its MAC.
+    pub fn parts(&self) -> (F, F) {
+        (self.sh.val, self.mac.val)
+    }
+}
+
 macro_rules! impl_basics_spdz {
     ($share:ident, $bound:ident) => {
         impl<T: $bound> Display for $share<T> {
@@ -107,27 +565,41 @@ macro_rules! impl_basics_spdz {
                 unimplemented!("deserialize_with_flags")
             }
         }
-        impl<T: $bound> UniformRand for $share<T> {
-            fn rand<R: Rng + ?Sized>(rng: &mut R) -> Self {
-                Self::from_add_shared(<T as UniformRand>::rand(rng))
-            }
-        }
     };
 }
 impl_basics_spdz!(SpdzFieldShare, Field);
 
+impl<F: Field> UniformRand for SpdzFieldShare<F> {
+    /// A random share. Once a [SpdzMaterial] is loaded, it is the sum of one input mask of each
+    /// party, which no party knows unless all collude; this takes no communication.
+    fn rand<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        match material::<F>() {
+            Some(material) => {
+                let mut sum = Self::from_parts(F::zero(), F::zero());
+                for owner in 0..Net::n_parties() {
+                    sum.add(&material.take_input_masks(owner, 1)[0].share);
+                }
+                sum
+            }
+            None => Self::from_add_shared(F::rand(rng)),
+        }
+    }
+}
+
 impl<F: Field> Reveal for SpdzFieldShare<F> {
     type Base = F;
 
     fn reveal(self) -> F {
-        let vals: Vec<F> = Net::broadcast(&self.sh.val);
+        self.try_reveal().unwrap_or_else(|e| abort(e))
+    }
+    fn try_reveal(self) -> NetResult<F> {
+        let vals: Vec<F> = Net::try_broadcast(&self.sh.val)?;
         // _Pragmatic MPC_ 6.6.2
         let x: F = vals.iter().sum();
-        let dx_t: F = mac_share::<F>() * x - self.mac.val;
-        let all_dx_ts: Vec<F> = Net::atomic_broadcast(&dx_t);
-        let sum: F = all_dx_ts.iter().sum();
-        assert!(sum.is_zero());
-        x
+        defer_checks(&vals, vec![mac_share::<F>() * x - self.mac.val], |d, r| {
+            d * r
+        })?;
+        Ok(x)
     }
     fn from_public(f: F) -> Self {
         Self {
@@ -142,6 +614,9 @@ impl<F: Field> Reveal for SpdzFieldShare<F> {
         }
     }
     fn king_share<R: Rng>(f: Self::Base, rng: &mut R) -> Self {
+        if let Some(material) = material::<F>() {
+            return input(&material, vec![f]).pop().unwrap();
+        }
         let mut r: Vec<F> = (0..(Net::n_parties() - 1)).map(|_| F::rand(rng)).collect();
         let sum_r: F = r.iter().sum();
         r.push(f - sum_r);
@@ -152,6 +627,9 @@ impl<F: Field> Reveal for SpdzFieldShare<F> {
         }))
     }
     fn king_share_batch<R: Rng>(f: Vec<Self::Base>, rng: &mut R) -> Vec<Self> {
+        if let Some(material) = material::<F>() {
+            return input(&material, f);
+        }
         let mut rs: Vec<Vec<Self::Base>> = (0..(Net::n_parties() - 1))
             .map(|_| (0..f.len()).map(|_| F::rand(rng)).collect())
             .collect();
@@ -172,10 +650,13 @@ impl<F: Field> Reveal for SpdzFieldShare<F> {
 
 impl<F: Field> FieldShare<F> for SpdzFieldShare<F> {
     fn batch_open(selfs: impl IntoIterator<Item = Self>) -> Vec<F> {
+        Self::try_batch_open(selfs).unwrap_or_else(|e| abort(e))
+    }
+    fn try_batch_open(selfs: impl IntoIterator<Item = Self>) -> NetResult<Vec<F>> {
         let (s_vals, macs): (Vec<F>, Vec<F>) =
             selfs.into_iter().map(|s| (s.sh.val, s.mac.val)).unzip();
         let n = s_vals.len();
-        let all_vals = Net::broadcast(&s_vals);
+        let all_vals = Net::try_broadcast(&s_vals)?;
         let vals: Vec<F> = (0..n)
             .map(|i| all_vals.iter().map(|v| &v[i]).sum())
             .collect();
@@ -184,12 +665,8 @@ impl<F: Field> FieldShare<F> for SpdzFieldShare<F> {
             .zip(vals.iter())
             .map(|(mac, val)| mac_share::<F>() * val - mac)
             .collect();
-        let all_dx_ts: Vec<Vec<F>> = Net::atomic_broadcast(&dx_ts);
-        for i in 0..n {
-            let sum: F = all_dx_ts.iter().map(|dx_ts| &dx_ts[i]).sum();
-            assert!(sum.is_zero());
-        }
-        vals
+        defer_checks(&all_vals, dx_ts, |d, r| d * r)?;
+        Ok(vals)
     }
     fn add(&mut self, other: &Self) -> &mut Self {
         self.sh.add(&other.sh);
@@ -264,11 +741,14 @@ pub struct SpdzGroupShare<T, M> {
     mac: AdditiveGroupShare<T, M>,
 }
 
-impl<G: Group, M> Reveal for SpdzGroupShare<G, M> {
+impl<G: Group, M: Msm<G, G::ScalarField>> Reveal for SpdzGroupShare<G, M> {
     type Base = G;
 
     fn reveal(self) -> G {
-        let vals: Vec<G> = Net::broadcast(&self.sh.val);
+        self.try_reveal().unwrap_or_else(|e| abort(e))
+    }
+    fn try_reveal(self) -> NetResult<G> {
+        let vals: Vec<G> = Net::try_broadcast(&self.sh.val)?;
         // _Pragmatic MPC_ 6.6.2
         let x: G = vals.iter().sum();
         let dx_t: G = {
@@ -276,10 +756,8 @@ impl<G: Group, M> Reveal for SpdzGroupShare<G, M> {
             t *= mac_share::<G::ScalarField>();
             t - self.mac.val
         };
-        let all_dx_ts: Vec<G> = Net::atomic_broadcast(&dx_t);
-        let sum: G = all_dx_ts.iter().sum();
-        assert!(sum.is_zero());
-        x
+        defer_checks(&vals, vec![dx_t], |d, r| d.mul(r))?;
+        Ok(x)
     }
     fn from_public(f: G) -> Self {
         Self {
@@ -291,7 +769,27 @@ impl<G: Group, M> Reveal for SpdzGroupShare<G, M> {
             }),
         }
     }
+    /// Once a [SpdzMaterial] is loaded, every party inputs its part `f` with one of its input
+    /// masks, in one round, and the shares of the parts are summed.
     fn from_add_shared(f: G) -> Self {
+        if let Some(material) = material::<G::ScalarField>() {
+            let base = input_base::<G>();
+            let masks: Vec<_> = (0..Net::n_parties())
+                .map(|owner| material.take_input_masks(owner, 1).pop().unwrap())
+                .collect();
+            let mask = masks[Net::party_id()].value;
+            let offset = f - base.mul(&mask.expect("An input mask without its value"));
+            let offsets = match can_cheat() {
+                true => Net::broadcast(&offset),
+                false => Net::consistent_broadcast(&offset),
+            };
+            let mut sum = Self::from_public(G::zero());
+            for (mask, offset) in masks.into_iter().zip(offsets) {
+                let mut share = Self::scale_pub_group(base, &mask.share);
+                sum.add(share.shift(&offset));
+            }
+            return sum;
+        }
         Self {
             sh: Reveal::from_add_shared(f),
             mac: Reveal::from_add_shared({
@@ -302,6 +800,9 @@ impl<G: Group, M> Reveal for SpdzGroupShare<G, M> {
         }
     }
     fn king_share<R: Rng>(f: Self::Base, rng: &mut R) -> Self {
+        if let Some(material) = material::<G::ScalarField>() {
+            return input_group(&material, vec![f]).pop().unwrap();
+        }
         let mut r: Vec<G> = (0..(Net::n_parties() - 1)).map(|_| G::rand(rng)).collect();
         let sum_r: G = r.iter().sum();
         r.push(f - sum_r);
@@ -312,6 +813,9 @@ impl<G: Group, M> Reveal for SpdzGroupShare<G, M> {
         }))
     }
     fn king_share_batch<R: Rng>(f: Vec<Self::Base>, rng: &mut R) -> Vec<Self> {
+        if let Some(material) = material::<G::ScalarField>() {
+            return input_group(&material, f);
+        }
         let mut rs: Vec<Vec<Self::Base>> = (0..(Net::n_parties() - 1))
             .map(|_| (0..f.len()).map(|_| Self::Base::rand(rng)).collect())
             .collect();
@@ -395,10 +899,13 @@ impl<G: Group, M: Msm<G, G::ScalarField>> GroupShare<G> for SpdzGroupShare<G, M>
     type FieldShare = SpdzFieldShare<G::ScalarField>;
 
     fn batch_open(selfs: impl IntoIterator<Item = Self>) -> Vec<G> {
+        Self::try_batch_open(selfs).unwrap_or_else(|e| abort(e))
+    }
+    fn try_batch_open(selfs: impl IntoIterator<Item = Self>) -> NetResult<Vec<G>> {
         let (s_vals, macs): (Vec<G>, Vec<G>) =
             selfs.into_iter().map(|s| (s.sh.val, s.mac.val)).unzip();
         let n = s_vals.len();
-        let all_vals = Net::broadcast(&s_vals);
+        let all_vals = Net::try_broadcast(&s_vals)?;
         let vals: Vec<G> = (0..n)
             .map(|i| all_vals.iter().map(|v| &v[i]).sum())
             .collect();
@@ -407,12 +914,8 @@ impl<G: Group, M: Msm<G, G::ScalarField>> GroupShare<G> for SpdzGroupShare<G, M>
             .zip(vals.iter())
             .map(|(mac, val)| val.mul(&mac_share::<G::ScalarField>()) - mac)
             .collect();
-        let all_dx_ts: Vec<Vec<G>> = Net::atomic_broadcast(&dx_ts);
-        for i in 0..n {
-            let sum: G = all_dx_ts.iter().map(|dx_ts| &dx_ts[i]).sum();
-            assert!(sum.is_zero());
-        }
-        vals
+        defer_checks(&all_vals, dx_ts, |d, r| d.mul(r))?;
+        Ok(vals)
     }
 
     fn add(&mut self, other: &Self) -> &mut Self {
@@ -451,7 +954,7 @@ impl<G: Group, M: Msm<G, G::ScalarField>> GroupShare<G> for SpdzGroupShare<G, M>
 
     fn multi_scale_pub_group(bases: &[G], scalars: &[Self::FieldShare]) -> Self {
         let shares: Vec<G::ScalarField> = scalars.into_iter().map(|s| s.sh.val.clone()).collect();
-        let macs: Vec<G::ScalarField> = scalars.into_iter().map(|s| s.sh.val.clone()).collect();
+        let macs: Vec<G::ScalarField> = scalars.into_iter().map(|s| s.mac.val.clone()).collect();
         let sh = AdditiveGroupShare::from_add_shared(M::msm(bases, &shares));
         let mac = AdditiveGroupShare::from_add_shared(M::msm(bases, &macs));
         Self { sh, mac }
@@ -479,14 +982,28 @@ impl<F: Field, S: PrimeField> Reveal for SpdzMulFieldShare<F, S> {
     type Base = F;
 
     fn reveal(self) -> F {
-        let vals: Vec<F> = Net::broadcast(&self.sh.val);
+        self.try_reveal().unwrap_or_else(|e| abort(e))
+    }
+    fn try_reveal(self) -> NetResult<F> {
+        let vals: Vec<F> = Net::try_broadcast(&self.sh.val)?;
         // _Pragmatic MPC_ 6.6.2
         let x: F = vals.iter().product();
+        // Checked at once: multiplicative MACs do not combine linearly.
         let dx_t: F = x.pow(&mac_share::<S>().into_repr()) / self.mac.val;
-        let all_dx_ts: Vec<F> = Net::atomic_broadcast(&dx_t);
-        let prod: F = all_dx_ts.iter().product();
-        assert!(prod.is_one());
-        x
+        let mut echoes = Echoes::new();
+        echoes.record(&vals);
+        let mut out = Bundle::new();
+        echoes.push_to(&mut out);
+        out.push(&dx_t);
+        let mut theirs = Net::try_atomic_broadcast_bundle(&out)?;
+        echoes.check(&mut theirs)?;
+        let mut prod = F::one();
+        for t in &mut theirs {
+            prod *= t.take::<F>()?;
+        }
+        theirs.into_iter().try_for_each(Unbundle::finish)?;
+        check_mac(0, prod.is_one())?;
+        Ok(x)
     }
     fn from_public(f: F) -> Self {
         Self {
@@ -661,3 +1178,234 @@ impl<E: PairingEngine> PairingShare<E> for SpdzPairingShare<E> {
     type G1 = SpdzG1Share<E>;
     type G2 = SpdzG2Share<E>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{catch_abort, checkpoint};
+    use mpc_net::{local::simulate, MpcSession, Security};
+
+    type F = ark_bls12_377::Fr;
+
+    /// Party `id`'s part of `masks` input masks per party under a random key, as a dealer would
+    /// hand out. Every party deals the same from [ark_std::test_rng].
+    fn dealt(id: usize, n: usize, masks: usize) -> SpdzMaterial<F> {
+        let rng = &mut ark_std::test_rng();
+        let split = |x: F, rng: &mut _| {
+            let mut parts: Vec<F> = (1..n).map(|_| F::rand(rng)).collect();
+            parts.push(x - parts.iter().sum::<F>());
+            parts
+        };
+        let alpha = F::rand(rng);
+        let mut material = SpdzMaterial::new(split(alpha, rng)[id], n);
+        for owner in 0..n {
+            let masks: Vec<_> = (0..masks)
+                .map(|_| {
+                    let r = F::rand(rng);
+                    let (sh, mac) = (split(r, rng)[id], split(alpha * r, rng)[id]);
+                    InputMask {
+                        share: SpdzFieldShare::from_parts(sh, mac),
+                        value: (owner == id).then_some(r),
+                    }
+                })
+                .collect();
+            material = material.with_input_masks(owner, masks);
+        }
+        material
+    }
+
+    #[test]
+    fn failed_mac_check_names_the_value() {
+        type G = ark_bls12_377::G1Projective;
+        let results = simulate(3, |id| {
+            let x = SpdzFieldShare::<F>::from_add_shared(F::from(id as u64));
+            let g =
+                SpdzGroupShare::<G, NaiveMsm<G>>::from_add_shared(G::prime_subgroup_generator());
+            let round = MpcSession::current().unwrap().round();
+            assert_eq!(x.reveal(), F::from(3u64));
+            // One round: the echo waits for the check.
+            assert_eq!(MpcSession::current().unwrap().round(), round + 1);
+            assert_eq!(g.reveal(), G::prime_subgroup_generator().mul([3]));
+            mac_check().unwrap();
+            // Nothing left to check.
+            let round = MpcSession::current().unwrap().round();
+            mac_check().unwrap();
+            assert_eq!(MpcSession::current().unwrap().round(), round);
+
+            let mut shares: Vec<_> = (0..3)
+                .map(|i| SpdzFieldShare::<F>::from_add_shared(F::from((id + i) as u64)))
+                .collect();
+            if id == 1 {
+                // Lie about a share, but not its MAC.
+                shares[2].sh.val += F::one();
+            }
+            assert_eq!(g.reveal(), G::prime_subgroup_generator().mul([3]));
+            let opened = SpdzFieldShare::try_batch_open(shares).unwrap();
+            assert_eq!(opened[2], F::from(10u64));
+            mac_check()
+        });
+        for r in results {
+            assert!(matches!(r, Err(NetError::MacCheck { index: 3 })));
+        }
+    }
+
+    #[test]
+    fn production_refuses_to_fabricate_macs() {
+        let t = mpc_net::local::LocalTransport::mesh(1).pop().unwrap();
+        let session = Arc::new(MpcSession::from_transport(t).with_security(Security::Production));
+        session.enter(|| {
+            assert!(!can_cheat());
+            // Not even public values, without a key.
+            assert!(std::panic::catch_unwind(mac_share::<F>).is_err());
+            dealt(0, 1, 0).load();
+            let public = SpdzFieldShare::<F>::from_public(F::one());
+            assert_eq!(public.reveal(), F::one());
+            mac_check().unwrap();
+            let fabricated =
+                std::panic::catch_unwind(|| SpdzFieldShare::<F>::from_add_shared(F::one()));
+            assert!(fabricated.is_err());
+        });
+    }
+
+    #[test]
+    fn inputs_under_a_shared_key() {
+        let results = simulate(3, |id| {
+            let rng = &mut ark_std::test_rng();
+            dealt(id, 3, 4).load();
+            assert!(std::panic::catch_unwind(mac::<F>).is_err());
+            let xs = SpdzFieldShare::king_share_batch(vec![F::from(2u64), F::from(3u64)], rng);
+            let y = SpdzFieldShare::king_share(F::from(5u64), rng);
+            let mut z = xs[0];
+            z.add(&xs[1]).scale(&F::from(10u64)).shift(&F::from(7u64));
+            z.sub(&y).add(&SpdzFieldShare::from_public(F::one()));
+            let left = Net::attached::<SpdzMaterial<F>>()
+                .unwrap()
+                .input_masks_left(0);
+            let opened = SpdzFieldShare::try_batch_open(vec![z, y]);
+            let mut tampered = z;
+            if id == 2 {
+                tampered.sh.val += F::one();
+            }
+            tampered.reveal();
+            (left, opened, mac_check())
+        });
+        for (left, opened, checked) in results {
+            assert_eq!(left, 1);
+            assert_eq!(opened.unwrap(), vec![F::from(53u64), F::from(5u64)]);
+            assert!(matches!(checked, Err(NetError::MacCheck { index: 2 })));
+        }
+    }
+
+    #[test]
+    fn group_shares_under_a_shared_key() {
+        type G = ark_bls12_377::G1Projective;
+        type S = SpdzGroupShare<G, NaiveMsm<G>>;
+        let g = G::prime_subgroup_generator();
+        let results = simulate(3, |id| {
+            let rng = &mut ark_std::test_rng();
+            dealt(id, 3, 4).load();
+            assert!(!input_base::<G>().is_zero());
+            let xs = SpdzFieldShare::king_share_batch(vec![F::from(2u64), F::from(3u64)], rng);
+            let msm = S::multi_scale_pub_group(&[g, g.mul([2])], &xs);
+            let y = S::king_share(g.mul([5]), rng);
+            let z = S::from_add_shared(g.mul([id as u64]));
+            (S::batch_open(vec![msm, y, z]), mac_check())
+        });
+        for (opened, checked) in results {
+            assert_eq!(opened, vec![g.mul([8]), g.mul([5]), g.mul([3])]);
+            checked.unwrap();
+        }
+    }
+
+    #[test]
+    fn production_checks_at_checkpoints() {
+        let results: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = mpc_net::local::LocalTransport::mesh(3)
+                .into_iter()
+                .map(|t| {
+                    let session =
+                        Arc::new(MpcSession::from_transport(t).with_security(Security::Production));
+                    s.spawn(move || {
+                        session.enter(|| {
+                            let id = Net::party_id();
+                            let rng = &mut ark_std::test_rng();
+                            dealt(id, 3, 3).load();
+                            let xs = SpdzFieldShare::king_share_batch(
+                                vec![F::from(2u64), F::from(3u64)],
+                                rng,
+                            );
+                            let rounds = || session.round();
+                            // Openings are not checked one by one...
+                            let start = rounds();
+                            let x = xs[0].reveal();
+                            let opened = SpdzFieldShare::batch_open(xs.clone());
+                            assert_eq!(rounds() - start, 2);
+                            // ...but together: the first check also commits to its coin.
+                            let start = rounds();
+                            checkpoint();
+                            assert_eq!(rounds() - start, 4);
+                            let start = rounds();
+                            checkpoint();
+                            assert_eq!(rounds(), start);
+                            // Later checks open a coin committed to in the last one.
+                            xs[1].reveal();
+                            let start = rounds();
+                            checkpoint();
+                            assert_eq!(rounds() - start, 3);
+                            let mut tampered = xs[1];
+                            if id == 2 {
+                                tampered.sh.val += F::one();
+                            }
+                            tampered.reveal();
+                            (x, opened, Net::try_checkpoint())
+                        })
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for (x, opened, checked) in results {
+            assert_eq!(x, F::from(2u64));
+            assert_eq!(opened, vec![F::from(2u64), F::from(3u64)]);
+            assert!(matches!(checked, Err(NetError::MacCheck { index: 0 })));
+        }
+    }
+
+    #[test]
+    fn random_shares_under_a_shared_key() {
+        let results = simulate(3, |id| {
+            let rng = &mut ark_std::test_rng();
+            dealt(id, 3, 2).load();
+            let rs: Vec<_> = (0..2).map(|_| SpdzFieldShare::<F>::rand(rng)).collect();
+            let left = Net::attached::<SpdzMaterial<F>>()
+                .unwrap()
+                .input_masks_left(id);
+            (left, SpdzFieldShare::batch_open(rs), mac_check())
+        });
+        for (left, opened, checked) in &results {
+            assert_eq!(*left, 0);
+            assert_ne!(opened[0], opened[1]);
+            assert_eq!(opened, &results[0].1);
+            checked.as_ref().unwrap();
+        }
+    }
+
+    #[test]
+    fn failed_check_aborts() {
+        let results = simulate(3, |id| {
+            let mut share = SpdzFieldShare::<F>::from_add_shared(F::from(id as u64));
+            if id == 0 {
+                share.sh.val += F::one();
+            }
+            catch_abort(|| {
+                share.reveal();
+                mac_check().unwrap_or_else(|e| abort(e))
+            })
+        });
+        for r in results {
+            let e = r.unwrap_err();
+            assert!(matches!(e, NetError::MacCheck { index: 0 }));
+            assert_eq!(e.cheater(), None);
+        }
+    }
+}
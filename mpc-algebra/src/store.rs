@@ -0,0 +1,982 @@
+//! Per-party files of SPDZ preprocessing material.
+//!
+//! A [PreprocessingStore] holds one party's share of the MAC key, and its part of the Beaver
+//! triples, inverse pairs, random bits and input masks dealt for a run, all over one field.
+//! Material is appended as it is produced and spent from the front; what has been spent is
+//! recorded on disk before it is handed out, so no run spends it twice. The header is kept in two
+//! slots of the same length, and each change overwrites the older one with the next generation,
+//! after syncing any material appended to the body. A crash thus tears at most the slot being
+//! written, and [PreprocessingStore::open] takes the newest intact one, ignoring whatever lies
+//! past the body it records. An open store holds a lock file, its path with `.lock` appended, so
+//! no two handles spend the same material. The layout, with every integer little-endian, is
+//!
+//! ```text
+//! file    header, header, body
+//! header  "ZKMPCPRE", version u32, generation u64, party u32, parties u32,
+//!         characteristic (limb count u32, limbs u64...), extension degree u64,
+//!         (total u64, used u64) for triples, inverse pairs, bits, then each party's input masks,
+//!         body length u64, body digest [32], header digest [32]
+//! body    MAC key share, then chunks of: kind u8, owner u32, count u64, records...
+//! ```
+//!
+//! A record is one or more SPDZ shares, each a share and a MAC share as canonically serialized
+//! field elements: three for a triple, two for an inverse pair, one for a bit or an input mask.
+//! Our own input masks also carry their value. The body digest is a SHA-256 chain, H(d || chunk)
+//! for each chunk in turn starting from H(0³² || key share), so appending does not rehash the
+//! file; the header digest covers the rest of the header. [PreprocessingStore::open] checks both,
+//! and that the store was made for the party, party count and field opening it.
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use ark_ff::Field;
+use log::{info, warn};
+use mpc_net::{MpcMultiNet as Net, MpcNet};
+use sha2::{Digest, Sha256};
+use structopt::StructOpt;
+
+use crate::dealer::store_path;
+use crate::share::spdz::{InputMask, SpdzFieldShare, SpdzMaterial};
+use crate::wire::field::{DummyTriples, TripleStore};
+
+const MAGIC: &[u8; 8] = b"ZKMPCPRE";
+
+/// The store format written by this version.
+pub const VERSION: u32 = 1;
+
+const TRIPLES: u8 = 1;
+const INV_PAIRS: u8 = 2;
+const BITS: u8 = 3;
+const INPUT_MASKS: u8 = 4;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    /// The file is not a store of this version, or fails its integrity checks.
+    Corrupt(String),
+    /// The store, or material for it, belongs to a different party, party count or field.
+    Mismatch(String),
+    /// Less unspent material of kind `what` is left than asked for.
+    Exhausted {
+        what: &'static str,
+        need: usize,
+        have: usize,
+    },
+    /// Another handle holds the store's lock file, at this path. A process that died holding it
+    /// leaves it behind, to be removed by hand.
+    Locked(PathBuf),
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "I/O error: {}", e),
+            StoreError::Corrupt(reason) => write!(f, "corrupt preprocessing store: {}", reason),
+            StoreError::Mismatch(reason) => write!(f, "wrong preprocessing store: {}", reason),
+            StoreError::Exhausted { what, need, have } => {
+                write!(
+                    f,
+                    "out of preprocessed {}: need {}, have {}",
+                    what, need, have
+                )
+            }
+            StoreError::Locked(path) => {
+                write!(f, "preprocessing store in use: {} exists", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+fn corrupt(reason: impl fmt::Display) -> StoreError {
+    StoreError::Corrupt(reason.to_string())
+}
+
+/// How much of one kind of material a store has held, and how much of it is spent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cursor {
+    pub total: u64,
+    pub used: u64,
+}
+
+impl Cursor {
+    pub fn left(&self) -> usize {
+        (self.total - self.used) as usize
+    }
+}
+
+/// An amount of each kind of material.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub triples: usize,
+    pub inv_pairs: usize,
+    pub bits: usize,
+    /// Of each party.
+    pub input_masks: usize,
+}
+
+/// What a store says about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub party_id: usize,
+    pub n_parties: usize,
+    /// The characteristic of the field, as little-endian limbs.
+    pub characteristic: Vec<u64>,
+    /// The degree of the field over its prime field.
+    pub degree: u64,
+    pub triples: Cursor,
+    pub inv_pairs: Cursor,
+    pub bits: Cursor,
+    /// Of the masks each party owns.
+    pub input_masks: Vec<Cursor>,
+}
+
+/// What a header slot records besides the [Header]: its generation, and the length and digest of
+/// the body.
+#[derive(Debug, Clone, Copy, Default)]
+struct Seal {
+    generation: u64,
+    body_len: u64,
+    digest: [u8; 32],
+}
+
+impl Header {
+    fn new<F: Field>(party_id: usize, n_parties: usize) -> Self {
+        Self {
+            version: VERSION,
+            party_id,
+            n_parties,
+            characteristic: F::characteristic().to_vec(),
+            degree: F::extension_degree(),
+            triples: Cursor::default(),
+            inv_pairs: Cursor::default(),
+            bits: Cursor::default(),
+            input_masks: vec![Cursor::default(); n_parties],
+        }
+    }
+
+    fn cursors(&self) -> impl Iterator<Item = &Cursor> {
+        [&self.triples, &self.inv_pairs, &self.bits]
+            .into_iter()
+            .chain(&self.input_masks)
+    }
+
+    /// The header slot for `seal`, ending in a digest of the rest.
+    fn encode(&self, seal: &Seal) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend(self.version.to_le_bytes());
+        out.extend(seal.generation.to_le_bytes());
+        out.extend((self.party_id as u32).to_le_bytes());
+        out.extend((self.n_parties as u32).to_le_bytes());
+        out.extend((self.characteristic.len() as u32).to_le_bytes());
+        for limb in &self.characteristic {
+            out.extend(limb.to_le_bytes());
+        }
+        out.extend(self.degree.to_le_bytes());
+        for cursor in self.cursors() {
+            out.extend(cursor.total.to_le_bytes());
+            out.extend(cursor.used.to_le_bytes());
+        }
+        out.extend(seal.body_len.to_le_bytes());
+        out.extend(seal.digest);
+        let check = Sha256::digest(&out);
+        out.extend(check);
+        out
+    }
+
+    /// The length of its slots.
+    fn slot_len(&self) -> usize {
+        self.encode(&Seal::default()).len()
+    }
+
+    /// Read a header slot from the front of `bytes`.
+    fn decode(bytes: &mut &[u8]) -> StoreResult<(Self, Seal)> {
+        let start = *bytes;
+        if take_bytes(bytes, MAGIC.len())? != MAGIC {
+            return Err(corrupt("not a preprocessing store"));
+        }
+        let version = read_u32(bytes)?;
+        if version != VERSION {
+            return Err(corrupt(format!(
+                "format version {}, expected {}",
+                version, VERSION
+            )));
+        }
+        let generation = read_u64(bytes)?;
+        let party_id = read_u32(bytes)? as usize;
+        let n_parties = read_u32(bytes)? as usize;
+        let limbs = read_u32(bytes)? as usize;
+        let characteristic = (0..limbs)
+            .map(|_| read_u64(bytes))
+            .collect::<StoreResult<_>>()?;
+        let degree = read_u64(bytes)?;
+        let mut cursor = || -> StoreResult<Cursor> {
+            let cursor = Cursor {
+                total: read_u64(bytes)?,
+                used: read_u64(bytes)?,
+            };
+            if cursor.used > cursor.total {
+                return Err(corrupt("more material spent than held"));
+            }
+            Ok(cursor)
+        };
+        let (triples, inv_pairs, bits) = (cursor()?, cursor()?, cursor()?);
+        let input_masks = (0..n_parties)
+            .map(|_| cursor())
+            .collect::<StoreResult<_>>()?;
+        let body_len = read_u64(bytes)?;
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(take_bytes(bytes, 32)?);
+        let checked = &start[..start.len() - bytes.len()];
+        if take_bytes(bytes, 32)? != &Sha256::digest(checked)[..] {
+            return Err(corrupt("header digest mismatch"));
+        }
+        let header = Self {
+            version,
+            party_id,
+            n_parties,
+            characteristic,
+            degree,
+            triples,
+            inv_pairs,
+            bits,
+            input_masks,
+        };
+        let seal = Seal {
+            generation,
+            body_len,
+            digest,
+        };
+        Ok((header, seal))
+    }
+}
+
+fn take_bytes<'a>(bytes: &mut &'a [u8], n: usize) -> StoreResult<&'a [u8]> {
+    if bytes.len() < n {
+        return Err(corrupt("file is truncated"));
+    }
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn read_u32(bytes: &mut &[u8]) -> StoreResult<u32> {
+    let mut le = [0u8; 4];
+    le.copy_from_slice(take_bytes(bytes, 4)?);
+    Ok(u32::from_le_bytes(le))
+}
+
+fn read_u64(bytes: &mut &[u8]) -> StoreResult<u64> {
+    let mut le = [0u8; 8];
+    le.copy_from_slice(take_bytes(bytes, 8)?);
+    Ok(u64::from_le_bytes(le))
+}
+
+fn chain(digest: &[u8; 32], bytes: &[u8]) -> [u8; 32] {
+    let mut next = [0u8; 32];
+    next.copy_from_slice(&Sha256::new().chain(digest).chain(bytes).finalize());
+    next
+}
+
+fn write_element<F: Field>(out: &mut Vec<u8>, x: &F) {
+    x.serialize(out).unwrap();
+}
+
+fn read_element<F: Field>(bytes: &mut &[u8]) -> StoreResult<F> {
+    F::deserialize(bytes).map_err(|e| corrupt(format!("bad field element: {}", e)))
+}
+
+fn write_share<F: Field>(out: &mut Vec<u8>, x: &SpdzFieldShare<F>) {
+    let (share, mac) = x.parts();
+    write_element(out, &share);
+    write_element(out, &mac);
+}
+
+fn read_share<F: Field>(bytes: &mut &[u8]) -> StoreResult<SpdzFieldShare<F>> {
+    Ok(SpdzFieldShare::from_parts(
+        read_element(bytes)?,
+        read_element(bytes)?,
+    ))
+}
+
+/// One party's material, in the order it is spent.
+#[derive(Debug, Clone)]
+pub struct Material<F: Field> {
+    /// `(x, y, xy)` for random `x` and `y`.
+    pub triples: Vec<(SpdzFieldShare<F>, SpdzFieldShare<F>, SpdzFieldShare<F>)>,
+    /// Two shares of the same random nonzero value, as [crate::FieldShare::inv] spends them.
+    pub inv_pairs: Vec<(SpdzFieldShare<F>, SpdzFieldShare<F>)>,
+    /// Shares of random values in {0, 1}.
+    pub bits: Vec<SpdzFieldShare<F>>,
+    /// The masks each party owns; only ours carry their values.
+    pub input_masks: Vec<Vec<InputMask<F>>>,
+}
+
+impl<F: Field> Material<F> {
+    /// No material, for `n_parties` parties.
+    pub fn new(n_parties: usize) -> Self {
+        Self {
+            triples: Vec::new(),
+            inv_pairs: Vec::new(),
+            bits: Vec::new(),
+            input_masks: vec![Vec::new(); n_parties],
+        }
+    }
+
+    /// Load this material into the current session under the MAC key share `mac_share`: the key
+    /// and input masks as a [SpdzMaterial], the triples, inverse pairs and bits as a
+    /// [TripleStore].
+    pub fn load(self, mac_share: F) {
+        let n = self.input_masks.len();
+        self.input_masks
+            .into_iter()
+            .enumerate()
+            .fold(SpdzMaterial::new(mac_share, n), |m, (owner, masks)| {
+                m.with_input_masks(owner, masks)
+            })
+            .load();
+        TripleStore::default()
+            .with_triples(self.triples)
+            .with_inv_pairs(self.inv_pairs)
+            .with_bits(self.bits)
+            .load();
+    }
+}
+
+/// The lock file of an open store, removed when it is dropped.
+#[derive(Debug)]
+struct Lock(PathBuf);
+
+impl Lock {
+    fn take(store: &Path) -> StoreResult<Self> {
+        let mut name = store.file_name().unwrap_or_default().to_owned();
+        name.push(".lock");
+        let path = store.with_file_name(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let lock = Self(path);
+                writeln!(file, "{}", std::process::id())?;
+                Ok(lock)
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(StoreError::Locked(path)),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// A party's preprocessing store on disk; see the [module docs](self).
+#[derive(Debug)]
+pub struct PreprocessingStore<F: Field> {
+    path: PathBuf,
+    file: File,
+    header: Header,
+    /// Of the newest header slot.
+    seal: Seal,
+    mac_share: F,
+    /// Everything the store holds, spent or not.
+    material: Material<F>,
+    /// Dropped last, once the file is closed.
+    _lock: Lock,
+}
+
+impl<F: Field> PreprocessingStore<F> {
+    /// Create an empty store at `path` for party `party_id` of `n_parties`, with its MAC key
+    /// share. Fails if the file exists.
+    pub fn create(
+        path: impl AsRef<Path>,
+        party_id: usize,
+        n_parties: usize,
+        mac_share: F,
+    ) -> StoreResult<Self> {
+        let path = path.as_ref().to_owned();
+        let lock = Lock::take(&path)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut key = Vec::new();
+        write_element(&mut key, &mac_share);
+        let header = Header::new::<F>(party_id, n_parties);
+        let seal = Seal {
+            generation: 0,
+            body_len: key.len() as u64,
+            digest: chain(&[0; 32], &key),
+        };
+        let slot = header.encode(&seal);
+        file.write_all(&slot)?;
+        file.write_all(&slot)?;
+        file.write_all(&key)?;
+        file.sync_all()?;
+        #[cfg(unix)]
+        {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(Self {
+            path,
+            file,
+            header,
+            seal,
+            mac_share,
+            material: Material::new(n_parties),
+            _lock: lock,
+        })
+    }
+
+    /// Open the store at `path`, checking its integrity, and that it is party `party_id`'s of
+    /// `n_parties` over `F`.
+    pub fn open(path: impl AsRef<Path>, party_id: usize, n_parties: usize) -> StoreResult<Self> {
+        let path = path.as_ref().to_owned();
+        let lock = Lock::take(&path)?;
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut all = Vec::new();
+        file.read_to_end(&mut all)?;
+        let expected = Header::new::<F>(party_id, n_parties);
+        let first = Header::decode(&mut &all[..]);
+        let slot_len = match &first {
+            Ok((header, _)) => header.slot_len(),
+            Err(_) => expected.slot_len(),
+        };
+        let second = Header::decode(&mut all.get(slot_len..).unwrap_or_default());
+        let (header, seal) = match (first, second) {
+            (Ok(a), Ok(b)) => std::cmp::max_by_key(a, b, |(_, seal)| seal.generation),
+            (Ok(newest), Err(_)) | (Err(_), Ok(newest)) => newest,
+            (Err(e), Err(_)) => return Err(e),
+        };
+        if (header.party_id, header.n_parties) != (party_id, n_parties) {
+            return Err(StoreError::Mismatch(format!(
+                "made for party {} of {}, opened by party {} of {}",
+                header.party_id, header.n_parties, party_id, n_parties
+            )));
+        }
+        if (&header.characteristic, header.degree) != (&expected.characteristic, expected.degree) {
+            return Err(StoreError::Mismatch("made for another field".to_owned()));
+        }
+        if header.slot_len() != slot_len {
+            return Err(corrupt("header slots differ in length"));
+        }
+        let body = all
+            .get(2 * slot_len..)
+            .and_then(|rest| rest.get(..seal.body_len as usize))
+            .ok_or_else(|| corrupt("body shorter than its header says"))?;
+        let bytes = &mut &body[..];
+
+        let key_len = F::zero().serialized_size();
+        let mut body_digest = chain(&[0; 32], &bytes[..key_len.min(bytes.len())]);
+        let mac_share = read_element(bytes)?;
+        let mut material = Material::new(n_parties);
+        while !bytes.is_empty() {
+            let start = *bytes;
+            let kind = take_bytes(bytes, 1)?[0];
+            let owner = read_u32(bytes)? as usize;
+            let count = read_u64(bytes)?;
+            for _ in 0..count {
+                match kind {
+                    TRIPLES => material.triples.push((
+                        read_share(bytes)?,
+                        read_share(bytes)?,
+                        read_share(bytes)?,
+                    )),
+                    INV_PAIRS => material
+                        .inv_pairs
+                        .push((read_share(bytes)?, read_share(bytes)?)),
+                    BITS => material.bits.push(read_share(bytes)?),
+                    INPUT_MASKS if owner < n_parties => {
+                        let share = read_share(bytes)?;
+                        let value = match owner == party_id {
+                            true => Some(read_element(bytes)?),
+                            false => None,
+                        };
+                        material.input_masks[owner].push(InputMask { share, value });
+                    }
+                    _ => return Err(corrupt(format!("unknown chunk {} of {}", kind, owner))),
+                }
+            }
+            body_digest = chain(&body_digest, &start[..start.len() - bytes.len()]);
+        }
+        if body_digest != seal.digest {
+            return Err(corrupt("body digest mismatch"));
+        }
+        let held = [
+            material.triples.len(),
+            material.inv_pairs.len(),
+            material.bits.len(),
+        ]
+        .into_iter()
+        .chain(material.input_masks.iter().map(Vec::len));
+        if header.cursors().zip(held).any(|(c, n)| c.total != n as u64) {
+            return Err(corrupt("header counts do not match the body"));
+        }
+        Ok(Self {
+            path,
+            file,
+            header,
+            seal,
+            mac_share,
+            material,
+            _lock: lock,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn mac_share(&self) -> F {
+        self.mac_share
+    }
+
+    /// Unspent material; for input masks, the fewest any party has left.
+    pub fn left(&self) -> Counts {
+        Counts {
+            triples: self.header.triples.left(),
+            inv_pairs: self.header.inv_pairs.left(),
+            bits: self.header.bits.left(),
+            input_masks: self
+                .header
+                .input_masks
+                .iter()
+                .map(Cursor::left)
+                .min()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Add `material` after what the store holds.
+    pub fn append(&mut self, material: &Material<F>) -> StoreResult<()> {
+        let (me, n) = (self.header.party_id, self.header.n_parties);
+        if material.input_masks.len() != n {
+            return Err(StoreError::Mismatch(format!(
+                "input masks for {} parties, expected {}",
+                material.input_masks.len(),
+                n
+            )));
+        }
+        let mut chunks = Vec::new();
+        let mut chunk = |kind: u8, owner: usize, count: usize, records: Vec<u8>| {
+            if count > 0 {
+                let mut bytes = vec![kind];
+                bytes.extend((owner as u32).to_le_bytes());
+                bytes.extend((count as u64).to_le_bytes());
+                bytes.extend(records);
+                chunks.push(bytes);
+            }
+        };
+        let mut records = Vec::new();
+        for (x, y, z) in &material.triples {
+            write_share(&mut records, x);
+            write_share(&mut records, y);
+            write_share(&mut records, z);
+        }
+        chunk(TRIPLES, 0, material.triples.len(), records);
+        let mut records = Vec::new();
+        for (b, c) in &material.inv_pairs {
+            write_share(&mut records, b);
+            write_share(&mut records, c);
+        }
+        chunk(INV_PAIRS, 0, material.inv_pairs.len(), records);
+        let mut records = Vec::new();
+        for b in &material.bits {
+            write_share(&mut records, b);
+        }
+        chunk(BITS, 0, material.bits.len(), records);
+        for (owner, masks) in material.input_masks.iter().enumerate() {
+            let mut records = Vec::new();
+            for mask in masks {
+                write_share(&mut records, &mask.share);
+                match (owner == me, mask.value) {
+                    (true, Some(r)) => write_element(&mut records, &r),
+                    (false, None) => {}
+                    (true, None) => {
+                        return Err(StoreError::Mismatch(
+                            "one of our input masks is missing its value".to_owned(),
+                        ))
+                    }
+                    (false, Some(_)) => {
+                        return Err(StoreError::Mismatch(format!(
+                            "an input mask of party {} comes with its value",
+                            owner
+                        )))
+                    }
+                }
+            }
+            chunk(INPUT_MASKS, owner, masks.len(), records);
+        }
+
+        let digest = chunks
+            .iter()
+            .fold(self.seal.digest, |d, bytes| chain(&d, bytes));
+        let mut header = self.header.clone();
+        header.triples.total += material.triples.len() as u64;
+        header.inv_pairs.total += material.inv_pairs.len() as u64;
+        header.bits.total += material.bits.len() as u64;
+        for (cursor, masks) in header.input_masks.iter_mut().zip(&material.input_masks) {
+            cursor.total += masks.len() as u64;
+        }
+        self.commit(header, digest, &chunks.concat())?;
+        let m = &mut self.material;
+        m.triples.extend_from_slice(&material.triples);
+        m.inv_pairs.extend_from_slice(&material.inv_pairs);
+        m.bits.extend_from_slice(&material.bits);
+        for (ours, theirs) in m.input_masks.iter_mut().zip(&material.input_masks) {
+            ours.extend_from_slice(theirs);
+        }
+        Ok(())
+    }
+
+    /// Spend `counts` of each kind of material, input masks of every party. It is marked spent
+    /// on disk before it is returned.
+    pub fn take(&mut self, counts: Counts) -> StoreResult<Material<F>> {
+        let left = self.left();
+        for (what, need, have) in [
+            ("Beaver triples", counts.triples, left.triples),
+            ("inverse pairs", counts.inv_pairs, left.inv_pairs),
+            ("random bits", counts.bits, left.bits),
+            ("input masks", counts.input_masks, left.input_masks),
+        ] {
+            if need > have {
+                return Err(StoreError::Exhausted { what, need, have });
+            }
+        }
+        fn spend<T: Clone>(all: &[T], cursor: &mut Cursor, n: usize) -> Vec<T> {
+            let start = cursor.used as usize;
+            cursor.used += n as u64;
+            all[start..start + n].to_vec()
+        }
+        let (mut header, m) = (self.header.clone(), &self.material);
+        let h = &mut header;
+        let taken = Material {
+            triples: spend(&m.triples, &mut h.triples, counts.triples),
+            inv_pairs: spend(&m.inv_pairs, &mut h.inv_pairs, counts.inv_pairs),
+            bits: spend(&m.bits, &mut h.bits, counts.bits),
+            input_masks: m
+                .input_masks
+                .iter()
+                .zip(&mut h.input_masks)
+                .map(|(masks, cursor)| spend(masks, cursor, counts.input_masks))
+                .collect(),
+        };
+        self.commit(header, self.seal.digest, &[])?;
+        Ok(taken)
+    }
+
+    /// Record `header`, with `appended` after the body, whose digest then is `digest`. Appended
+    /// bytes go past the body's end and are synced before the older header slot is overwritten
+    /// with the next generation; only then does `self` take the new header.
+    fn commit(&mut self, header: Header, digest: [u8; 32], appended: &[u8]) -> StoreResult<()> {
+        let seal = Seal {
+            generation: self.seal.generation + 1,
+            body_len: self.seal.body_len + appended.len() as u64,
+            digest,
+        };
+        // The header keeps its length: the party count and the field are fixed.
+        let slot = header.encode(&seal);
+        if !appended.is_empty() {
+            let end = 2 * slot.len() as u64 + self.seal.body_len;
+            // Cut off whatever an interrupted append left.
+            self.file.set_len(end)?;
+            self.file.seek(SeekFrom::Start(end))?;
+            self.file.write_all(appended)?;
+            self.file.sync_data()?;
+        }
+        let at = seal.generation % 2 * slot.len() as u64;
+        self.file.seek(SeekFrom::Start(at))?;
+        self.file.write_all(&slot)?;
+        self.file.sync_data()?;
+        self.header = header;
+        self.seal = seal;
+        Ok(())
+    }
+}
+
+/// Load this party's preprocessing into the current session, on every party once the network is
+/// up: `counts` of the material in its store in `dir` (see [store_path]), or all that is left
+/// without `counts`; or, without a `dir`, [DummyTriples], which only an insecure session accepts.
+/// Logs which it is.
+pub fn load_preprocessing_or_dummy<F: Field>(
+    dir: Option<&Path>,
+    counts: Option<Counts>,
+) -> StoreResult<()> {
+    let party_id = Net::party_id();
+    match dir {
+        Some(dir) => {
+            let path = store_path(dir, party_id);
+            let mut store = PreprocessingStore::<F>::open(&path, party_id, Net::n_parties())?;
+            let counts = counts.unwrap_or_else(|| store.left());
+            let material = store.take(counts)?;
+            info!(
+                "Party {} spends {:?} of the preprocessing in {}",
+                party_id,
+                counts,
+                path.display()
+            );
+            material.load(store.mac_share());
+        }
+        None => {
+            warn!(
+                "Party {} has no preprocessing: multiplying with insecure dummy triples",
+                party_id
+            );
+            DummyTriples.load();
+        }
+    }
+    Ok(())
+}
+
+/// The options of an application that runs on dealt preprocessing, to flatten into its own.
+#[derive(Debug, Clone, Default, StructOpt)]
+pub struct PreprocessingOpt {
+    /// Directory of the preprocessing stores, as the dealer wrote them. Without it, multiply with
+    /// insecure dummy triples
+    #[structopt(long = "preprocessing", parse(from_os_str))]
+    pub dir: Option<PathBuf>,
+
+    /// Beaver triples to spend. Without any of the counts, spend all that is left
+    #[structopt(long)]
+    pub triples: Option<usize>,
+
+    /// Inverse pairs to spend
+    #[structopt(long = "inv-pairs")]
+    pub inv_pairs: Option<usize>,
+
+    /// Random bits to spend
+    #[structopt(long)]
+    pub bits: Option<usize>,
+
+    /// Input masks of each party to spend
+    #[structopt(long = "input-masks")]
+    pub input_masks: Option<usize>,
+}
+
+impl PreprocessingOpt {
+    /// The counts asked for, if any; the rest are zero.
+    pub fn counts(&self) -> Option<Counts> {
+        let asked = [self.triples, self.inv_pairs, self.bits, self.input_masks];
+        asked.iter().any(Option::is_some).then(|| Counts {
+            triples: self.triples.unwrap_or(0),
+            inv_pairs: self.inv_pairs.unwrap_or(0),
+            bits: self.bits.unwrap_or(0),
+            input_masks: self.input_masks.unwrap_or(0),
+        })
+    }
+
+    /// [load_preprocessing_or_dummy] as asked.
+    pub fn load<F: Field>(&self) -> StoreResult<()> {
+        load_preprocessing_or_dummy::<F>(self.dir.as_deref(), self.counts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::rand::{rngs::StdRng, SeedableRng};
+    use ark_std::UniformRand;
+
+    type F = ark_bls12_377::Fr;
+
+    fn path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("mpc-store-{}-{}.bin", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("bin.lock"));
+        path
+    }
+
+    /// Random material for party 1 of 3, with `n` of everything.
+    fn material(n: usize) -> Material<F> {
+        let rng = &mut StdRng::seed_from_u64(n as u64);
+        let mut share = || SpdzFieldShare::from_parts(F::rand(rng), F::rand(rng));
+        let mut m = Material::new(3);
+        for _ in 0..n {
+            m.triples.push((share(), share(), share()));
+            m.inv_pairs.push((share(), share()));
+            m.bits.push(share());
+            for owner in 0..3 {
+                let value = (owner == 1).then(|| F::from(owner as u64));
+                m.input_masks[owner].push(InputMask {
+                    share: share(),
+                    value,
+                });
+            }
+        }
+        m
+    }
+
+    #[test]
+    fn spent_material_stays_spent() {
+        let path = path("spent");
+        let (all, more) = (material(3), material(2));
+        let mut store = PreprocessingStore::create(&path, 1, 3, F::from(9u64)).unwrap();
+        store.append(&all).unwrap();
+        let counts = Counts {
+            triples: 2,
+            inv_pairs: 1,
+            bits: 3,
+            input_masks: 2,
+        };
+        let first = store.take(counts).unwrap();
+        assert_eq!(first.triples, all.triples[..2]);
+        assert_eq!(first.input_masks[1][1].value, Some(F::from(1u64)));
+        drop(store);
+
+        let mut store = PreprocessingStore::<F>::open(&path, 1, 3).unwrap();
+        assert_eq!(store.mac_share(), F::from(9u64));
+        assert_eq!(store.header().triples, Cursor { total: 3, used: 2 });
+        store.append(&more).unwrap();
+        let next = store.take(Counts { bits: 2, ..counts }).unwrap();
+        assert_eq!(next.triples, vec![all.triples[2], more.triples[0]]);
+        assert_eq!(next.bits, more.bits);
+        assert!(matches!(
+            store.take(counts),
+            Err(StoreError::Exhausted {
+                need: 2,
+                have: 1,
+                ..
+            })
+        ));
+        drop(store);
+        let store = PreprocessingStore::<F>::open(&path, 1, 3).unwrap();
+        assert_eq!(
+            store.left(),
+            Counts {
+                triples: 1,
+                inv_pairs: 3,
+                bits: 0,
+                input_masks: 1,
+            }
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_tampering_and_strangers() {
+        let path = path("tamper");
+        let mut store = PreprocessingStore::create(&path, 1, 3, F::from(9u64)).unwrap();
+        store.append(&material(2)).unwrap();
+        drop(store);
+        assert!(matches!(
+            PreprocessingStore::<F>::open(&path, 0, 3),
+            Err(StoreError::Mismatch(_))
+        ));
+        assert!(matches!(
+            PreprocessingStore::<ark_bls12_377::Fq>::open(&path, 1, 3),
+            Err(StoreError::Mismatch(_))
+        ));
+        let clean = std::fs::read(&path).unwrap();
+        let slot = Header::new::<F>(1, 3).slot_len();
+        for at in [vec![9, slot + 9], vec![clean.len() - 5]] {
+            let mut bytes = clean.clone();
+            for at in at {
+                bytes[at] ^= 1;
+            }
+            std::fs::write(&path, &bytes).unwrap();
+            assert!(matches!(
+                PreprocessingStore::<F>::open(&path, 1, 3),
+                Err(StoreError::Corrupt(_))
+            ));
+        }
+        std::fs::write(&path, &clean[..clean.len() - 1]).unwrap();
+        assert!(matches!(
+            PreprocessingStore::<F>::open(&path, 1, 3),
+            Err(StoreError::Corrupt(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_writes_roll_back() {
+        let path = path("torn");
+        let mut store = PreprocessingStore::create(&path, 1, 3, F::from(9u64)).unwrap();
+        store.append(&material(2)).unwrap();
+        let before = store.left();
+        let one = Counts {
+            triples: 1,
+            ..Counts::default()
+        };
+        store.take(one).unwrap();
+        drop(store);
+
+        // The take was the third write, so it went to the first slot.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[9] ^= 1;
+        // And an append died before its header was written.
+        bytes.extend([TRIPLES, 0, 0]);
+        std::fs::write(&path, &bytes).unwrap();
+        let mut store = PreprocessingStore::<F>::open(&path, 1, 3).unwrap();
+        assert_eq!(store.left(), before);
+        store.append(&material(1)).unwrap();
+        drop(store);
+        let store = PreprocessingStore::<F>::open(&path, 1, 3).unwrap();
+        assert_eq!(store.left().triples, 3);
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn options_ask_for_all_or_some() {
+        let opt = PreprocessingOpt::from_iter(["app", "--preprocessing", "prep"]);
+        assert_eq!(opt.dir, Some(PathBuf::from("prep")));
+        assert_eq!(opt.counts(), None);
+        let opt = PreprocessingOpt::from_iter(["app", "--preprocessing", "prep", "--bits", "4"]);
+        assert_eq!(
+            opt.counts(),
+            Some(Counts {
+                bits: 4,
+                ..Counts::default()
+            })
+        );
+    }
+
+    #[test]
+    fn one_handle_at_a_time() {
+        let path = path("locked");
+        let store = PreprocessingStore::create(&path, 1, 3, F::from(9u64)).unwrap();
+        assert!(matches!(
+            PreprocessingStore::<F>::open(&path, 1, 3),
+            Err(StoreError::Locked(_))
+        ));
+        drop(store);
+        let store = PreprocessingStore::<F>::open(&path, 1, 3).unwrap();
+        assert!(matches!(
+            PreprocessingStore::<F>::open(&path, 1, 3),
+            Err(StoreError::Locked(_))
+        ));
+        drop(store);
+        PreprocessingStore::<F>::open(&path, 1, 3).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
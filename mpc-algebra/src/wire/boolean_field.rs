@@ -3,7 +3,8 @@ use std::ops::{BitAnd, BitOr, BitXor, Not};
 use ark_ff::{BigInteger, Field, FpParameters, One, PrimeField, SquareRootField, UniformRand, Zero};
 use mpc_trait::MpcWire;
 use rand::Rng;
-use crate::{BitAdd, BitwiseLessThan, FieldShare, MpcField, Reveal, UniformBitRand};
+use mpc_net::{MpcMultiNet as Net, MpcNet};
+use crate::{BitAdd, BitwiseLessThan, FieldShare, MpcField, Reveal, TripleStore, UniformBitRand};
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MpcBooleanField<F: Field, S: FieldShare<F>>(MpcField<F,S>);
@@ -131,7 +132,12 @@ impl<F: PrimeField, S: FieldShare<F>> BitwiseLessThan for Vec<MpcBooleanField<F,
 impl<F: PrimeField + SquareRootField, S: FieldShare<F>> UniformBitRand for MpcBooleanField<F, S> {
     type BaseField = MpcField<F, S>;
 
+    /// Spends a bit of the [TripleStore] loaded into the session, if any. Otherwise, derives one
+    /// from a random value `r` and the opened square root of `r²`.
     fn bit_rand<R: rand::Rng + ?Sized>(rng: &mut R) -> Self {
+        if let Some(store) = Net::attached::<TripleStore<S>>() {
+            return Self(MpcField::Shared(store.bit()));
+        }
         let r = Self::BaseField::rand(rng);
         let r2 = (r * r).reveal();
         let mut root_r2;
@@ -4,12 +4,14 @@ use derivative::Derivative;
 use mpc_trait::MpcWire;
 use num_bigint::BigUint;
 use rand::Rng;
+use std::collections::VecDeque;
 use std::fmt::{self, Debug, Display};
 use std::io::{self, Read, Write};
 use std::iter::{Product, Sum};
 use std::marker::PhantomData;
 use std::ops::*;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
 use zeroize::Zeroize;
 
 use log::debug;
@@ -22,13 +24,14 @@ use ark_serialize::{
 };
 
 use crate::boolean_field::MpcBooleanField;
+use crate::channel::can_cheat;
 // use crate::channel::MpcSerNet;
 use crate::share::field::FieldShare;
 use crate::{
     BeaverSource, BitAdd, BitDecomposition, BitwiseLessThan, LessThan, LogicalOperations, Reveal,
 };
 use crate::{EqualityZero, UniformBitRand};
-use mpc_net::{MpcMultiNet as Net, MpcNet};
+use mpc_net::{MpcMultiNet as Net, MpcNet, NetResult};
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MpcField<F: Field, S: FieldShare<F>> {
@@ -36,6 +39,7 @@ pub enum MpcField<F: Field, S: FieldShare<F>> {
     Shared(S),
 }
 
+/// Hands out the public triple (1, 1, 1) and inverse pair (1, 1): correct, but no secret at all.
 #[derive(Derivative)]
 #[derivative(Default(bound = ""), Clone(bound = ""), Copy(bound = ""))]
 pub struct DummyFieldTripleSource<F, S> {
@@ -59,13 +63,166 @@ impl<T: Field, S: FieldShare<T>> BeaverSource<S, S, S> for DummyFieldTripleSourc
     }
 }
 
+/// Beaver triples, inverse pairs and random bits of shares `S` from preprocessing, for
+/// [SessionBeaverSource] and [crate::UniformBitRand::bit_rand] to spend once it is loaded into a
+/// session.
+#[derive(Debug)]
+pub struct TripleStore<S> {
+    triples: Mutex<VecDeque<(S, S, S)>>,
+    inv_pairs: Mutex<VecDeque<(S, S)>>,
+    bits: Mutex<VecDeque<S>>,
+}
+
+impl<S> Default for TripleStore<S> {
+    fn default() -> Self {
+        Self {
+            triples: Mutex::new(VecDeque::new()),
+            inv_pairs: Mutex::new(VecDeque::new()),
+            bits: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl<S: Send + Sync + 'static> TripleStore<S> {
+    /// Add triples `(x, y, z)` of random `x` and `y`, with `z = xy`.
+    pub fn with_triples(self, triples: impl IntoIterator<Item = (S, S, S)>) -> Self {
+        lock(&self.triples).extend(triples);
+        self
+    }
+
+    /// Add inverse pairs, as [FieldShare::inv] spends them: two shares of the same random
+    /// nonzero value.
+    pub fn with_inv_pairs(self, pairs: impl IntoIterator<Item = (S, S)>) -> Self {
+        lock(&self.inv_pairs).extend(pairs);
+        self
+    }
+
+    /// Add shares of random values in {0, 1}.
+    pub fn with_bits(self, bits: impl IntoIterator<Item = S>) -> Self {
+        lock(&self.bits).extend(bits);
+        self
+    }
+
+    /// Attach this store to the current session, replacing any loaded before. Do so after the
+    /// network is initialized, and at the same point on every party.
+    pub fn load(self) {
+        Net::attach(self)
+    }
+
+    pub fn triples_left(&self) -> usize {
+        lock(&self.triples).len()
+    }
+
+    pub fn inv_pairs_left(&self) -> usize {
+        lock(&self.inv_pairs).len()
+    }
+
+    pub fn bits_left(&self) -> usize {
+        lock(&self.bits).len()
+    }
+
+    /// Spend the next random bit, or panic if none is left.
+    pub(crate) fn bit(&self) -> S {
+        take(&self.bits, 1, "random bits").pop().unwrap()
+    }
+}
+
+/// Take the next `n` of `what` from `items`, or panic if there are not that many left.
+fn take<T>(items: &Mutex<VecDeque<T>>, n: usize, what: &str) -> Vec<T> {
+    let mut items = lock(items);
+    let left = items.len();
+    if left < n {
+        drop(items);
+        panic!("Out of preprocessed {}: need {}, have {}", what, n, left);
+    }
+    items.drain(..n).collect()
+}
+
+fn lock<T>(items: &Mutex<T>) -> MutexGuard<'_, T> {
+    items.lock().expect("Poisoned TripleStore")
+}
+
+/// Loaded into a session in place of a [TripleStore], lets [SessionBeaverSource] hand out the
+/// public triples of [DummyFieldTripleSource]. Only an [mpc_net::Security::Insecure] session can
+/// use them: for tests and benchmarks, where no preprocessing is at hand.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DummyTriples;
+
+impl DummyTriples {
+    /// Attach to the current session, at the same point on every party.
+    pub fn load(self) {
+        Net::attach(self)
+    }
+}
+
+/// Spends the [TripleStore] loaded into the session, and panics once it runs out. Without a
+/// store, it panics too, unless the session opted in to [DummyTriples].
+#[derive(Derivative)]
+#[derivative(Default(bound = ""), Clone(bound = ""), Copy(bound = ""))]
+pub struct SessionBeaverSource<F, S> {
+    _scalar: PhantomData<F>,
+    _share: PhantomData<S>,
+}
+
+impl<F: Field, S: FieldShare<F>> SessionBeaverSource<F, S> {
+    fn store() -> Option<Arc<TripleStore<S>>> {
+        let store = Net::attached::<TripleStore<S>>();
+        if store.is_none() {
+            if Net::attached::<DummyTriples>().is_none() {
+                panic!("No Beaver triples are loaded in this session, nor DummyTriples");
+            }
+            if !can_cheat() {
+                panic!("Dummy Beaver triples are insecure, but this is a production session");
+            }
+        }
+        store
+    }
+}
+
+impl<F: Field, S: FieldShare<F>> BeaverSource<S, S, S> for SessionBeaverSource<F, S> {
+    fn triple(&mut self) -> (S, S, S) {
+        match Self::store() {
+            Some(store) => take(&store.triples, 1, "Beaver triples").pop().unwrap(),
+            None => DummyFieldTripleSource::<F, S>::default().triple(),
+        }
+    }
+    fn triples(&mut self, n: usize) -> (Vec<S>, Vec<S>, Vec<S>) {
+        match Self::store() {
+            Some(store) => {
+                let mut xs = Vec::with_capacity(n);
+                let mut ys = Vec::with_capacity(n);
+                let mut zs = Vec::with_capacity(n);
+                for (x, y, z) in take(&store.triples, n, "Beaver triples") {
+                    xs.push(x);
+                    ys.push(y);
+                    zs.push(z);
+                }
+                (xs, ys, zs)
+            }
+            None => DummyFieldTripleSource::<F, S>::default().triples(n),
+        }
+    }
+    fn inv_pair(&mut self) -> (S, S) {
+        match Self::store() {
+            Some(store) => take(&store.inv_pairs, 1, "inverse pairs").pop().unwrap(),
+            None => DummyFieldTripleSource::<F, S>::default().inv_pair(),
+        }
+    }
+    fn inv_pairs(&mut self, n: usize) -> (Vec<S>, Vec<S>) {
+        match Self::store() {
+            Some(store) => take(&store.inv_pairs, n, "inverse pairs")
+                .into_iter()
+                .unzip(),
+            None => DummyFieldTripleSource::<F, S>::default().inv_pairs(n),
+        }
+    }
+}
+
 impl<F: Field, S: FieldShare<F>> MpcField<F, S> {
     pub fn inv(self) -> Option<Self> {
         match self {
             Self::Public(x) => x.inverse().map(MpcField::Public),
-            Self::Shared(x) => Some(MpcField::Shared(
-                x.inv(&mut DummyFieldTripleSource::default()),
-            )),
+            Self::Shared(x) => Some(MpcField::Shared(x.inv(&mut SessionBeaverSource::default()))),
         }
     }
 
@@ -108,6 +265,15 @@ impl<T: Field, S: FieldShare<T>> Reveal for MpcField<T, S> {
         result
     }
     #[inline]
+    fn try_reveal(self) -> NetResult<Self::Base> {
+        let result = match self {
+            Self::Shared(s) => s.try_reveal()?,
+            Self::Public(s) => s,
+        };
+        super::macros::check_eq(result);
+        Ok(result)
+    }
+    #[inline]
     fn from_public(b: Self::Base) -> Self {
         MpcField::Public(b)
     }
@@ -445,10 +611,7 @@ impl<'a, F: Field, S: FieldShare<F>> MulAssign<&'a MpcField<F, S>> for MpcField<
                     a.scale(b);
                 }
                 MpcField::Shared(b) => {
-                    // TODO implement correctly by using beaver triples
-
-                    let mut source = DummyFieldTripleSource::<F, S>::default();
-
+                    let mut source = SessionBeaverSource::<F, S>::default();
                     let t = a.beaver_mul(*b, &mut source);
                     *self = MpcField::Shared(t);
                 }
@@ -497,8 +660,7 @@ impl<'a, F: Field, S: FieldShare<F>> DivAssign<&'a MpcField<F, S>> for MpcField<
                     a.scale(&b.inverse().unwrap());
                 }
                 MpcField::Shared(b) => {
-                    // TODO implement correctly by using beaver triples
-                    let src = &mut DummyFieldTripleSource::default();
+                    let src = &mut SessionBeaverSource::default();
                     *a = a.beaver_div(*b, src);
                 }
             },
@@ -876,7 +1038,7 @@ impl<F: PrimeField, S: FieldShare<F>> Field for MpcField<F, S> {
                     Self::Public(_) => unreachable!(),
                 })
                 .collect();
-            let nshares = S::batch_mul(sshares, oshares, &mut DummyFieldTripleSource::default());
+            let nshares = S::batch_mul(sshares, oshares, &mut SessionBeaverSource::default());
             for (self_, new) in selfs.iter_mut().zip(nshares.into_iter()) {
                 *self_ = Self::Shared(new);
             }
@@ -913,7 +1075,7 @@ impl<F: PrimeField, S: FieldShare<F>> Field for MpcField<F, S> {
                     Self::Public(_) => unreachable!(),
                 })
                 .collect();
-            let nshares = S::batch_div(sshares, oshares, &mut DummyFieldTripleSource::default());
+            let nshares = S::batch_div(sshares, oshares, &mut SessionBeaverSource::default());
             for (self_, new) in selfs.iter_mut().zip(nshares.into_iter()) {
                 *self_ = Self::Shared(new);
             }
@@ -1094,3 +1256,139 @@ mod poly_impl {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::share::{additive::AdditiveFieldShare, spdz::SpdzFieldShare};
+    use ark_std::{test_rng, UniformRand};
+    use mpc_net::{local::simulate, King, KingRotation};
+
+    type F = ark_bls12_377::Fr;
+    type AddField = MpcField<F, AdditiveFieldShare<F>>;
+    type SpdzField = MpcField<F, SpdzFieldShare<F>>;
+
+    const N_PARTIES: usize = 3;
+
+    fn arithmetic<S: FieldShare<F>>(king: King) {
+        simulate(N_PARTIES, |_| {
+            DummyTriples.load();
+            Net::set_king(king);
+            let rng = &mut test_rng();
+            let (a, b) = (F::rand(rng), F::rand(rng));
+            let sa = MpcField::<F, S>::king_share(a, rng);
+            let sb = MpcField::<F, S>::king_share(b, rng);
+            assert_eq!((sa + sb).reveal(), a + b);
+            assert_eq!((sa - sb).reveal(), a - b);
+            assert_eq!((sa * sb).reveal(), a * b);
+            assert_eq!((sa / sb).reveal(), a / b);
+            assert_eq!(
+                [sa, sb, sa].iter().sum::<MpcField<F, S>>().reveal(),
+                a + b + a
+            );
+        });
+    }
+
+    #[test]
+    fn additive_arithmetic() {
+        arithmetic::<AdditiveFieldShare<F>>(King::default());
+    }
+
+    #[test]
+    fn spdz_arithmetic() {
+        arithmetic::<SpdzFieldShare<F>>(King::default());
+    }
+
+    #[test]
+    fn spdz_arithmetic_rotating_king() {
+        arithmetic::<SpdzFieldShare<F>>(King {
+            first: 1,
+            rotate: KingRotation::PerCall,
+        });
+    }
+
+    #[test]
+    fn beaver_mul() {
+        let outputs = simulate(N_PARTIES, |id| {
+            let a = SpdzFieldShare::from_add_shared(F::from(id as u64 + 1));
+            let b = SpdzFieldShare::from_add_shared(F::from(id as u64 + 2));
+            a.beaver_mul(b, &mut DummyFieldTripleSource::default())
+                .reveal()
+        });
+        assert!(outputs.iter().all(|&c| c == F::from(6u64 * 9)));
+    }
+
+    #[test]
+    fn multiplication_spends_the_store() {
+        type S = AdditiveFieldShare<F>;
+        let outputs = simulate(N_PARTIES, |id| {
+            // Every party deals the same material, and keeps its own part.
+            let rng = &mut test_rng();
+            let mut share = |x: F| {
+                let parts: Vec<F> = (1..N_PARTIES).map(|_| F::rand(rng)).collect();
+                let last = x - parts.iter().sum::<F>();
+                S::from_add_shared(parts.get(id).copied().unwrap_or(last))
+            };
+            let mut triples = Vec::new();
+            for _ in 0..3 {
+                let (x, y) = (F::rand(&mut test_rng()), F::from(7u64));
+                triples.push((share(x), share(y), share(x * y)));
+            }
+            let r = F::from(11u64);
+            let pairs = vec![(share(r), share(r))];
+
+            let (a, b) = (F::from(3u64), F::from(5u64));
+            let sa = AddField::king_share(a, rng);
+            let sb = AddField::king_share(b, rng);
+            // No store, and no opt-in to dummy triples.
+            assert!(std::panic::catch_unwind(|| sa * sb).is_err());
+            TripleStore::default()
+                .with_triples(triples)
+                .with_inv_pairs(pairs)
+                .load();
+            let product = (sa * sb).reveal();
+            let quotient = (sa / sb).reveal();
+            let store = Net::attached::<TripleStore<S>>().unwrap();
+            let left = (store.triples_left(), store.inv_pairs_left());
+            let exhausted = std::panic::catch_unwind(|| sa / sb).is_err();
+            (product, quotient, left, exhausted)
+        });
+        for (product, quotient, left, exhausted) in outputs {
+            assert_eq!(product, F::from(15u64));
+            assert_eq!(quotient, F::from(3u64) / F::from(5u64));
+            assert_eq!(left, (0, 0));
+            assert!(exhausted);
+        }
+    }
+
+    #[test]
+    fn bit_decomposition() {
+        simulate(N_PARTIES, |_| {
+            DummyTriples.load();
+            let a = AddField::rand(&mut test_rng());
+            let value = a.reveal();
+            let bits = a.bit_decomposition().reveal();
+            let recomposed = bits.iter().rev().fold(F::zero(), |acc, b| acc.double() + b);
+            assert_eq!(recomposed, value);
+        });
+    }
+
+    #[test]
+    fn less_than_and_equality_zero() {
+        simulate(N_PARTIES, |_| {
+            DummyTriples.load();
+            let rng = &mut test_rng();
+            let a = SpdzField::rand(rng);
+            let b = SpdzField::rand(rng);
+            assert_eq!(
+                a.is_smaller_than(&b).reveal().is_one(),
+                a.reveal() < b.reveal()
+            );
+            assert!(SpdzField::from_add_shared(F::zero())
+                .is_zero_shared()
+                .reveal()
+                .is_one());
+            assert!(a.is_zero_shared().reveal().is_zero());
+        });
+    }
+}
@@ -12,6 +12,7 @@ use ark_serialize::{
     CanonicalSerializeWithFlags,
 };
 use ark_serialize::{Flags, SerializationError};
+use mpc_net::NetResult;
 use mpc_trait::MpcWire;
 
 use crate::share::group::GroupShare;
@@ -50,6 +51,15 @@ impl<G: Group, S: GroupShare<G>> Reveal for MpcGroup<G, S> {
         result
     }
 
+    fn try_reveal(self) -> NetResult<Self::Base> {
+        let result = match self {
+            Self::Shared(s) => s.try_reveal()?,
+            Self::Public(s) => s,
+        };
+        super::macros::check_eq(result);
+        Ok(result)
+    }
+
     fn from_add_shared(_b: Self::Base) -> Self {
         todo!()
     }
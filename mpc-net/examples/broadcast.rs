@@ -1,5 +1,5 @@
 use log::debug;
-use mpc_net::{MpcMultiNet, MpcNet};
+use mpc_net::{secure::from_hex, MpcMultiNet, MpcNet};
 
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -10,17 +10,34 @@ struct Opt {
     /// Id
     id: usize,
 
-    /// Input file
+    /// Input file: an address file, or a .toml/.json config
     #[structopt(parse(from_os_str))]
     input: PathBuf,
+
+    /// Private key file (see the keygen example); enables authenticated, encrypted channels
+    #[structopt(long, parse(from_os_str))]
+    key: Option<PathBuf>,
 }
 
 fn main() {
-    env_logger::builder().format_timestamp(None).format_module_path(false).init();
+    env_logger::builder()
+        .format_timestamp(None)
+        .format_module_path(false)
+        .init();
     debug!("Start");
     let opt = Opt::from_args();
     println!("{:?}", opt);
-    MpcMultiNet::init_from_file(opt.input.to_str().unwrap(), opt.id);
+    let input = opt.input.to_str().unwrap();
+    match &opt.key {
+        _ if input.ends_with(".toml") || input.ends_with(".json") => {
+            MpcMultiNet::init_from_config(input, opt.id)
+        }
+        Some(key) => {
+            let key = from_hex(&std::fs::read_to_string(key).unwrap()).unwrap();
+            MpcMultiNet::try_init_secure_from_file(input, opt.id, &key).unwrap()
+        }
+        None => MpcMultiNet::init_from_file(input, opt.id),
+    }
     let all = MpcMultiNet::broadcast_bytes(&[opt.id as u8]);
     println!("{:?}", all);
     let r = MpcMultiNet::send_bytes_to_king(&[opt.id as u8]);
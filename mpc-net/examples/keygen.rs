@@ -0,0 +1,40 @@
+use mpc_net::secure::{generate_keypair, to_hex};
+
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "keygen",
+    about = "Generate a keypair per party for authenticated, encrypted channels."
+)]
+struct Opt {
+    /// Address file, one HOST:PORT per line
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Output directory; gets `hosts` (addresses with public keys) and `<id>.key` per party
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let addrs: Vec<String> = BufReader::new(fs::File::open(&opt.input).unwrap())
+        .lines()
+        .map(|l| l.unwrap().trim().to_owned())
+        .filter(|l| !l.is_empty())
+        .collect();
+    fs::create_dir_all(&opt.output).unwrap();
+    let mut hosts = String::new();
+    for (id, addr) in addrs.iter().enumerate() {
+        let kp = generate_keypair();
+        hosts += &format!("{} {}\n", addr, to_hex(&kp.public));
+        fs::write(opt.output.join(format!("{}.key", id)), to_hex(&kp.private)).unwrap();
+    }
+    fs::write(opt.output.join("hosts"), hosts).unwrap();
+}
@@ -0,0 +1,35 @@
+use log::info;
+use mpc_net::relay::Relay;
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "relay",
+    about = "Forward all messages between parties that cannot reach each other directly."
+)]
+struct Opt {
+    /// HOST:PORT to listen on
+    #[structopt(default_value = "127.0.0.1:8000")]
+    listen: String,
+
+    /// Serve a single session, then exit
+    #[structopt(long)]
+    once: bool,
+}
+
+fn main() {
+    env_logger::builder()
+        .format_timestamp(None)
+        .format_module_path(false)
+        .init();
+    let opt = Opt::from_args();
+    let relay = Relay::bind(&opt.listen).unwrap();
+    info!("Listening on {}", relay.local_addr().unwrap());
+    loop {
+        relay.serve_session().unwrap();
+        if opt.once {
+            break;
+        }
+    }
+}
@@ -0,0 +1,959 @@
+//! An async transport on tokio, so parties can be embedded in async services.
+//!
+//! [AsyncSession] offers the byte operations of [MpcSession] as futures. Connections are set up
+//! exactly as for the blocking transport (on a blocking thread, see [MpcSession::try_from_config])
+//! and then driven by the runtime, with the same frames and tags on the wire: async and blocking
+//! parties can take part in one session.
+//!
+//! To run code written against the blocking [crate::MpcNet] API, e.g. the share types of
+//! `mpc-algebra`, turn the session into an [MpcSession] with [AsyncSession::into_blocking]. Its
+//! operations block on the runtime, so they must run off the runtime's worker threads, e.g. inside
+//! [tokio::task::spawn_blocking].
+use std::{
+    future::Future,
+    net,
+    panic::Location,
+    time::{Duration, Instant},
+};
+
+use futures::future::{try_join, try_join_all};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    runtime::Handle,
+    sync::Mutex,
+};
+
+use crate::{
+    multi::{check_frame_len, Connections},
+    secure::{SecureLink, MAX_RECORD_PAYLOAD},
+    session::{open_all, State},
+    tag::{Op, Tag},
+    King, MpcSession, NetConfig, NetError, NetResult, PhaseStats, Security, Stats, Transport,
+};
+
+#[derive(Debug)]
+struct Peer {
+    id: usize,
+    stream: Option<TcpStream>,
+    link: Option<SecureLink>,
+    /// Longest frame we accept.
+    max_len: usize,
+}
+
+impl Peer {
+    /// `bytes` as one frame on the wire, as the blocking transport writes it.
+    fn seal(&mut self, bytes: &[u8]) -> NetResult<Vec<u8>> {
+        let size = (bytes.len() as u64).to_le_bytes();
+        let Some(link) = &mut self.link else {
+            return Ok([&size[..], bytes].concat());
+        };
+        let mut frame = vec![];
+        for chunk in std::iter::once(&size[..]).chain(bytes.chunks(MAX_RECORD_PAYLOAD)) {
+            let record = link.encrypt(self.id, chunk)?;
+            frame.extend_from_slice(&(record.len() as u16).to_le_bytes());
+            frame.extend_from_slice(&record);
+        }
+        Ok(frame)
+    }
+
+    fn stream(&mut self) -> NetResult<&mut TcpStream> {
+        self.stream
+            .as_mut()
+            .ok_or(NetError::Disconnected { peer: self.id })
+    }
+
+    async fn write(&mut self, frame: &[u8], limit: Option<Duration>) -> NetResult<()> {
+        let id = self.id;
+        let stream = self.stream()?;
+        within(id, limit, async {
+            stream
+                .write_all(frame)
+                .await
+                .map_err(|e| NetError::io(id, e))
+        })
+        .await
+    }
+
+    async fn read(&mut self, limit: Option<Duration>) -> NetResult<Vec<u8>> {
+        let id = self.id;
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(NetError::Disconnected { peer: id })?;
+        within(
+            id,
+            limit,
+            read_frame(id, stream, &mut self.link, self.max_len),
+        )
+        .await
+    }
+
+    /// Write `frame` and read one from the peer at the same time.
+    async fn exchange(
+        &mut self,
+        frame: &[u8],
+        read_limit: Option<Duration>,
+        write_limit: Option<Duration>,
+    ) -> NetResult<Vec<u8>> {
+        let id = self.id;
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(NetError::Disconnected { peer: id })?;
+        let (mut reader, mut writer) = stream.split();
+        let write = within(id, write_limit, async {
+            writer
+                .write_all(frame)
+                .await
+                .map_err(|e| NetError::io(id, e))
+        });
+        let read = within(
+            id,
+            read_limit,
+            read_frame(id, &mut reader, &mut self.link, self.max_len),
+        );
+        let ((), bytes_in) = try_join(write, read).await?;
+        Ok(bytes_in)
+    }
+}
+
+/// Fail `fut` with [NetError::Timeout] if it takes longer than `limit`.
+async fn within<T>(
+    peer: usize,
+    limit: Option<Duration>,
+    fut: impl Future<Output = NetResult<T>>,
+) -> NetResult<T> {
+    match limit {
+        None => fut.await,
+        Some(limit) => tokio::time::timeout(limit, fut)
+            .await
+            .unwrap_or(Err(NetError::Timeout { peer })),
+    }
+}
+
+/// Read one frame written by [Peer::seal] on the other end.
+async fn read_frame(
+    peer: usize,
+    reader: &mut (impl AsyncRead + Unpin),
+    link: &mut Option<SecureLink>,
+    max_len: usize,
+) -> NetResult<Vec<u8>> {
+    let Some(link) = link else {
+        let mut size = [0u8; 8];
+        reader
+            .read_exact(&mut size)
+            .await
+            .map_err(|e| NetError::io(peer, e))?;
+        let mut bytes_in = vec![0u8; check_frame_len(peer, size, max_len)?];
+        reader
+            .read_exact(&mut bytes_in)
+            .await
+            .map_err(|e| NetError::io(peer, e))?;
+        return Ok(bytes_in);
+    };
+    let size = read_record(peer, reader, link).await?;
+    let size: [u8; 8] = size.try_into().map_err(|_| NetError::MalformedFrame {
+        peer,
+        reason: "bad length record".to_owned(),
+    })?;
+    let m = check_frame_len(peer, size, max_len)?;
+    let mut bytes_in = Vec::with_capacity(m);
+    while bytes_in.len() < m {
+        let chunk = read_record(peer, reader, link).await?;
+        if chunk.is_empty() || bytes_in.len() + chunk.len() > m {
+            return Err(NetError::MalformedFrame {
+                peer,
+                reason: "records do not add up to the announced length".to_owned(),
+            });
+        }
+        bytes_in.extend_from_slice(&chunk);
+    }
+    Ok(bytes_in)
+}
+
+async fn read_record(
+    peer: usize,
+    reader: &mut (impl AsyncRead + Unpin),
+    link: &mut SecureLink,
+) -> NetResult<Vec<u8>> {
+    let mut len = [0u8; 2];
+    reader
+        .read_exact(&mut len)
+        .await
+        .map_err(|e| NetError::io(peer, e))?;
+    let mut record = vec![0u8; u16::from_le_bytes(len) as usize];
+    reader
+        .read_exact(&mut record)
+        .await
+        .map_err(|e| NetError::io(peer, e))?;
+    link.decrypt(peer, &record)
+}
+
+/// The connections of one party, driven by a tokio runtime.
+///
+/// As a [Transport], every operation blocks on the runtime it was created in.
+#[derive(Debug)]
+pub(crate) struct AsyncConnections {
+    id: usize,
+    peers: Vec<Peer>,
+    stats: Stats,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    handle: Handle,
+}
+
+impl AsyncConnections {
+    /// Connect with `init` on a blocking thread, then move the connections onto the runtime.
+    async fn connect(
+        init: impl FnOnce(&mut Connections) -> NetResult<()> + Send + 'static,
+    ) -> NetResult<Self> {
+        let mut conns = tokio::task::spawn_blocking(move || {
+            let mut conns = Connections::default();
+            init(&mut conns)?;
+            conns.connect_to_all()?;
+            Ok::<_, NetError>(conns)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))?;
+        let max_len = conns.max_message_len();
+        let peers = conns
+            .take_streams()
+            .into_iter()
+            .enumerate()
+            .map(|(id, (stream, link))| {
+                let stream = stream
+                    .map(|s: net::TcpStream| {
+                        // Deadlines are the runtime's job now.
+                        s.set_read_timeout(None)?;
+                        s.set_write_timeout(None)?;
+                        s.set_nonblocking(true)?;
+                        TcpStream::from_std(s)
+                    })
+                    .transpose()
+                    .map_err(|e| NetError::io(id, e))?;
+                Ok(Peer {
+                    id,
+                    stream,
+                    link,
+                    max_len,
+                })
+            })
+            .collect::<NetResult<_>>()?;
+        Ok(Self {
+            id: conns.id,
+            peers,
+            stats: std::mem::take(&mut conns.stats),
+            read_timeout: conns.read_timeout,
+            write_timeout: conns.write_timeout,
+            handle: Handle::current(),
+        })
+    }
+
+    async fn broadcast_async(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let own_id = self.id;
+        let (read_limit, write_limit) = (self.read_timeout, self.write_timeout);
+        self.stats.bytes_sent += (self.peers.len() - 1) * (bytes_out.len() + 8);
+        self.stats.broadcasts += 1;
+        let r = try_join_all(self.peers.iter_mut().map(|peer| async move {
+            if peer.id == own_id {
+                return Ok(bytes_out.to_vec());
+            }
+            let frame = peer.seal(bytes_out)?;
+            peer.exchange(&frame, read_limit, write_limit).await
+        }))
+        .await?;
+        self.stats.bytes_recv += received(&r, own_id);
+        Ok(r)
+    }
+
+    async fn send_to_king_async(
+        &mut self,
+        king: usize,
+        bytes_out: &[u8],
+    ) -> NetResult<Option<Vec<Vec<u8>>>> {
+        let own_id = self.id;
+        let read_limit = self.read_timeout;
+        self.stats.to_king += 1;
+        if own_id != king {
+            self.stats.bytes_sent += bytes_out.len() + 8;
+            let peer = &mut self.peers[king];
+            let frame = peer.seal(bytes_out)?;
+            peer.write(&frame, self.write_timeout).await?;
+            return Ok(None);
+        }
+        let r = try_join_all(self.peers.iter_mut().map(|peer| async move {
+            if peer.id == own_id {
+                Ok(bytes_out.to_vec())
+            } else {
+                peer.read(read_limit).await
+            }
+        }))
+        .await?;
+        self.stats.bytes_recv += received(&r, own_id);
+        Ok(Some(r))
+    }
+
+    async fn recv_from_king_async(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        let own_id = self.id;
+        let write_limit = self.write_timeout;
+        self.stats.from_king += 1;
+        if own_id != king {
+            let bytes_in = self.peers[king].read(self.read_timeout).await?;
+            self.stats.bytes_recv += bytes_in.len() + 8;
+            return Ok(bytes_in);
+        }
+        let bytes_out = bytes_out.expect("the king must provide bytes to recv_from_king");
+        assert_eq!(bytes_out.len(), self.peers.len());
+        self.stats.bytes_sent += bytes_out
+            .iter()
+            .enumerate()
+            .filter(|(id, _)| *id != own_id)
+            .map(|(_, b)| b.len() + 8)
+            .sum::<usize>();
+        let bytes_out = &bytes_out;
+        try_join_all(self.peers.iter_mut().filter(|peer| peer.id != own_id).map(
+            |peer| async move {
+                let frame = peer.seal(&bytes_out[peer.id])?;
+                peer.write(&frame, write_limit).await
+            },
+        ))
+        .await?;
+        Ok(bytes_out[own_id].clone())
+    }
+
+    async fn send_to_async(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.stats.bytes_sent += bytes_out.len() + 8;
+        let peer = &mut self.peers[to];
+        let frame = peer.seal(bytes_out)?;
+        peer.write(&frame, self.write_timeout).await
+    }
+
+    async fn recv_from_async(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        let bytes_in = self.peers[from].read(self.read_timeout).await?;
+        self.stats.bytes_recv += bytes_in.len() + 8;
+        Ok(bytes_in)
+    }
+
+    async fn exchange_async(&mut self, peer: usize, bytes_out: &[u8]) -> NetResult<Vec<u8>> {
+        self.stats.bytes_sent += bytes_out.len() + 8;
+        let (read_limit, write_limit) = (self.read_timeout, self.write_timeout);
+        let peer = &mut self.peers[peer];
+        let frame = peer.seal(bytes_out)?;
+        let bytes_in = peer.exchange(&frame, read_limit, write_limit).await?;
+        self.stats.bytes_recv += bytes_in.len() + 8;
+        Ok(bytes_in)
+    }
+}
+
+/// Bytes on the wire for `msgs`, length prefixes included, not counting our own message.
+fn received(msgs: &[Vec<u8>], own_id: usize) -> usize {
+    msgs.iter()
+        .enumerate()
+        .filter(|(id, _)| *id != own_id)
+        .map(|(_, b)| b.len() + 8)
+        .sum()
+}
+
+impl Transport for AsyncConnections {
+    fn party_id(&self) -> usize {
+        self.id
+    }
+    fn n_parties(&self) -> usize {
+        self.peers.len()
+    }
+    fn is_init(&self) -> bool {
+        self.peers
+            .iter()
+            .any(|p| p.id != self.id && p.stream.is_some())
+    }
+    fn stats(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+    fn set_timeouts(&mut self, read: Option<Duration>, write: Option<Duration>) -> NetResult<()> {
+        self.read_timeout = read;
+        self.write_timeout = write;
+        Ok(())
+    }
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        self.handle
+            .clone()
+            .block_on(self.broadcast_async(bytes_out))
+    }
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        self.handle
+            .clone()
+            .block_on(self.send_to_king_async(king, bytes_out))
+    }
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        self.handle
+            .clone()
+            .block_on(self.recv_from_king_async(king, bytes_out))
+    }
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.handle
+            .clone()
+            .block_on(self.send_to_async(to, bytes_out))
+    }
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        self.handle.clone().block_on(self.recv_from_async(from))
+    }
+    fn exchange(&mut self, peer: usize, bytes_out: &[u8]) -> NetResult<Vec<u8>> {
+        self.handle
+            .clone()
+            .block_on(self.exchange_async(peer, bytes_out))
+    }
+    fn uninit(&mut self) {
+        for p in &mut self.peers {
+            p.stream = None;
+            p.link = None;
+        }
+    }
+}
+
+/// The async counterpart of [MpcSession].
+///
+/// Operations take the session's lock for their whole duration, so concurrent calls on one
+/// session run one after the other, in the order they take the lock.
+#[derive(Debug)]
+pub struct AsyncSession {
+    id: usize,
+    n: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    conns: AsyncConnections,
+    state: State,
+}
+
+impl Inner {
+    /// Like the blocking session's: the stats of the phase of `tag`, charged with an operation.
+    fn phase(&mut self, tag: &Tag, started: Instant) -> &mut PhaseStats {
+        let n = self.conns.peers.len();
+        self.conns.stats.phase(&tag.label, n).op(tag.op, started)
+    }
+}
+
+impl AsyncSession {
+    /// Like [MpcSession::try_init_from_file].
+    pub async fn connect_from_file(path: &str, party_id: usize) -> NetResult<Self> {
+        let path = path.to_owned();
+        let conns =
+            AsyncConnections::connect(move |conns| conns.init_from_path(&path, party_id)).await?;
+        Ok(Self::from_parts(conns, State::networked()))
+    }
+
+    /// Like [MpcSession::try_init_secure_from_file].
+    pub async fn connect_secure_from_file(
+        path: &str,
+        party_id: usize,
+        private_key: &[u8],
+    ) -> NetResult<Self> {
+        let (path, private_key) = (path.to_owned(), private_key.to_vec());
+        let conns = AsyncConnections::connect(move |conns| {
+            conns.set_private_key(&private_key);
+            conns.init_from_path(&path, party_id)
+        })
+        .await?;
+        Ok(Self::from_parts(conns, State::networked()))
+    }
+
+    /// Like [MpcSession::try_from_config], for a mesh; relays, transcripts and emulated network
+    /// conditions are not supported yet.
+    pub async fn connect_from_config(config: &NetConfig, party_id: usize) -> NetResult<Self> {
+        if config.session.relay.is_some() {
+            return Err(NetError::Config(
+                "the async transport cannot connect through a relay".to_owned(),
+            ));
+        }
+        if config.session.transcript.is_some() {
+            return Err(NetError::Config(
+                "the async transport cannot record a transcript".to_owned(),
+            ));
+        }
+        if config.session.emulate.is_some() {
+            return Err(NetError::Config(
+                "the async transport cannot emulate network conditions".to_owned(),
+            ));
+        }
+        let state = State::from_config(config);
+        let config = config.clone();
+        let conns =
+            AsyncConnections::connect(move |conns| conns.init_from_config(&config, party_id))
+                .await?;
+        Ok(Self::from_parts(conns, state))
+    }
+
+    fn from_parts(conns: AsyncConnections, state: State) -> Self {
+        Self {
+            id: conns.id,
+            n: conns.peers.len(),
+            inner: Mutex::new(Inner { conns, state }),
+        }
+    }
+
+    /// Like [MpcSession::with_session_id].
+    pub fn with_session_id(mut self, id: &str) -> Self {
+        self.inner.get_mut().state.set_session_id(id);
+        self
+    }
+
+    /// Like [MpcSession::with_security].
+    pub fn with_security(mut self, security: Security) -> Self {
+        self.inner.get_mut().state.set_security(security);
+        self
+    }
+
+    /// Like [MpcSession::with_king].
+    pub fn with_king(mut self, king: King) -> Self {
+        let n = self.n;
+        self.inner.get_mut().state.set_king(king, n);
+        self
+    }
+
+    /// What is my party number (0 to n-1)?
+    pub fn party_id(&self) -> usize {
+        self.id
+    }
+
+    /// How many parties are there?
+    pub fn n_parties(&self) -> usize {
+        self.n
+    }
+
+    /// Get statistics.
+    pub async fn stats(&self) -> Stats {
+        self.inner.lock().await.conns.stats.clone()
+    }
+
+    /// A blocking session over the same connections, picking up where this one is. See the
+    /// [module docs](self) for where it may be used.
+    pub fn into_blocking(self) -> MpcSession {
+        let Inner { conns, state } = self.inner.into_inner();
+        MpcSession::from_parts(Box::new(conns), state, Default::default())
+    }
+
+    /// All parties send bytes to each other.
+    #[track_caller]
+    pub fn try_broadcast_bytes<'a>(
+        &'a self,
+        bytes: &'a [u8],
+    ) -> impl Future<Output = NetResult<Vec<Vec<u8>>>> + 'a {
+        self.broadcast_at(bytes, Location::caller())
+    }
+
+    /// All parties send bytes to the king.
+    #[track_caller]
+    pub fn try_send_bytes_to_king<'a>(
+        &'a self,
+        bytes: &'a [u8],
+    ) -> impl Future<Output = NetResult<Option<Vec<Vec<u8>>>>> + 'a {
+        self.send_to_king_at(bytes, Location::caller())
+    }
+
+    /// All parties recv bytes from the king.
+    /// Provide bytes iff you're the king!
+    #[track_caller]
+    pub fn try_recv_bytes_from_king(
+        &self,
+        bytes: Option<Vec<Vec<u8>>>,
+    ) -> impl Future<Output = NetResult<Vec<u8>>> + '_ {
+        self.recv_from_king_at(bytes, Location::caller())
+    }
+
+    /// Everyone sends bytes to the king, who runs `f` on them and redistributes the result.
+    #[track_caller]
+    pub fn try_king_compute<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        f: impl FnOnce(Vec<Vec<u8>>) -> Vec<Vec<u8>> + 'a,
+    ) -> impl Future<Output = NetResult<Vec<u8>>> + 'a {
+        let at = Location::caller();
+        async move {
+            let king_response = self.send_to_king_at(bytes, at).await?.map(f);
+            self.recv_from_king_at(king_response, at).await
+        }
+    }
+
+    /// Send bytes to party `to` only.
+    pub async fn try_send_bytes_to(&self, to: usize, bytes: &[u8]) -> NetResult<()> {
+        assert_ne!(to, self.id, "cannot send to oneself");
+        let mut inner = self.inner.lock().await;
+        let tag = inner.state.tag(Op::Direct);
+        let frame = tag.seal(bytes);
+        let started = Instant::now();
+        inner.conns.send_to_async(to, &frame).await?;
+        inner.phase(&tag, started).sent(to, frame.len());
+        Ok(())
+    }
+
+    /// Receive the next message that party `from` sent us.
+    #[track_caller]
+    pub fn try_recv_bytes_from(
+        &self,
+        from: usize,
+    ) -> impl Future<Output = NetResult<Vec<u8>>> + '_ {
+        let at = Location::caller();
+        async move {
+            assert_ne!(from, self.id, "cannot receive from oneself");
+            let mut inner = self.inner.lock().await;
+            let tag = inner.state.tag(Op::Direct);
+            let started = Instant::now();
+            let frame = inner.conns.recv_from_async(from).await?;
+            inner.phase(&tag, started).recv(from, frame.len());
+            tag.open(from, frame, at)
+        }
+    }
+
+    /// Swap bytes with party `peer`.
+    #[track_caller]
+    pub fn try_exchange_bytes<'a>(
+        &'a self,
+        peer: usize,
+        bytes: &'a [u8],
+    ) -> impl Future<Output = NetResult<Vec<u8>>> + 'a {
+        let at = Location::caller();
+        async move {
+            assert_ne!(peer, self.id, "cannot exchange with oneself");
+            let mut inner = self.inner.lock().await;
+            let tag = inner.state.tag(Op::Direct);
+            let frame_out = tag.seal(bytes);
+            let started = Instant::now();
+            let frame = inner.conns.exchange_async(peer, &frame_out).await?;
+            inner
+                .phase(&tag, started)
+                .sent(peer, frame_out.len())
+                .recv(peer, frame.len());
+            tag.open(peer, frame, at)
+        }
+    }
+
+    async fn broadcast_at(
+        &self,
+        bytes: &[u8],
+        at: &'static Location<'static>,
+    ) -> NetResult<Vec<Vec<u8>>> {
+        let mut inner = self.inner.lock().await;
+        let tag = inner.state.tag(Op::Broadcast);
+        let frame = tag.seal(bytes);
+        let started = Instant::now();
+        let frames = inner.conns.broadcast_async(&frame).await?;
+        inner
+            .phase(&tag, started)
+            .sent_to_all(self.id, frame.len())
+            .recv_all(self.id, &frames);
+        open_all(&tag, frames, at)
+    }
+
+    async fn send_to_king_at(
+        &self,
+        bytes: &[u8],
+        at: &'static Location<'static>,
+    ) -> NetResult<Option<Vec<Vec<u8>>>> {
+        let mut inner = self.inner.lock().await;
+        let (king, tag) = inner.state.begin_to_king(self.n);
+        let frame = tag.seal(bytes);
+        let started = Instant::now();
+        let frames = inner.conns.send_to_king_async(king, &frame).await?;
+        let phase = inner.phase(&tag, started);
+        match &frames {
+            Some(frames) => phase.recv_all(self.id, frames),
+            None => phase.sent(king, frame.len()),
+        };
+        frames.map(|frames| open_all(&tag, frames, at)).transpose()
+    }
+
+    async fn recv_from_king_at(
+        &self,
+        bytes: Option<Vec<Vec<u8>>>,
+        at: &'static Location<'static>,
+    ) -> NetResult<Vec<u8>> {
+        let mut inner = self.inner.lock().await;
+        let (king, tag) = inner.state.begin_from_king(self.n);
+        let bytes: Option<Vec<Vec<u8>>> =
+            bytes.map(|all| all.iter().map(|b| tag.seal(b)).collect());
+        let sent: Vec<usize> = bytes.iter().flatten().map(Vec::len).collect();
+        let started = Instant::now();
+        let frame = inner.conns.recv_from_king_async(king, bytes).await?;
+        let phase = inner.phase(&tag, started);
+        if king == self.id {
+            for (to, len) in sent
+                .into_iter()
+                .enumerate()
+                .filter(|(to, _)| *to != self.id)
+            {
+                phase.sent(to, len);
+            }
+        } else {
+            phase.recv(king, frame.len());
+        }
+        tag.open(king, frame, at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::DEFAULT_MAX_MESSAGE_LEN, secure, KingRotation, MpcMultiNet, MpcNet};
+    use futures::future::join_all;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// `n` parties connected to each other over loopback, without a host file.
+    async fn local_mesh(n: usize) -> Vec<AsyncSession> {
+        let mut streams: Vec<Vec<Option<TcpStream>>> =
+            (0..n).map(|_| (0..n).map(|_| None).collect()).collect();
+        let pairs: Vec<_> = (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .collect();
+        for (i, j) in pairs {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (dialed, (accepted, _)) = try_join(TcpStream::connect(addr), listener.accept())
+                .await
+                .unwrap();
+            streams[i][j] = Some(dialed);
+            streams[j][i] = Some(accepted);
+        }
+        streams
+            .into_iter()
+            .enumerate()
+            .map(|(id, row)| {
+                let peers = row
+                    .into_iter()
+                    .enumerate()
+                    .map(|(peer, stream)| Peer {
+                        id: peer,
+                        stream,
+                        link: None,
+                        max_len: DEFAULT_MAX_MESSAGE_LEN,
+                    })
+                    .collect();
+                let conns = AsyncConnections {
+                    id,
+                    peers,
+                    stats: Stats::default(),
+                    read_timeout: None,
+                    write_timeout: None,
+                    handle: Handle::current(),
+                };
+                AsyncSession::from_parts(conns, State::default())
+            })
+            .collect()
+    }
+
+    fn host_file(name: &str, port: u16, keys: &[Option<&[u8]>]) -> String {
+        let path = std::env::temp_dir().join(format!("mpc-net-{}-{}", name, std::process::id()));
+        let lines: String = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| match key {
+                Some(key) => format!("127.0.0.1:{} {}\n", port + i as u16, secure::to_hex(key)),
+                None => format!("127.0.0.1:{}\n", port + i as u16),
+            })
+            .collect();
+        std::fs::write(&path, lines).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    /// Broadcast and king-compute the sum of everyone's id.
+    async fn sum_of_ids(session: &AsyncSession) -> NetResult<(Vec<Vec<u8>>, Vec<u8>)> {
+        let id = session.party_id() as u8;
+        let all = session.try_broadcast_bytes(&vec![id; 70_000]).await?;
+        let sum = session
+            .try_king_compute(&[id], |all| {
+                let sum = all.iter().map(|b| b[0]).sum();
+                vec![vec![sum]; all.len()]
+            })
+            .await?;
+        Ok((all, sum))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn secure_broadcast_and_king_compute() {
+        let keys: Vec<_> = (0..3).map(|_| secure::generate_keypair()).collect();
+        let public: Vec<_> = keys.iter().map(|k| Some(&k.public[..])).collect();
+        let path = host_file("async", 17400, &public);
+        let parties: Vec<_> = keys
+            .into_iter()
+            .enumerate()
+            .map(|(id, key)| {
+                let path = path.clone();
+                tokio::spawn(async move {
+                    let session =
+                        AsyncSession::connect_secure_from_file(&path, id, &key.private).await?;
+                    sum_of_ids(&session.with_king(King::fixed(2))).await
+                })
+            })
+            .collect();
+        for party in parties {
+            let (all, sum) = party.await.unwrap().unwrap();
+            for (i, bytes) in all.iter().enumerate() {
+                assert_eq!(bytes, &vec![i as u8; 70_000]);
+            }
+            assert_eq!(sum, vec![3]);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mixes_with_blocking_parties() {
+        let path = host_file("async-mixed", 17410, &[None; 3]);
+        // Party 0 runs natively on the runtime, party 1 through the blocking API on the runtime,
+        // and party 2 is an ordinary blocking party.
+        let native = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                let session = AsyncSession::connect_from_file(&path, 0).await?;
+                sum_of_ids(&session).await.map(|(_, sum)| sum)
+            })
+        };
+        let driven = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                let session = AsyncSession::connect_from_file(&path, 1).await.unwrap();
+                let session = Arc::new(session.into_blocking());
+                tokio::task::spawn_blocking(move || {
+                    session.enter(|| {
+                        MpcMultiNet::broadcast_bytes(&vec![1; 70_000]);
+                        MpcMultiNet::king_compute(&[1], |all| all)
+                    })
+                })
+                .await
+                .unwrap()
+            })
+        };
+        let blocking = std::thread::spawn(move || {
+            let session = MpcSession::init_from_file(&path, 2);
+            session.broadcast_bytes(&vec![2; 70_000]);
+            session.king_compute(&[2], |all| all)
+        });
+        assert_eq!(native.await.unwrap().unwrap(), vec![3]);
+        assert_eq!(driven.await.unwrap(), vec![3]);
+        assert_eq!(blocking.join().unwrap(), vec![3]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn framing() {
+        let sessions = local_mesh(2).await;
+        let results = join_all(sessions.iter().map(|session| async move {
+            let id = session.party_id();
+            // Different lengths from each party, including an empty message.
+            let all = session.try_broadcast_bytes(&vec![id as u8; 3 * id]).await?;
+            let at_king = session.try_send_bytes_to_king(&vec![7; id + 1]).await?;
+            let reply = at_king.as_ref().map(|_| vec![vec![], vec![9; 1000]]);
+            let from_king = session.try_recv_bytes_from_king(reply).await?;
+            let swapped = session
+                .try_exchange_bytes(1 - id, &vec![id as u8; 100_000 * (id + 1)])
+                .await?;
+            let direct = match id {
+                0 => session
+                    .try_send_bytes_to(1, &[5; 10])
+                    .await
+                    .map(|_| vec![])?,
+                _ => session.try_recv_bytes_from(0).await?,
+            };
+            Ok::<_, NetError>((all, at_king, from_king, swapped, direct))
+        }))
+        .await;
+        let results: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+        for (id, r) in results.iter().enumerate() {
+            assert_eq!(r.0, vec![vec![], vec![1; 3]]);
+            assert_eq!(r.3, vec![1 - id as u8; 100_000 * (2 - id)]);
+        }
+        assert_eq!(results[0].1, Some(vec![vec![7], vec![7; 2]]));
+        assert!(results[0].2.is_empty());
+        assert!(results[1].1.is_none());
+        assert_eq!(results[1].2, vec![9; 1000]);
+        assert_eq!(results[1].4, vec![5; 10]);
+        let stats = join_all(sessions.iter().map(AsyncSession::stats)).await;
+        assert_eq!(stats[0].bytes_sent, stats[1].bytes_recv);
+        assert_eq!(stats[1].bytes_sent, stats[0].bytes_recv);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn desync_names_call_site() {
+        let sessions = local_mesh(2).await;
+        let results = join_all(sessions.iter().map(|session| async move {
+            session.try_broadcast_bytes(&[0]).await?;
+            if session.party_id() == 0 {
+                session.try_broadcast_bytes(&[1]).await.map(|_| ())
+            } else {
+                // Party 1 has taken another path.
+                session.try_send_bytes_to_king(&[1]).await?;
+                session.try_recv_bytes_from(0).await.map(|_| ())
+            }
+        }))
+        .await;
+        assert!(matches!(results[1], Err(NetError::Desync { peer: 0, .. })));
+        let err = results[0].as_ref().unwrap_err();
+        assert!(matches!(err, NetError::Desync { peer: 1, .. }));
+        let msg = err.to_string();
+        assert!(msg.contains("broadcast in round 1"), "{}", msg);
+        assert!(msg.contains("send to king in round 1"), "{}", msg);
+        assert!(msg.contains(file!()), "{}", msg);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn desync_on_session_id() {
+        let sessions = local_mesh(2).await;
+        let sessions: Vec<_> = sessions
+            .into_iter()
+            .zip(["a", "b"])
+            .map(|(session, id)| session.with_session_id(id))
+            .collect();
+        let results = join_all(sessions.iter().map(|s| s.try_broadcast_bytes(&[0]))).await;
+        for r in results {
+            assert!(matches!(r, Err(NetError::Desync { .. })));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rotating_king() {
+        let kings = |king: King| async move {
+            let sessions: Vec<_> = local_mesh(3)
+                .await
+                .into_iter()
+                .map(|session| session.with_king(king))
+                .collect();
+            join_all(sessions.iter().map(|session| async move {
+                let id = session.party_id() as u8;
+                let mut kings = vec![];
+                for _ in 0..4 {
+                    // Everyone learns who the king was from the king itself.
+                    let r = session.try_send_bytes_to_king(&[id]).await.unwrap();
+                    let reply = r.map(|all| all.iter().map(|_| vec![id]).collect());
+                    kings.push(session.try_recv_bytes_from_king(reply).await.unwrap()[0]);
+                }
+                kings
+            }))
+            .await
+        };
+        let fixed = kings(King::fixed(2)).await;
+        let per_call = kings(King {
+            first: 1,
+            rotate: KingRotation::PerCall,
+        })
+        .await;
+        let per_round = kings(King {
+            first: 0,
+            rotate: KingRotation::PerRound,
+        })
+        .await;
+        for id in 0..3 {
+            assert_eq!(fixed[id], vec![2, 2, 2, 2]);
+            assert_eq!(per_call[id], vec![1, 2, 0, 1]);
+            // Each iteration takes two rounds.
+            assert_eq!(per_round[id], vec![0, 2, 1, 0]);
+        }
+    }
+}
@@ -0,0 +1,498 @@
+//! Structured host configuration, as an alternative to a plain address file.
+//!
+//! A config lists every party and the parameters of the session, in TOML
+//!
+//! ```toml
+//! [session]
+//! id = "auction-42"
+//! read_timeout_ms = 30000
+//! connect_timeout_ms = 120000
+//! king = 1
+//! rotate_king = "per_call"
+//! security = "production"
+//!
+//! [[parties]]
+//! id = 0
+//! address = "alice.example.org:8000"
+//! listen = "0.0.0.0:8000"
+//! public_key = "4c1f...e2"
+//! private_key_file = "/etc/mpc/0.key"
+//!
+//! [[parties]]
+//! id = 1
+//! address = "bob.example.org:8000"
+//! public_key = "9a07...5d"
+//! private_key_file = "/etc/mpc/1.key"
+//! ```
+//!
+//! or the same structure in JSON. `address` is where the others reach a party and may be a
+//! hostname; `listen` is what the party itself binds (defaults to `address`). Keys are optional,
+//! but if one party has a public key all must (see [crate::secure]). A party only reads its own
+//! `private_key_file`.
+//!
+//! With `relay = "HOST:PORT"` under `[session]`, parties connect only to that relay (see
+//! [crate::relay]), so they need neither an `address` nor a `listen`, and cannot have keys.
+//!
+//! With `transcript = "run/{party}.jsonl"` under `[session]`, each party records everything it
+//! sends and receives to that file, with `{party}` replaced by its id; see [crate::transcript].
+//! Under `[session.emulate]`, a [NetworkModel] slows every link down; see [crate::emulate].
+use std::{
+    fs,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{emulate::NetworkModel, secure, KingRotation, NetError, NetResult, Security};
+
+/// The contents of a host config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetConfig {
+    #[serde(default)]
+    pub session: SessionConfig,
+    pub parties: Vec<PartyConfig>,
+}
+
+/// Parameters shared by all parties of a session.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Names this run of the protocol, so parties of different runs are not mixed up.
+    pub id: Option<String>,
+    /// How long a single read from a peer may block; unbounded if absent.
+    pub read_timeout_ms: Option<u64>,
+    /// How long a single write to a peer may block; unbounded if absent.
+    pub write_timeout_ms: Option<u64>,
+    /// `HOST:PORT` of a [crate::relay::Relay]. If set, every party connects only to the relay,
+    /// which forwards all messages.
+    pub relay: Option<String>,
+    /// Where each party records a transcript of the session (see [crate::transcript]). `{party}`
+    /// is replaced by the party id.
+    pub transcript: Option<String>,
+    /// Network conditions to emulate; see [crate::emulate].
+    pub emulate: Option<NetworkModel>,
+    /// How long connecting to all other parties may take; see [ConnectPolicy].
+    pub connect_timeout_ms: Option<u64>,
+    /// Wait before the first retry of a refused connection.
+    pub connect_backoff_ms: Option<u64>,
+    /// The longest wait between retries.
+    pub connect_max_backoff_ms: Option<u64>,
+    /// The first king; see [crate::King].
+    #[serde(default)]
+    pub king: usize,
+    /// How the king moves on: `never` (the default), `per_call` or `per_round`.
+    #[serde(default)]
+    pub rotate_king: KingRotation,
+    /// `insecure` (the default, logged as a warning when the session starts) or `production`;
+    /// see [Security].
+    #[serde(default)]
+    pub security: Security,
+    /// The longest message a peer may send, in bytes; [DEFAULT_MAX_MESSAGE_LEN] if absent.
+    pub max_message_len: Option<usize>,
+}
+
+/// The longest message a peer may send unless the config says otherwise. A longer length prefix
+/// is treated as corrupt, so a peer cannot make us allocate more than this per message.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 64 << 20;
+
+/// How a party connects to the others.
+///
+/// Each party dials every higher-numbered party, retrying refused connections (the peer may not be
+/// listening yet) after `initial_backoff`, doubling the wait up to `max_backoff`. Connecting to all
+/// parties fails with [NetError::Timeout] if it takes longer than `timeout` in total.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for ConnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// One party of the session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartyConfig {
+    pub id: usize,
+    /// `HOST:PORT` the other parties connect to. Not needed through a relay.
+    pub address: Option<String>,
+    /// `HOST:PORT` this party listens on, if not `address`.
+    pub listen: Option<String>,
+    /// Hex-encoded static public key.
+    pub public_key: Option<String>,
+    /// File holding this party's hex-encoded static private key.
+    pub private_key_file: Option<String>,
+}
+
+/// A party's entry after validation.
+#[derive(Debug, Clone)]
+pub(crate) struct ResolvedParty {
+    pub(crate) address: SocketAddr,
+    pub(crate) listen: SocketAddr,
+    pub(crate) public_key: Option<Vec<u8>>,
+}
+
+impl NetConfig {
+    /// Load a config from a `.toml` or `.json` file and validate it.
+    pub fn load(path: impl AsRef<Path>) -> NetResult<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| NetError::Config(format!("{}: {}", path.display(), e)))?;
+        let config = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml_str(&text),
+            Some("json") => Self::from_json_str(&text),
+            _ => Err(NetError::Config(
+                "config files must end in .toml or .json".to_owned(),
+            )),
+        };
+        config.map_err(|e| match e {
+            NetError::Config(msg) => NetError::Config(format!("{}: {}", path.display(), msg)),
+            e => e,
+        })
+    }
+
+    /// Parse and validate a TOML config.
+    pub fn from_toml_str(s: &str) -> NetResult<Self> {
+        let config: Self = toml::from_str(s).map_err(|e| NetError::Config(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse and validate a JSON config.
+    pub fn from_json_str(s: &str) -> NetResult<Self> {
+        let config: Self = serde_json::from_str(s).map_err(|e| NetError::Config(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the config is usable by every party. Loading does this already.
+    pub fn validate(&self) -> NetResult<()> {
+        if self.parties.is_empty() {
+            return Err(NetError::Config("no parties".to_owned()));
+        }
+        for (i, p) in self.parties.iter().enumerate() {
+            if p.id != i {
+                return Err(NetError::Config(format!(
+                    "parties must be listed by id from 0; entry {} has id {}",
+                    i, p.id
+                )));
+            }
+        }
+        if let Some(relay) = &self.session.relay {
+            resolve_addr("relay", relay)?;
+            if self.parties.iter().any(|p| p.public_key.is_some()) {
+                return Err(NetError::Config(
+                    "public keys cannot be used through a relay".to_owned(),
+                ));
+            }
+        } else {
+            self.validate_mesh()?;
+        }
+        if self.session.king >= self.parties.len() {
+            return Err(NetError::Config(format!(
+                "king {} is not one of the {} parties",
+                self.session.king,
+                self.parties.len()
+            )));
+        }
+        let session = &self.session;
+        if [
+            session.read_timeout_ms,
+            session.write_timeout_ms,
+            session.connect_timeout_ms,
+            session.connect_backoff_ms,
+            session.connect_max_backoff_ms,
+        ]
+        .contains(&Some(0))
+        {
+            return Err(NetError::Config("timeouts must be positive".to_owned()));
+        }
+        if session.max_message_len == Some(0) {
+            return Err(NetError::Config(
+                "max_message_len must be positive".to_owned(),
+            ));
+        }
+        let policy = self.connect_policy();
+        if policy.initial_backoff > policy.max_backoff {
+            return Err(NetError::Config(format!(
+                "connect_backoff_ms ({:?}) exceeds connect_max_backoff_ms ({:?})",
+                policy.initial_backoff, policy.max_backoff
+            )));
+        }
+        if let Some(model) = &session.emulate {
+            model.validate(self.parties.len())?;
+        }
+        Ok(())
+    }
+
+    /// Checks for parties that connect to each other.
+    fn validate_mesh(&self) -> NetResult<()> {
+        let resolved = self.resolve()?;
+        for (i, p) in resolved.iter().enumerate() {
+            if let Some(j) = resolved[..i].iter().position(|q| q.address == p.address) {
+                return Err(NetError::Config(format!(
+                    "parties {} and {} share the address {}",
+                    j, i, p.address
+                )));
+            }
+        }
+        let keyed = resolved.iter().filter(|p| p.public_key.is_some()).count();
+        if keyed != 0 && keyed != resolved.len() {
+            return Err(NetError::Config(format!(
+                "only {} of {} parties have a public key; give all or none",
+                keyed,
+                resolved.len()
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn resolve(&self) -> NetResult<Vec<ResolvedParty>> {
+        self.parties
+            .iter()
+            .map(|p| {
+                let what = |field| format!("party {}: {}", p.id, field);
+                let address = p
+                    .address
+                    .as_deref()
+                    .ok_or_else(|| NetError::Config(format!("party {} has no address", p.id)))?;
+                let address = resolve_addr(&what("address"), address)?;
+                let listen = match &p.listen {
+                    Some(l) => resolve_addr(&what("listen"), l)?,
+                    None => address,
+                };
+                let public_key = p
+                    .public_key
+                    .as_deref()
+                    .map(|k| {
+                        let k = secure::from_hex(k)
+                            .map_err(|e| NetError::Config(format!("party {}: {}", p.id, e)))?;
+                        if k.len() != 32 {
+                            return Err(NetError::Config(format!(
+                                "party {}: public key must be 32 bytes, not {}",
+                                p.id,
+                                k.len()
+                            )));
+                        }
+                        Ok(k)
+                    })
+                    .transpose()?;
+                Ok(ResolvedParty {
+                    address,
+                    listen,
+                    public_key,
+                })
+            })
+            .collect()
+    }
+
+    /// Read the private key of party `party_id`, if the config has one for it.
+    pub(crate) fn private_key(&self, party_id: usize) -> NetResult<Option<Vec<u8>>> {
+        let party = self.parties.get(party_id).ok_or_else(|| {
+            NetError::Config(format!(
+                "party {} does not exist; the config lists {} parties",
+                party_id,
+                self.parties.len()
+            ))
+        })?;
+        let Some(path) = &party.private_key_file else {
+            return if party.public_key.is_some() {
+                Err(NetError::Config(format!(
+                    "party {} has a public key but no private_key_file",
+                    party_id
+                )))
+            } else {
+                Ok(None)
+            };
+        };
+        let hex = fs::read_to_string(path)
+            .map_err(|e| NetError::Config(format!("party {}: {}: {}", party_id, path, e)))?;
+        secure::from_hex(&hex).map(Some)
+    }
+
+    pub(crate) fn read_timeout(&self) -> Option<Duration> {
+        self.session.read_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub(crate) fn write_timeout(&self) -> Option<Duration> {
+        self.session.write_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// The session's connect policy, with defaults for what the config leaves out.
+    pub fn connect_policy(&self) -> ConnectPolicy {
+        let default = ConnectPolicy::default();
+        let ms = |ms: Option<u64>, default| ms.map_or(default, Duration::from_millis);
+        ConnectPolicy {
+            initial_backoff: ms(self.session.connect_backoff_ms, default.initial_backoff),
+            max_backoff: ms(self.session.connect_max_backoff_ms, default.max_backoff),
+            timeout: ms(self.session.connect_timeout_ms, default.timeout),
+        }
+    }
+
+    /// The longest message a peer may send.
+    pub fn max_message_len(&self) -> usize {
+        self.session
+            .max_message_len
+            .unwrap_or(DEFAULT_MAX_MESSAGE_LEN)
+    }
+
+    /// Where party `id` records its transcript, if anywhere.
+    pub fn transcript_path(&self, id: usize) -> Option<PathBuf> {
+        let path = self.session.transcript.as_ref()?;
+        Some(PathBuf::from(path.replace("{party}", &id.to_string())))
+    }
+}
+
+/// Resolve the address `addr` of `what`, e.g. "party 1: listen".
+fn resolve_addr(what: &str, addr: &str) -> NetResult<SocketAddr> {
+    addr.to_socket_addrs()
+        .map_err(|e| NetError::Config(format!("{} {}: {}", what, addr, e)))?
+        .next()
+        .ok_or_else(|| NetError::Config(format!("{} {} does not resolve", what, addr)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulate::DelayDistribution;
+
+    fn error(s: &str) -> String {
+        match NetConfig::from_toml_str(s) {
+            Err(NetError::Config(msg)) => msg,
+            r => panic!("expected a config error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn toml_and_json_agree() {
+        let toml = r#"
+            [session]
+            id = "test"
+            read_timeout_ms = 500
+            transcript = "run/{party}.jsonl"
+            security = "production"
+
+            [[parties]]
+            id = 0
+            address = "localhost:8000"
+            listen = "0.0.0.0:8000"
+
+            [[parties]]
+            id = 1
+            address = "127.0.0.1:8001"
+        "#;
+        let json = r#"{
+            "session": {
+                "id": "test",
+                "read_timeout_ms": 500,
+                "transcript": "run/{party}.jsonl",
+                "security": "production"
+            },
+            "parties": [
+                { "id": 0, "address": "localhost:8000", "listen": "0.0.0.0:8000" },
+                { "id": 1, "address": "127.0.0.1:8001" }
+            ]
+        }"#;
+        let config = NetConfig::from_toml_str(toml).unwrap();
+        assert_eq!(config, NetConfig::from_json_str(json).unwrap());
+        assert_eq!(config.read_timeout(), Some(Duration::from_millis(500)));
+        assert_eq!(config.session.security, Security::Production);
+        assert_eq!(config.max_message_len(), DEFAULT_MAX_MESSAGE_LEN);
+        assert_eq!(
+            config.transcript_path(1),
+            Some(PathBuf::from("run/1.jsonl"))
+        );
+        let resolved = config.resolve().unwrap();
+        assert_eq!(resolved[0].listen, "0.0.0.0:8000".parse().unwrap());
+        assert_eq!(resolved[1].listen, resolved[1].address);
+    }
+
+    #[test]
+    fn emulated_links() {
+        let config = NetConfig::from_toml_str(
+            r#"
+            [session.emulate]
+            latency_us = 40000
+            distribution = "normal"
+            jitter_us = 5000
+
+            [[session.emulate.links]]
+            between = [1, 0]
+            latency_us = 150000
+            bytes_per_sec = 1000
+
+            [[parties]]
+            id = 0
+            address = "127.0.0.1:8000"
+
+            [[parties]]
+            id = 1
+            address = "127.0.0.1:8001"
+        "#,
+        )
+        .unwrap();
+        let model = config.session.emulate.unwrap();
+        assert_eq!(model.link(0, 1).latency_us, 150_000);
+        assert_eq!(model.link(0, 1).bytes_per_sec, Some(1000));
+        assert_eq!(model.link(1, 1).latency_us, 40_000);
+        assert_eq!(model.link(1, 1).distribution, DelayDistribution::Normal);
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        let party = |id: usize, port: u16| {
+            format!(
+                "[[parties]]\nid = {}\naddress = \"127.0.0.1:{}\"\n",
+                id, port
+            )
+        };
+        assert!(error("").contains("parties"));
+        assert!(
+            error("[[parties]]\nid = 0\naddress = \"127.0.0.1:1\"\nport = 3\n")
+                .contains("unknown field")
+        );
+        assert!(error(&(party(1, 8000) + &party(0, 8001))).contains("by id"));
+        assert!(error(&(party(0, 8000) + &party(1, 8000))).contains("share the address"));
+        assert!(error("[[parties]]\nid = 0\naddress = \"nowhere\"\n").contains("party 0"));
+        let keyed = format!("public_key = \"{}\"\n", "ab".repeat(32));
+        assert!(error(&(party(0, 8000) + &keyed + &party(1, 8001))).contains("all or none"));
+        assert!(error(&(party(0, 8000) + "public_key = \"abc\"\n")).contains("hex"));
+        assert!(
+            error(&("[session]\nread_timeout_ms = 0\n".to_owned() + &party(0, 8000)))
+                .contains("positive")
+        );
+        assert!(
+            error(&("[session]\nmax_message_len = 0\n".to_owned() + &party(0, 8000)))
+                .contains("max_message_len")
+        );
+        assert!(
+            error(&("[session]\nconnect_backoff_ms = 2000\n".to_owned() + &party(0, 8000)))
+                .contains("exceeds")
+        );
+        let relayed = "[session]\nrelay = \"127.0.0.1:9000\"\n[[parties]]\nid = 0\n";
+        NetConfig::from_toml_str(relayed).unwrap();
+        assert!(error(&(relayed.to_owned() + &keyed)).contains("through a relay"));
+        assert!(error("[[parties]]\nid = 0\n").contains("no address"));
+        assert!(error(&("[session]\nking = 1\n".to_owned() + &party(0, 8000))).contains("king 1"));
+        let emulated = "[session.emulate]\nlatency_us = 1000\n[[session.emulate.links]]\n";
+        assert!(
+            error(&(emulated.to_owned() + "between = [0, 1]\n" + &party(0, 8000)))
+                .contains("does not exist")
+        );
+        assert!(
+            error(&("[session.emulate]\nbytes_per_sec = 0\n".to_owned() + &party(0, 8000)))
+                .contains("bandwidth")
+        );
+    }
+}
@@ -0,0 +1,474 @@
+//! Emulated network conditions, to see how a protocol would fare on a slower network than the one
+//! it runs on.
+//!
+//! An [Emulated] transport wraps another and keeps an emulated clock per party. Every frame is
+//! stamped with the time it would arrive given the [LinkModel] of its link: it waits for the
+//! link to finish sending earlier frames, takes `len / bytes_per_sec` to send, and then `latency`
+//! plus a random delay drawn from `distribution` to arrive. A receiver's clock jumps ahead to the
+//! arrival time of what it receives, if that is later. Local computation between network
+//! operations advances the clock by the real time it takes; waiting for peers on the real network
+//! does not.
+//!
+//! The emulated time is reported as [crate::Stats::simulated_time], so a round-heavy and a
+//! bandwidth-heavy protocol can be compared on one machine. With [NetworkModel::sleep] set, a
+//! party also really waits until each frame would arrive.
+//!
+//! In a host config (see [crate::config]) a model looks like
+//!
+//! ```toml
+//! [session.emulate]
+//! latency_us = 40000
+//! jitter_us = 5000
+//! distribution = "normal"
+//! bytes_per_sec = 12500000
+//! seed = 7
+//!
+//! [[session.emulate.links]]
+//! between = [0, 2]
+//! latency_us = 150000
+//! ```
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use ark_std::rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{NetError, NetResult, Stats, Transport};
+
+/// How a random extra delay of scale `jitter_us` is drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DelayDistribution {
+    /// Uniform between zero and `jitter_us`.
+    #[default]
+    Uniform,
+    /// The absolute value of a normal variable with standard deviation `jitter_us`.
+    Normal,
+    /// Exponential with mean `jitter_us`, giving the occasional long delay.
+    Exponential,
+}
+
+/// The conditions on one link, in both directions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkModel {
+    /// One-way delay of every frame, in microseconds.
+    #[serde(default)]
+    pub latency_us: u64,
+    /// Scale of the random delay added to `latency_us`.
+    #[serde(default)]
+    pub jitter_us: u64,
+    #[serde(default)]
+    pub distribution: DelayDistribution,
+    /// How fast a frame is sent; unlimited if absent.
+    pub bytes_per_sec: Option<u64>,
+}
+
+/// A [LinkModel] for the link between two particular parties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkOverride {
+    pub between: [usize; 2],
+    #[serde(flatten)]
+    pub link: LinkModel,
+}
+
+/// The conditions on every link of a session.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkModel {
+    /// The model of every link not in `links`.
+    #[serde(flatten)]
+    pub link: LinkModel,
+    #[serde(default)]
+    pub links: Vec<LinkOverride>,
+    /// Seeds the random delays; each party draws its own from it.
+    #[serde(default)]
+    pub seed: u64,
+    /// Really wait until frames would arrive, rather than only advancing the emulated clock.
+    #[serde(default)]
+    pub sleep: bool,
+}
+
+impl NetworkModel {
+    /// The same conditions on every link.
+    pub fn uniform(link: LinkModel) -> Self {
+        Self {
+            link,
+            ..Default::default()
+        }
+    }
+
+    /// The model of the link between parties `a` and `b`.
+    pub fn link(&self, a: usize, b: usize) -> &LinkModel {
+        self.links
+            .iter()
+            .find(|l| l.between == [a, b] || l.between == [b, a])
+            .map_or(&self.link, |l| &l.link)
+    }
+
+    /// Check the model makes sense for `n` parties.
+    pub(crate) fn validate(&self, n: usize) -> NetResult<()> {
+        let mut links = std::iter::once(&self.link).chain(self.links.iter().map(|l| &l.link));
+        if links.any(|l| l.bytes_per_sec == Some(0)) {
+            return Err(NetError::Config(
+                "emulated bandwidth must be positive".to_owned(),
+            ));
+        }
+        match self
+            .links
+            .iter()
+            .find(|l| l.between.iter().any(|&id| id >= n))
+        {
+            Some(l) => Err(NetError::Config(format!(
+                "emulated link between {} and {} names a party that does not exist",
+                l.between[0], l.between[1]
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Length of the arrival time stamped on a frame.
+const STAMP_LEN: usize = 8;
+
+/// A transport that delays frames as the links of a [NetworkModel] would, on an emulated clock.
+#[derive(Debug)]
+pub struct Emulated {
+    inner: Box<dyn Transport>,
+    model: NetworkModel,
+    rng: StdRng,
+    /// The emulated time at this party.
+    clock: Duration,
+    /// `link_free[j]` is when our link to party `j` finishes sending what it has been given.
+    link_free: Vec<Duration>,
+    /// The real time the clock was last brought up to date.
+    synced: Instant,
+}
+
+impl Emulated {
+    /// Emulate `model` on top of `inner`. All parties must use the same model.
+    pub fn new(inner: Box<dyn Transport>, model: NetworkModel) -> Self {
+        let id = inner.party_id() as u64;
+        Self {
+            link_free: vec![Duration::ZERO; inner.n_parties()],
+            rng: StdRng::seed_from_u64(model.seed ^ id.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+            clock: Duration::ZERO,
+            synced: Instant::now(),
+            inner,
+            model,
+        }
+    }
+
+    /// Advance the clock by `d`.
+    fn advance(&mut self, d: Duration) {
+        self.clock += d;
+        self.inner.stats().simulated_time += d;
+    }
+
+    /// Count the real time since the last operation as local computation.
+    fn compute(&mut self) {
+        let now = Instant::now();
+        self.advance(now - self.synced);
+        self.synced = now;
+    }
+
+    /// Stop counting real time, while we wait on the real network.
+    fn pause(&mut self) {
+        self.synced = Instant::now();
+    }
+
+    /// Bring the clock up to `arrival`, if it is behind.
+    fn wait_until(&mut self, arrival: Duration) {
+        if let Some(wait) = arrival.checked_sub(self.clock).filter(|w| !w.is_zero()) {
+            if self.model.sleep {
+                thread::sleep(wait);
+            }
+            self.advance(wait);
+        }
+        self.pause();
+    }
+
+    /// When a `len`-byte frame we send `to` now arrives.
+    fn arrival(&mut self, to: usize, len: usize) -> Duration {
+        let link = *self.model.link(self.inner.party_id(), to);
+        let start = self.clock.max(self.link_free[to]);
+        let sending = link.bytes_per_sec.map_or(Duration::ZERO, |bps| {
+            Duration::from_secs_f64(len as f64 / bps as f64)
+        });
+        self.link_free[to] = start + sending;
+        let jitter = self.delay(&link);
+        start + sending + Duration::from_micros(link.latency_us) + jitter
+    }
+
+    fn delay(&mut self, link: &LinkModel) -> Duration {
+        if link.jitter_us == 0 {
+            return Duration::ZERO;
+        }
+        let scale = link.jitter_us as f64;
+        let us = match link.distribution {
+            DelayDistribution::Uniform => self.rng.gen_range(0.0..=scale),
+            DelayDistribution::Normal => {
+                // Box-Muller
+                let (u, v): (f64, f64) = (1.0 - self.rng.gen::<f64>(), self.rng.gen());
+                scale * ((-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()).abs()
+            }
+            DelayDistribution::Exponential => -scale * (1.0 - self.rng.gen::<f64>()).ln(),
+        };
+        Duration::from_secs_f64(us / 1e6)
+    }
+
+    /// `bytes` with the time it arrives at `to` in front.
+    fn stamp(&mut self, to: usize, bytes: &[u8]) -> Vec<u8> {
+        let arrival = self.arrival(to, bytes.len());
+        [&(arrival.as_nanos() as u64).to_le_bytes()[..], bytes].concat()
+    }
+
+    /// Split a frame from `peer` into its `slot`th arrival time (of `slots`) and the payload.
+    fn unstamp(
+        peer: usize,
+        mut frame: Vec<u8>,
+        slot: usize,
+        slots: usize,
+    ) -> NetResult<(Duration, Vec<u8>)> {
+        if frame.len() < slots * STAMP_LEN {
+            return Err(NetError::MalformedFrame {
+                peer,
+                reason: "missing emulated arrival time".to_owned(),
+            });
+        }
+        let at = &frame[slot * STAMP_LEN..(slot + 1) * STAMP_LEN];
+        let arrival = Duration::from_nanos(u64::from_le_bytes(at.try_into().unwrap()));
+        frame.drain(..slots * STAMP_LEN);
+        Ok((arrival, frame))
+    }
+
+    /// Payloads of frames with one arrival time each, waiting for the last to arrive.
+    fn receive_all(&mut self, frames: Vec<Vec<u8>>, own: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let id = self.inner.party_id();
+        let mut last = Duration::ZERO;
+        let payloads = frames
+            .into_iter()
+            .enumerate()
+            .map(|(peer, frame)| {
+                if peer == id {
+                    return Ok(own.to_vec());
+                }
+                let (arrival, payload) = Self::unstamp(peer, frame, 0, 1)?;
+                last = last.max(arrival);
+                Ok(payload)
+            })
+            .collect::<NetResult<Vec<_>>>()?;
+        self.wait_until(last);
+        Ok(payloads)
+    }
+}
+
+impl Transport for Emulated {
+    fn party_id(&self) -> usize {
+        self.inner.party_id()
+    }
+    fn n_parties(&self) -> usize {
+        self.inner.n_parties()
+    }
+    fn is_init(&self) -> bool {
+        self.inner.is_init()
+    }
+    fn uninit(&mut self) {
+        self.inner.uninit()
+    }
+    fn stats(&mut self) -> &mut Stats {
+        self.compute();
+        self.inner.stats()
+    }
+    fn set_timeouts(&mut self, read: Option<Duration>, write: Option<Duration>) -> NetResult<()> {
+        self.inner.set_timeouts(read, write)
+    }
+    /// Every peer gets the same frame, so it carries an arrival time for each of them.
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        self.compute();
+        let (id, n) = (self.party_id(), self.n_parties());
+        let mut frame = Vec::with_capacity(n * STAMP_LEN + bytes_out.len());
+        for to in 0..n {
+            let arrival = if to == id {
+                Duration::ZERO
+            } else {
+                self.arrival(to, bytes_out.len())
+            };
+            frame.extend_from_slice(&(arrival.as_nanos() as u64).to_le_bytes());
+        }
+        frame.extend_from_slice(bytes_out);
+        let frames = self.inner.broadcast(&frame)?;
+        let mut last = Duration::ZERO;
+        let payloads = frames
+            .into_iter()
+            .enumerate()
+            .map(|(peer, frame)| {
+                if peer == id {
+                    return Ok(bytes_out.to_vec());
+                }
+                let (arrival, payload) = Self::unstamp(peer, frame, id, n)?;
+                last = last.max(arrival);
+                Ok(payload)
+            })
+            .collect::<NetResult<Vec<_>>>()?;
+        self.wait_until(last);
+        Ok(payloads)
+    }
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        self.compute();
+        if king == self.party_id() {
+            let frames = self.inner.send_to_king(king, bytes_out)?;
+            let frames = frames.expect("the king receives from everyone");
+            self.receive_all(frames, bytes_out).map(Some)
+        } else {
+            let frame = self.stamp(king, bytes_out);
+            let none = self.inner.send_to_king(king, &frame)?;
+            self.pause();
+            Ok(none)
+        }
+    }
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        self.compute();
+        let id = self.party_id();
+        let frames = bytes_out.map(|all| {
+            all.iter()
+                .enumerate()
+                .map(|(to, bytes)| {
+                    if to == id {
+                        bytes.clone()
+                    } else {
+                        self.stamp(to, bytes)
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+        let frame = self.inner.recv_from_king(king, frames)?;
+        if king == id {
+            self.pause();
+            return Ok(frame);
+        }
+        let (arrival, payload) = Self::unstamp(king, frame, 0, 1)?;
+        self.wait_until(arrival);
+        Ok(payload)
+    }
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.compute();
+        let frame = self.stamp(to, bytes_out);
+        self.inner.send_to(to, &frame)?;
+        self.pause();
+        Ok(())
+    }
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        self.compute();
+        let frame = self.inner.recv_from(from)?;
+        let (arrival, payload) = Self::unstamp(from, frame, 0, 1)?;
+        self.wait_until(arrival);
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{local::LocalTransport, MpcMultiNet as Net, MpcNet, MpcSession};
+
+    /// Run `f` as each of `n` parties over a [LocalTransport] mesh emulating `model`, returning
+    /// each party's emulated time.
+    fn emulate(n: usize, model: NetworkModel, f: impl Fn(usize) + Sync) -> Vec<Duration> {
+        let (f, model) = (&f, &model);
+        std::thread::scope(|s| {
+            let handles: Vec<_> = LocalTransport::mesh(n)
+                .into_iter()
+                .map(|t| {
+                    let session = Arc::new(MpcSession::from_transport(t).emulate(model.clone()));
+                    s.spawn(move || {
+                        session.enter(|| f(session.party_id()));
+                        session.stats().simulated_time
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn rounds_cost_latency() {
+        let model = NetworkModel::uniform(LinkModel {
+            latency_us: 50_000,
+            ..Default::default()
+        });
+        let times = emulate(3, model, |_| {
+            for _ in 0..4 {
+                Net::broadcast_bytes(&[0; 16]);
+            }
+        });
+        // Four rounds of 50ms each, plus a little local work.
+        assert!(
+            times.iter().all(|t| *t >= ms(200) && *t < ms(250)),
+            "{:?}",
+            times
+        );
+    }
+
+    #[test]
+    fn bandwidth_and_slow_links() {
+        let model = NetworkModel {
+            link: LinkModel {
+                latency_us: 1_000,
+                bytes_per_sec: Some(1_000_000),
+                ..Default::default()
+            },
+            links: vec![LinkOverride {
+                between: [0, 2],
+                link: LinkModel {
+                    latency_us: 100_000,
+                    ..Default::default()
+                },
+            }],
+            ..Default::default()
+        };
+        let times = emulate(3, model, |id| {
+            // 100kB at 1MB/s takes 100ms, on top of the 1ms latency. The link to party 2 has
+            // no bandwidth cap, but 100ms of latency.
+            if id == 0 {
+                Net::send_bytes_to(1, &vec![0; 100_000]);
+                Net::send_bytes_to(2, &[0]);
+            } else {
+                Net::recv_bytes_from(0);
+            }
+        });
+        assert!(times[1] >= ms(101) && times[1] < ms(150), "{:?}", times);
+        assert!(times[2] >= ms(100) && times[2] < ms(150), "{:?}", times);
+    }
+
+    #[test]
+    fn jitter_is_bounded_and_seeded() {
+        let model = NetworkModel {
+            link: LinkModel {
+                latency_us: 10_000,
+                jitter_us: 10_000,
+                ..Default::default()
+            },
+            seed: 1,
+            ..Default::default()
+        };
+        let times = emulate(2, model, |_| {
+            Net::broadcast_bytes(&[1]);
+        });
+        assert!(
+            times.iter().all(|t| *t >= ms(10) && *t < ms(40)),
+            "{:?}",
+            times
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::{fmt, io};
+
+/// Errors raised by the network layer, and by the checks protocols run on what their peers send.
+#[derive(Debug)]
+pub enum NetError {
+    /// The peer closed its connection, or crashed.
+    Disconnected { peer: usize },
+    /// The peer did not answer within the configured deadline.
+    Timeout { peer: usize },
+    /// The peer sent bytes that do not form a valid message.
+    MalformedFrame { peer: usize, reason: String },
+    /// A peer identified itself as a different party than expected.
+    WrongPartyId { expected: usize, got: usize },
+    /// A peer failed to prove its identity, or a message failed its integrity check.
+    Authentication { peer: usize, reason: String },
+    /// A peer's message belongs to a different point of the protocol than ours: the parties
+    /// have taken different code paths.
+    Desync {
+        peer: usize,
+        ours: String,
+        theirs: String,
+    },
+    /// Party `witness` saw a different message from party `sender` than we did in a consistent
+    /// broadcast. Either of the two may be the one cheating.
+    Equivocation { sender: usize, witness: usize },
+    /// The value party `peer` opened does not match the commitment it sent before.
+    BadOpening { peer: usize },
+    /// The MAC check of the value at `index` among those checked together, in the order they
+    /// were opened, failed: some party lied about its share, but the check cannot tell which.
+    MacCheck { index: usize },
+    /// Any other I/O failure while talking to a peer.
+    Io { peer: usize, source: io::Error },
+    /// The host configuration could not be used.
+    Config(String),
+    /// The relay of a star topology (see [crate::relay]) could not be reached, or failed.
+    Relay(String),
+    /// A transcript (see [crate::transcript]) could not be written or read, or a replayed party
+    /// strayed from it.
+    Transcript(String),
+}
+
+pub type NetResult<T> = Result<T, NetError>;
+
+impl NetError {
+    /// The party that surely cheated, if this error proves one did.
+    pub fn cheater(&self) -> Option<usize> {
+        match self {
+            NetError::BadOpening { peer } => Some(*peer),
+            _ => None,
+        }
+    }
+
+    /// Classify an I/O error on the link to `peer`.
+    pub(crate) fn io(peer: usize, e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => NetError::Disconnected { peer },
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => NetError::Timeout { peer },
+            _ => NetError::Io { peer, source: e },
+        }
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::Disconnected { peer } => write!(f, "party {} disconnected", peer),
+            NetError::Timeout { peer } => write!(f, "timed out waiting for party {}", peer),
+            NetError::MalformedFrame { peer, reason } => {
+                write!(f, "malformed message from party {}: {}", peer, reason)
+            }
+            NetError::WrongPartyId { expected, got } => {
+                write!(
+                    f,
+                    "expected party {}, but talked to party {}",
+                    expected, got
+                )
+            }
+            NetError::Authentication { peer, reason } => {
+                write!(f, "could not authenticate party {}: {}", peer, reason)
+            }
+            NetError::Desync { peer, ours, theirs } => write!(
+                f,
+                "out of step with party {}: expected {}, but got {}",
+                peer, ours, theirs
+            ),
+            NetError::Equivocation { sender, witness } => write!(
+                f,
+                "party {} sent party {} a different message than us",
+                sender, witness
+            ),
+            NetError::BadOpening { peer } => {
+                write!(f, "party {} opened a value it had not committed to", peer)
+            }
+            NetError::MacCheck { index } => {
+                write!(f, "MAC check failed on opened value {}", index)
+            }
+            NetError::Io { peer, source } => write!(f, "I/O error with party {}: {}", peer, source),
+            NetError::Config(msg) => write!(f, "bad host configuration: {}", msg),
+            NetError::Relay(msg) => write!(f, "relay failed: {}", msg),
+            NetError::Transcript(msg) => write!(f, "transcript: {}", msg),
+        }
+    }
+}
+
+/// I/O errors cannot be cloned, so a clone of [NetError::Io] carries a copy of the kind and
+/// message instead.
+impl Clone for NetError {
+    fn clone(&self) -> Self {
+        match self {
+            NetError::Disconnected { peer } => NetError::Disconnected { peer: *peer },
+            NetError::Timeout { peer } => NetError::Timeout { peer: *peer },
+            NetError::MalformedFrame { peer, reason } => NetError::MalformedFrame {
+                peer: *peer,
+                reason: reason.clone(),
+            },
+            NetError::WrongPartyId { expected, got } => NetError::WrongPartyId {
+                expected: *expected,
+                got: *got,
+            },
+            NetError::Authentication { peer, reason } => NetError::Authentication {
+                peer: *peer,
+                reason: reason.clone(),
+            },
+            NetError::Desync { peer, ours, theirs } => NetError::Desync {
+                peer: *peer,
+                ours: ours.clone(),
+                theirs: theirs.clone(),
+            },
+            NetError::Equivocation { sender, witness } => NetError::Equivocation {
+                sender: *sender,
+                witness: *witness,
+            },
+            NetError::BadOpening { peer } => NetError::BadOpening { peer: *peer },
+            NetError::MacCheck { index } => NetError::MacCheck { index: *index },
+            NetError::Io { peer, source } => NetError::Io {
+                peer: *peer,
+                source: io::Error::new(source.kind(), source.to_string()),
+            },
+            NetError::Config(msg) => NetError::Config(msg.clone()),
+            NetError::Relay(msg) => NetError::Relay(msg.clone()),
+            NetError::Transcript(msg) => NetError::Transcript(msg.clone()),
+        }
+    }
+}
+
+impl std::error::Error for NetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NetError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,270 +1,231 @@
 use std::{
-    fs::File,
-    io::{BufRead, BufReader, Read, Write},
-    net::{SocketAddr, TcpListener, TcpStream},
-    sync::Mutex,
+    any::Any,
+    collections::BTreeMap,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
-use ark_std::{end_timer, start_timer};
 use lazy_static::lazy_static;
-use log::debug;
-use rayon::prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
 
-lazy_static! {
-    static ref CONNECTIONS: Mutex<Connections> = Mutex::new(Connections::default());
-}
-
-/// Macro for locking the FieldChannel singleton in the current scope.
-macro_rules! get_ch {
-    () => {
-        CONNECTIONS.lock().expect("Poisoned FieldChannel")
-    };
-}
+#[cfg(feature = "tokio")]
+pub mod async_net;
+#[cfg(feature = "tokio")]
+pub use async_net::AsyncSession;
+pub mod config;
+pub use config::{ConnectPolicy, NetConfig};
+pub mod emulate;
+pub mod error;
+pub use error::{NetError, NetResult};
+pub mod local;
+mod multi;
+pub mod mux;
+pub mod relay;
+pub mod secure;
+pub mod session;
+pub use session::{King, KingRotation, MpcSession, Security};
+mod tag;
+pub mod transcript;
 
-#[derive(Debug)]
-struct Peer {
-    id: usize,
-    addr: SocketAddr,
-    stream: Option<TcpStream>,
+lazy_static! {
+    /// The session used by [MpcMultiNet] on threads that have not entered one of their own.
+    static ref DEFAULT_SESSION: RwLock<Arc<MpcSession>> =
+        RwLock::new(Arc::new(MpcSession::default()));
 }
 
-impl Default for Peer {
-    fn default() -> Self {
-        Self {
-            id: 0,
-            addr: "127.0.0.1:8000".parse().unwrap(),
-            stream: None,
-        }
-    }
-}
+/// Whether [MpcMultiNet] warned about a thread that fell back to [DEFAULT_SESSION] while other
+/// threads had entered sessions.
+static WARNED_UNBOUND: AtomicBool = AtomicBool::new(false);
 
-#[derive(Default, Debug, Clone)]
+/// Communication statistics of a session.
+///
+/// The counters at the top are kept by the transport, and include its framing. [Stats::phases]
+/// breaks traffic down by the label of [MpcSession::with_label] in force (the empty label outside
+/// any), counting the tagged messages the session sends and receives.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Stats {
     pub bytes_sent: usize,
     pub bytes_recv: usize,
     pub broadcasts: usize,
     pub to_king: usize,
     pub from_king: usize,
+    /// Time passed on the emulated clock of an [emulate::Emulated] transport; zero otherwise.
+    #[serde(rename = "simulated_secs", with = "secs")]
+    pub simulated_time: Duration,
+    #[serde(default)]
+    pub phases: BTreeMap<String, PhaseStats>,
 }
 
-#[derive(Default, Debug)]
-struct Connections {
-    id: usize,
-    peers: Vec<Peer>,
-    stats: Stats,
+/// The traffic of one protocol phase; see [Stats::phases].
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseStats {
+    /// Collective operations: broadcasts, sends to the king and replies from it.
+    pub rounds: usize,
+    /// Point-to-point messages sent or received, an exchange counting once.
+    pub messages: usize,
+    /// `bytes_sent[j]` bytes went to party `j`.
+    pub bytes_sent: Vec<usize>,
+    /// `bytes_recv[j]` bytes came from party `j`.
+    pub bytes_recv: Vec<usize>,
+    /// Time spent inside network operations: waiting for peers, and moving the bytes.
+    #[serde(rename = "blocked_secs", with = "secs")]
+    pub blocked: Duration,
 }
 
-impl Connections {
-    /// Given a path and the `id` of oneself, initialize the structure
-    fn init_from_path(&mut self, path: &str, id: usize) {
-        let f = BufReader::new(File::open(path).expect("host configuration path"));
-        let mut peer_id = 0;
-        for line in f.lines() {
-            let line = line.unwrap();
-            let trimmed = line.trim();
-            if !trimmed.is_empty() {
-                let addr: SocketAddr = trimmed
-                    .parse()
-                    .unwrap_or_else(|e| panic!("bad socket address: {}:\n{}", trimmed, e));
-                let peer = Peer {
-                    id: peer_id,
-                    addr,
-                    stream: None,
-                };
-                self.peers.push(peer);
-                peer_id += 1;
-            }
-        }
-        assert!(id < self.peers.len());
-        self.id = id;
-    }
-    fn connect_to_all(&mut self) {
-        let timer = start_timer!(|| "Connecting");
-        let n = self.peers.len();
-        for from_id in 0..n {
-            for to_id in (from_id + 1)..n {
-                debug!("{} to {}", from_id, to_id);
-                if self.id == from_id {
-                    let to_addr = self.peers[to_id].addr;
-                    debug!("Contacting {}", to_id);
-                    let stream = loop {
-                        let mut ms_waited = 0;
-                        match TcpStream::connect(to_addr) {
-                            Ok(s) => break s,
-                            Err(e) => match e.kind() {
-                                std::io::ErrorKind::ConnectionRefused
-                                | std::io::ErrorKind::ConnectionReset => {
-                                    ms_waited += 10;
-                                    std::thread::sleep(std::time::Duration::from_millis(10));
-                                    if ms_waited % 3_000 == 0 {
-                                        debug!("Still waiting");
-                                    } else if ms_waited > 30_000 {
-                                        panic!("Could not find peer in 30s");
-                                    }
-                                }
-                                _ => {
-                                    panic!("Error during FieldChannel::new: {}", e);
-                                }
-                            },
-                        }
-                    };
-                    stream.set_nodelay(true).unwrap();
-                    self.peers[to_id].stream = Some(stream);
-                } else if self.id == to_id {
-                    debug!("Awaiting {}", from_id);
-                    let listener = TcpListener::bind(self.peers[self.id].addr).unwrap();
-                    let (stream, _addr) = listener.accept().unwrap();
-                    stream.set_nodelay(true).unwrap();
-                    self.peers[from_id].stream = Some(stream);
-                }
-            }
-            // Sender for next round waits for note from this sender to prevent race on receipt.
-            if from_id + 1 < n {
-                if self.id == from_id {
-                    self.peers[self.id + 1]
-                        .stream
-                        .as_mut()
-                        .unwrap()
-                        .write_all(&[0u8])
-                        .unwrap();
-                } else if self.id == from_id + 1 {
-                    self.peers[self.id - 1]
-                        .stream
-                        .as_mut()
-                        .unwrap()
-                        .read_exact(&mut [0u8])
-                        .unwrap();
-                }
-            }
-        }
-        // Do a round with the king, to be sure everyone is ready
-        let from_all = self.send_to_king(&[self.id as u8]);
-        self.recv_from_king(from_all);
-        for id in 0..n {
-            if id != self.id {
-                assert!(self.peers[id].stream.is_some());
-            }
-        }
-        end_timer!(timer);
-    }
-    fn am_king(&self) -> bool {
-        self.id == 0
-    }
-    fn broadcast(&mut self, bytes_out: &[u8]) -> Vec<Vec<u8>> {
-        let timer = start_timer!(|| format!("Broadcast {}", bytes_out.len()));
-        let m = bytes_out.len();
-        let own_id = self.id;
-        self.stats.bytes_sent += (self.peers.len() - 1) * m;
-        self.stats.bytes_recv += (self.peers.len() - 1) * m;
-        self.stats.broadcasts += 1;
-        let r = self
-            .peers
-            .par_iter_mut()
-            .enumerate()
-            .map(|(id, peer)| {
-                let mut bytes_in = vec![0u8; m];
-                match id.cmp(&own_id) {
-                    std::cmp::Ordering::Less => {
-                        let stream = peer.stream.as_mut().unwrap();
-                        stream.read_exact(&mut bytes_in[..]).unwrap();
-                        stream.write_all(bytes_out).unwrap();
-                    }
-                    std::cmp::Ordering::Equal => {
-                        bytes_in.copy_from_slice(bytes_out);
-                    }
-                    std::cmp::Ordering::Greater => {
-                        let stream = peer.stream.as_mut().unwrap();
-                        stream.write_all(bytes_out).unwrap();
-                        stream.read_exact(&mut bytes_in[..]).unwrap();
-                    }
-                }
-                bytes_in
+impl Stats {
+    /// As pretty-printed JSON, e.g. to keep track of a protocol's cost across versions. Durations
+    /// are in (fractional) seconds.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("stats serialize")
+    }
+
+    /// The stats of phase `label`, among `n` parties.
+    pub(crate) fn phase(&mut self, label: &str, n: usize) -> &mut PhaseStats {
+        self.phases
+            .entry(label.to_owned())
+            .or_insert_with(|| PhaseStats {
+                bytes_sent: vec![0; n],
+                bytes_recv: vec![0; n],
+                ..Default::default()
             })
-            .collect();
-        end_timer!(timer);
-        r
-    }
-    fn send_to_king(&mut self, bytes_out: &[u8]) -> Option<Vec<Vec<u8>>> {
-        let timer = start_timer!(|| format!("To king {}", bytes_out.len()));
-        let m = bytes_out.len();
-        let own_id = self.id;
-        self.stats.to_king += 1;
-        let r = if self.am_king() {
-            self.stats.bytes_recv += (self.peers.len() - 1) * m;
-            Some(
-                self.peers
-                    .par_iter_mut()
-                    .enumerate()
-                    .map(|(id, peer)| {
-                        let mut bytes_in = vec![0u8; m];
-                        if id == own_id {
-                            bytes_in.copy_from_slice(bytes_out);
-                        } else {
-                            let stream = peer.stream.as_mut().unwrap();
-                            stream.read_exact(&mut bytes_in[..]).unwrap();
-                        };
-                        bytes_in
-                    })
-                    .collect(),
-            )
-        } else {
-            self.stats.bytes_sent += m;
-            self.peers[0]
-                .stream
-                .as_mut()
-                .unwrap()
-                .write_all(bytes_out)
-                .unwrap();
-            None
-        };
-        end_timer!(timer);
-        r
-    }
-    fn recv_from_king(&mut self, bytes_out: Option<Vec<Vec<u8>>>) -> Vec<u8> {
-        let own_id = self.id;
-        self.stats.from_king += 1;
-        if self.am_king() {
-            let bytes_out = bytes_out.unwrap();
-            let m = bytes_out[0].len();
-            let timer = start_timer!(|| format!("From king {}", m));
-            let bytes_size = (m as u64).to_le_bytes();
-            self.stats.bytes_sent += (self.peers.len() - 1) * (m + 8);
-            self.peers
-                .par_iter_mut()
-                .enumerate()
-                .filter(|p| p.0 != own_id)
-                .for_each(|(id, peer)| {
-                    let stream = peer.stream.as_mut().unwrap();
-                    assert_eq!(bytes_out[id].len(), m);
-                    stream.write_all(&bytes_size).unwrap();
-                    stream.write_all(&bytes_out[id]).unwrap();
-                });
-            end_timer!(timer);
-            bytes_out[own_id].clone()
+    }
+}
+
+impl PhaseStats {
+    /// Count one operation of kind `op` that began at `started` and just finished.
+    pub(crate) fn op(&mut self, op: tag::Op, started: Instant) -> &mut Self {
+        if op == tag::Op::Direct {
+            self.messages += 1;
         } else {
-            let stream = self.peers[0].stream.as_mut().unwrap();
-            let mut bytes_size = [0u8; 8];
-            stream.read_exact(&mut bytes_size).unwrap();
-            let m = u64::from_le_bytes(bytes_size) as usize;
-            self.stats.bytes_recv += m;
-            let mut bytes_in = vec![0u8; m];
-            stream.read_exact(&mut bytes_in).unwrap();
-            bytes_in
+            self.rounds += 1;
+        }
+        self.blocked += started.elapsed();
+        self
+    }
+
+    pub(crate) fn sent(&mut self, to: usize, len: usize) -> &mut Self {
+        self.bytes_sent[to] += len;
+        self
+    }
+
+    pub(crate) fn recv(&mut self, from: usize, len: usize) -> &mut Self {
+        self.bytes_recv[from] += len;
+        self
+    }
+
+    /// `len` bytes to every party but `own`.
+    pub(crate) fn sent_to_all(&mut self, own: usize, len: usize) -> &mut Self {
+        for to in (0..self.bytes_sent.len()).filter(|&to| to != own) {
+            self.sent(to, len);
         }
+        self
+    }
+
+    /// One frame from every party but `own`, whose slot is skipped.
+    pub(crate) fn recv_all(&mut self, own: usize, frames: &[Vec<u8>]) -> &mut Self {
+        for (from, frame) in frames.iter().enumerate().filter(|(from, _)| *from != own) {
+            self.recv(from, frame.len());
+        }
+        self
+    }
+}
+
+/// (De)serialize a [Duration] as seconds.
+mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(d.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        f64::deserialize(d).map(Duration::from_secs_f64)
+    }
+}
+
+/// A way of moving bytes between the parties of one session.
+///
+/// An [MpcSession] drives one of these. The TCP mesh set up from a host file and the in-process
+/// [local::LocalTransport] are the implementations in this crate, along with a star through a
+/// [relay::Relay], a tokio-driven TCP mesh with the `tokio` feature (see `async_net`), the
+/// [transcript::Recorder] and [transcript::Replay] used for debugging, and the
+/// [emulate::Emulated] network conditions used for benchmarking. The channels of a [mux::Mux]
+/// share the connections of one of these.
+pub trait Transport: Debug + Send {
+    /// What is my party number (0 to n-1)?
+    fn party_id(&self) -> usize;
+    /// How many parties are there?
+    fn n_parties(&self) -> usize;
+    /// Is the transport connected?
+    fn is_init(&self) -> bool;
+    /// Close all connections.
+    fn uninit(&mut self);
+    /// Statistics collected so far.
+    fn stats(&mut self) -> &mut Stats;
+    /// Bound how long a single read from (or write to) a peer may block. `None` waits forever.
+    fn set_timeouts(&mut self, read: Option<Duration>, write: Option<Duration>) -> NetResult<()>;
+    /// All parties send bytes to each other. Messages may differ in length between parties.
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>>;
+    /// All parties send bytes to party `king`. Messages may differ in length between parties.
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>>;
+    /// All parties recv bytes from party `king`.
+    /// Provide bytes iff you're the king!
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>>;
+    /// Send bytes to party `to` only.
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()>;
+    /// Receive the next message party `from` sent us with [Transport::send_to].
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>>;
+    /// Hand over the connection to each peer (`None` in our own slot), for a [mux::Mux] to share
+    /// between channels.
+    fn into_links(self: Box<Self>) -> NetResult<Vec<Option<mux::Link>>> {
+        Err(NetError::Config(
+            "this transport cannot be split into channels".to_owned(),
+        ))
     }
-    fn uninit(&mut self) {
-        for p in &mut self.peers {
-            p.stream = None;
+    /// Send bytes to `peer` and receive its bytes in return.
+    fn exchange(&mut self, peer: usize, bytes_out: &[u8]) -> NetResult<Vec<u8>> {
+        // The lower id sends first, so two large sends never block on each other.
+        if self.party_id() < peer {
+            self.send_to(peer, bytes_out)?;
+            self.recv_from(peer)
+        } else {
+            let bytes_in = self.recv_from(peer)?;
+            self.send_to(peer, bytes_out)?;
+            Ok(bytes_in)
         }
     }
 }
 
 pub trait MpcNet {
-    /// Am I the first party?
+    /// Am I the king of the next king operation ([MpcNet::send_bytes_to_king] or
+    /// [MpcNet::recv_bytes_from_king])?
     #[inline]
     fn am_king() -> bool {
-        Self::party_id() == 0
+        Self::party_id() == Self::king()
     }
+    /// The king of the next king operation. This depends on the session's [King] policy.
+    fn king() -> usize;
+    /// The king of the first king operation. Unlike [MpcNet::king], this never changes during a
+    /// session, so it can mark the party holding something for the whole session.
+    fn first_king() -> usize;
+    /// Change how the king is chosen. All parties must do this at the same point.
+    fn set_king(king: King);
+    /// The [Security] mode of the session.
+    fn security() -> Security;
+    /// Attach `value` to the session; see [MpcSession::attach].
+    fn attach<T: Any + Send + Sync>(value: T);
+    /// The value of type `T` attached to the session, if any; see [MpcSession::attached].
+    fn attached<T: Any + Send + Sync>() -> Option<Arc<T>>;
     /// How many parties are there?
     fn n_parties() -> usize;
     /// What is my party number (0 to n-1)?
@@ -274,22 +235,67 @@ pub trait MpcNet {
     /// the parties in increasing order.
     ///
     /// Parties are zero-indexed.
-    fn init_from_file(path: &str, party_id: usize);
+    fn try_init_from_file(path: &str, party_id: usize) -> NetResult<()>;
+    /// Like [MpcNet::try_init_from_file], but panics on failure.
+    #[inline]
+    fn init_from_file(path: &str, party_id: usize) {
+        Self::try_init_from_file(path, party_id)
+            .unwrap_or_else(|e| panic!("Could not initialize the network: {}", e))
+    }
+    /// Like [MpcNet::try_init_from_file], but authenticates and encrypts every link. Each line of
+    /// the file must also carry that party's hex-encoded public key; see [secure].
+    fn try_init_secure_from_file(path: &str, party_id: usize, private_key: &[u8]) -> NetResult<()>;
+    /// Initialize the network layer from a TOML or JSON config file; see [config].
+    fn try_init_from_config(path: &str, party_id: usize) -> NetResult<()>;
+    /// Like [MpcNet::try_init_from_config], but panics on failure.
+    #[inline]
+    fn init_from_config(path: &str, party_id: usize) {
+        Self::try_init_from_config(path, party_id)
+            .unwrap_or_else(|e| panic!("Could not initialize the network: {}", e))
+    }
+    /// Initialize the network layer with a [transcript::Replay] of the transcript at `path`, to
+    /// rerun one party of a recorded session on its own.
+    fn try_init_replay(path: &str) -> NetResult<()>;
     /// Is the network layer initalized?
     fn is_init() -> bool;
-    /// Uninitialize the network layer, closing all connections.
+    /// Uninitialize the network layer, closing all connections; see [MpcSession::try_deinit].
+    fn try_deinit() -> NetResult<()>;
+    /// Like [MpcNet::try_deinit], but panics if a check failed.
     fn deinit();
+    /// Register a check to pass before results leave the session; see
+    /// [MpcSession::on_checkpoint].
+    fn on_checkpoint(check: impl Fn() -> NetResult<()> + Send + Sync + 'static);
+    /// Run the checks registered with [MpcNet::on_checkpoint]; see [MpcSession::try_checkpoint].
+    fn try_checkpoint() -> NetResult<()>;
     /// Set statistics to zero.
     fn reset_stats();
+    /// Run `f` with every message it sends labelled `label`; see [MpcSession::with_label]. Its
+    /// traffic is counted under `label` in [Stats::phases].
+    fn with_label<R>(label: &str, f: impl FnOnce() -> R) -> R;
     /// Get statistics.
     fn stats() -> Stats;
-    /// All parties send bytes to each other.
-    fn broadcast_bytes(bytes: &[u8]) -> Vec<Vec<u8>>;
-    /// All parties send bytes to the king.
-    fn send_bytes_to_king(bytes: &[u8]) -> Option<Vec<Vec<u8>>>;
+    /// Bound how long a single read from (or write to) a peer may block before the operation
+    /// fails with [NetError::Timeout]. `None` waits forever, which is the default.
+    fn set_timeouts(read: Option<Duration>, write: Option<Duration>) -> NetResult<()>;
+    /// All parties send bytes to each other. Messages may differ in length between parties.
+    #[track_caller]
+    fn try_broadcast_bytes(bytes: &[u8]) -> NetResult<Vec<Vec<u8>>>;
+    /// All parties send bytes to the king. Messages may differ in length between parties.
+    #[track_caller]
+    fn try_send_bytes_to_king(bytes: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>>;
     /// All parties recv bytes from the king.
     /// Provide bytes iff you're the king!
-    fn recv_bytes_from_king(bytes: Option<Vec<Vec<u8>>>) -> Vec<u8>;
+    #[track_caller]
+    fn try_recv_bytes_from_king(bytes: Option<Vec<Vec<u8>>>) -> NetResult<Vec<u8>>;
+    /// Send bytes to party `to` only. It must call [MpcNet::try_recv_bytes_from] with our id.
+    #[track_caller]
+    fn try_send_bytes_to(to: usize, bytes: &[u8]) -> NetResult<()>;
+    /// Receive the next message that party `from` sent us with [MpcNet::try_send_bytes_to].
+    #[track_caller]
+    fn try_recv_bytes_from(from: usize) -> NetResult<Vec<u8>>;
+    /// Swap bytes with party `peer`, who must call this with our id.
+    #[track_caller]
+    fn try_exchange_bytes(peer: usize, bytes: &[u8]) -> NetResult<Vec<u8>>;
 
     /// Everyone sends bytes to the king, who recieves those bytes, runs a computation on them, and
     /// redistributes the resulting bytes.
@@ -297,75 +303,243 @@ pub trait MpcNet {
     /// The king's computation is given by a function, `f`
     /// proceeds.
     #[inline]
+    #[track_caller]
+    fn try_king_compute(
+        bytes: &[u8],
+        f: impl Fn(Vec<Vec<u8>>) -> Vec<Vec<u8>>,
+    ) -> NetResult<Vec<u8>> {
+        let king_response = Self::try_send_bytes_to_king(bytes)?.map(f);
+        Self::try_recv_bytes_from_king(king_response)
+    }
+
+    /// Like [MpcNet::try_broadcast_bytes], but panics on failure.
+    #[inline]
+    #[track_caller]
+    fn broadcast_bytes(bytes: &[u8]) -> Vec<Vec<u8>> {
+        Self::try_broadcast_bytes(bytes).unwrap_or_else(|e| panic!("broadcast failed: {}", e))
+    }
+    /// Like [MpcNet::try_send_bytes_to_king], but panics on failure.
+    #[inline]
+    #[track_caller]
+    fn send_bytes_to_king(bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
+        Self::try_send_bytes_to_king(bytes).unwrap_or_else(|e| panic!("send to king failed: {}", e))
+    }
+    /// Like [MpcNet::try_recv_bytes_from_king], but panics on failure.
+    #[inline]
+    #[track_caller]
+    fn recv_bytes_from_king(bytes: Option<Vec<Vec<u8>>>) -> Vec<u8> {
+        Self::try_recv_bytes_from_king(bytes)
+            .unwrap_or_else(|e| panic!("receive from king failed: {}", e))
+    }
+    /// Like [MpcNet::try_send_bytes_to], but panics on failure.
+    #[inline]
+    #[track_caller]
+    fn send_bytes_to(to: usize, bytes: &[u8]) {
+        Self::try_send_bytes_to(to, bytes)
+            .unwrap_or_else(|e| panic!("send to party {} failed: {}", to, e))
+    }
+    /// Like [MpcNet::try_recv_bytes_from], but panics on failure.
+    #[inline]
+    #[track_caller]
+    fn recv_bytes_from(from: usize) -> Vec<u8> {
+        Self::try_recv_bytes_from(from)
+            .unwrap_or_else(|e| panic!("receive from party {} failed: {}", from, e))
+    }
+    /// Like [MpcNet::try_exchange_bytes], but panics on failure.
+    #[inline]
+    #[track_caller]
+    fn exchange_bytes(peer: usize, bytes: &[u8]) -> Vec<u8> {
+        Self::try_exchange_bytes(peer, bytes)
+            .unwrap_or_else(|e| panic!("exchange with party {} failed: {}", peer, e))
+    }
+    /// Like [MpcNet::try_king_compute], but panics on failure.
+    #[inline]
+    #[track_caller]
     fn king_compute(bytes: &[u8], f: impl Fn(Vec<Vec<u8>>) -> Vec<Vec<u8>>) -> Vec<u8> {
-        let king_response = Self::send_bytes_to_king(bytes).map(f);
-        Self::recv_bytes_from_king(king_response)
+        Self::try_king_compute(bytes, f).unwrap_or_else(|e| panic!("king compute failed: {}", e))
     }
 
     fn uninit();
 }
 
+/// The static network API over the session bound to the calling thread (see
+/// [MpcSession::enter]), or over a process-wide default session otherwise.
 pub struct MpcMultiNet;
 
+impl MpcMultiNet {
+    #[inline]
+    fn session() -> Arc<MpcSession> {
+        MpcSession::current().unwrap_or_else(|| {
+            if session::ENTERED.load(Ordering::SeqCst) > 0
+                && !WARNED_UNBOUND.swap(true, Ordering::SeqCst)
+            {
+                log::warn!(
+                    "A thread without a session binding uses the default session while others \
+                     are entered; spawned protocol work should go through MpcSession::propagate"
+                );
+            }
+            DEFAULT_SESSION.read().expect("Poisoned MpcSession").clone()
+        })
+    }
+}
+
 impl MpcNet for MpcMultiNet {
     #[inline]
     fn party_id() -> usize {
-        get_ch!().id
+        Self::session().party_id()
     }
 
     #[inline]
     fn n_parties() -> usize {
-        get_ch!().peers.len()
+        Self::session().n_parties()
     }
 
     #[inline]
-    fn init_from_file(path: &str, party_id: usize) {
-        let mut ch = get_ch!();
-        ch.init_from_path(path, party_id);
-        ch.connect_to_all();
+    fn king() -> usize {
+        Self::session().king()
+    }
+
+    #[inline]
+    fn first_king() -> usize {
+        Self::session().first_king()
+    }
+
+    #[inline]
+    fn set_king(king: King) {
+        Self::session().set_king(king)
+    }
+
+    #[inline]
+    fn security() -> Security {
+        Self::session().security()
+    }
+
+    #[inline]
+    fn attach<T: Any + Send + Sync>(value: T) {
+        Self::session().attach(value)
+    }
+
+    #[inline]
+    fn attached<T: Any + Send + Sync>() -> Option<Arc<T>> {
+        Self::session().attached()
+    }
+
+    /// (Re)initializes the process-wide default session.
+    #[inline]
+    fn try_init_from_file(path: &str, party_id: usize) -> NetResult<()> {
+        let session = Arc::new(MpcSession::try_init_from_file(path, party_id)?);
+        *DEFAULT_SESSION.write().expect("Poisoned MpcSession") = session;
+        Ok(())
+    }
+
+    #[inline]
+    fn try_init_secure_from_file(path: &str, party_id: usize, private_key: &[u8]) -> NetResult<()> {
+        let session = Arc::new(MpcSession::try_init_secure_from_file(
+            path,
+            party_id,
+            private_key,
+        )?);
+        *DEFAULT_SESSION.write().expect("Poisoned MpcSession") = session;
+        Ok(())
+    }
+
+    #[inline]
+    fn try_init_from_config(path: &str, party_id: usize) -> NetResult<()> {
+        let session = Arc::new(MpcSession::try_init_from_config(path, party_id)?);
+        *DEFAULT_SESSION.write().expect("Poisoned MpcSession") = session;
+        Ok(())
+    }
+
+    #[inline]
+    fn try_init_replay(path: &str) -> NetResult<()> {
+        let session = Arc::new(MpcSession::try_replay(path)?);
+        *DEFAULT_SESSION.write().expect("Poisoned MpcSession") = session;
+        Ok(())
     }
 
     #[inline]
     fn is_init() -> bool {
-        get_ch!()
-            .peers
-            .first()
-            .map(|p| p.stream.is_some())
-            .unwrap_or(false)
+        Self::session().is_init()
+    }
+
+    #[inline]
+    fn try_deinit() -> NetResult<()> {
+        Self::session().try_deinit()
     }
 
     #[inline]
     fn deinit() {
-        get_ch!().uninit()
+        Self::session().deinit()
+    }
+
+    #[inline]
+    fn on_checkpoint(check: impl Fn() -> NetResult<()> + Send + Sync + 'static) {
+        Self::session().on_checkpoint(check)
+    }
+
+    #[inline]
+    fn try_checkpoint() -> NetResult<()> {
+        Self::session().try_checkpoint()
     }
 
     #[inline]
     fn reset_stats() {
-        get_ch!().stats = Stats::default();
+        Self::session().reset_stats()
+    }
+
+    #[inline]
+    fn with_label<R>(label: &str, f: impl FnOnce() -> R) -> R {
+        Self::session().with_label(label, f)
     }
 
     #[inline]
     fn stats() -> crate::Stats {
-        get_ch!().stats.clone()
+        Self::session().stats()
     }
 
     #[inline]
-    fn broadcast_bytes(bytes: &[u8]) -> Vec<Vec<u8>> {
-        get_ch!().broadcast(bytes)
+    fn set_timeouts(read: Option<Duration>, write: Option<Duration>) -> NetResult<()> {
+        Self::session().set_timeouts(read, write)
     }
 
     #[inline]
-    fn send_bytes_to_king(bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
-        get_ch!().send_to_king(bytes)
+    #[track_caller]
+    fn try_broadcast_bytes(bytes: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        Self::session().try_broadcast_bytes(bytes)
     }
 
     #[inline]
-    fn recv_bytes_from_king(bytes: Option<Vec<Vec<u8>>>) -> Vec<u8> {
-        get_ch!().recv_from_king(bytes)
+    #[track_caller]
+    fn try_send_bytes_to_king(bytes: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        Self::session().try_send_bytes_to_king(bytes)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_recv_bytes_from_king(bytes: Option<Vec<Vec<u8>>>) -> NetResult<Vec<u8>> {
+        Self::session().try_recv_bytes_from_king(bytes)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_send_bytes_to(to: usize, bytes: &[u8]) -> NetResult<()> {
+        Self::session().try_send_bytes_to(to, bytes)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_recv_bytes_from(from: usize) -> NetResult<Vec<u8>> {
+        Self::session().try_recv_bytes_from(from)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_exchange_bytes(peer: usize, bytes: &[u8]) -> NetResult<Vec<u8>> {
+        Self::session().try_exchange_bytes(peer, bytes)
     }
 
     #[inline]
     fn uninit() {
-        get_ch!().uninit()
+        Self::session().deinit()
     }
 }
@@ -0,0 +1,350 @@
+//! An in-process network: every party is a thread and every link is a channel.
+//!
+//! Useful for running MPC code as ordinary `#[test]`s:
+//!
+//! ```
+//! use mpc_net::{local::simulate, MpcMultiNet as Net, MpcNet};
+//!
+//! let outputs = simulate(3, |id| Net::broadcast_bytes(&[id as u8]));
+//! assert!(outputs.iter().all(|all| all == &vec![vec![0], vec![1], vec![2]]));
+//! ```
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    mux::{FrameRead, FrameWrite, Link},
+    MpcSession, NetError, NetResult, Stats, Transport,
+};
+
+/// The length prefix a TCP frame carries; counted in [Stats] so both transports report alike.
+const FRAME_HEADER_LEN: usize = 8;
+
+/// One party's end of an in-memory, fully connected network.
+#[derive(Debug)]
+pub struct LocalTransport {
+    id: usize,
+    /// `to[j]` carries messages from this party to party `j`.
+    to: Vec<Sender<Vec<u8>>>,
+    /// `from[j]` carries messages from party `j` to this party.
+    from: Vec<Receiver<Vec<u8>>>,
+    stats: Stats,
+    connected: bool,
+    read_timeout: Option<Duration>,
+}
+
+impl LocalTransport {
+    /// Create the transports of `n` parties, all connected to each other.
+    pub fn mesh(n: usize) -> Vec<Self> {
+        let mut to: Vec<Vec<Sender<Vec<u8>>>> = (0..n).map(|_| Vec::new()).collect();
+        let mut from: Vec<Vec<Receiver<Vec<u8>>>> = (0..n).map(|_| Vec::new()).collect();
+        for to_i in to.iter_mut() {
+            for from_j in from.iter_mut() {
+                let (tx, rx) = channel();
+                to_i.push(tx);
+                from_j.push(rx);
+            }
+        }
+        to.into_iter()
+            .zip(from)
+            .enumerate()
+            .map(|(id, (to, from))| Self {
+                id,
+                to,
+                from,
+                stats: Stats::default(),
+                connected: true,
+                read_timeout: None,
+            })
+            .collect()
+    }
+
+    fn send(&self, to: usize, bytes: &[u8]) -> NetResult<()> {
+        self.to[to]
+            .send(bytes.to_vec())
+            .map_err(|_| NetError::Disconnected { peer: to })
+    }
+
+    /// Receive one message from every other party, with `own` in our slot.
+    fn recv_all(&mut self, own: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let r = (0..self.n_parties())
+            .map(|id| {
+                if id == self.id {
+                    Ok(own.to_vec())
+                } else {
+                    self.recv(id)
+                }
+            })
+            .collect::<NetResult<Vec<_>>>()?;
+        self.stats.bytes_recv +=
+            (r.iter().map(Vec::len).sum::<usize>() - own.len()) + (r.len() - 1) * FRAME_HEADER_LEN;
+        Ok(r)
+    }
+
+    fn recv(&self, from: usize) -> NetResult<Vec<u8>> {
+        let rx = &self.from[from];
+        match self.read_timeout {
+            None => rx.recv().map_err(|_| NetError::Disconnected { peer: from }),
+            Some(t) => rx.recv_timeout(t).map_err(|e| match e {
+                RecvTimeoutError::Timeout => NetError::Timeout { peer: from },
+                RecvTimeoutError::Disconnected => NetError::Disconnected { peer: from },
+            }),
+        }
+    }
+}
+
+/// One direction of the link to `peer`, handed to a [crate::mux::Mux].
+struct End<T> {
+    peer: usize,
+    end: T,
+}
+
+impl FrameRead for End<Receiver<Vec<u8>>> {
+    fn read_frame(&mut self) -> NetResult<Vec<u8>> {
+        self.end
+            .recv()
+            .map_err(|_| NetError::Disconnected { peer: self.peer })
+    }
+}
+
+impl FrameWrite for End<Sender<Vec<u8>>> {
+    fn write_frame(&mut self, bytes: &[u8]) -> NetResult<()> {
+        self.end
+            .send(bytes.to_vec())
+            .map_err(|_| NetError::Disconnected { peer: self.peer })
+    }
+}
+
+impl Transport for LocalTransport {
+    fn party_id(&self) -> usize {
+        self.id
+    }
+    fn n_parties(&self) -> usize {
+        self.to.len()
+    }
+    fn is_init(&self) -> bool {
+        self.connected
+    }
+    fn uninit(&mut self) {
+        self.connected = false;
+    }
+    fn stats(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+    /// Channels never block on send, so only the read deadline applies.
+    fn set_timeouts(&mut self, read: Option<Duration>, _write: Option<Duration>) -> NetResult<()> {
+        self.read_timeout = read;
+        Ok(())
+    }
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let n = self.n_parties();
+        self.stats.bytes_sent += (n - 1) * (bytes_out.len() + FRAME_HEADER_LEN);
+        self.stats.broadcasts += 1;
+        for id in (0..n).filter(|&id| id != self.id) {
+            self.send(id, bytes_out)?;
+        }
+        self.recv_all(bytes_out)
+    }
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        self.stats.to_king += 1;
+        if self.id == king {
+            self.recv_all(bytes_out).map(Some)
+        } else {
+            self.stats.bytes_sent += bytes_out.len() + FRAME_HEADER_LEN;
+            self.send(king, bytes_out)?;
+            Ok(None)
+        }
+    }
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        self.stats.from_king += 1;
+        if self.id == king {
+            let bytes_out = bytes_out.expect("the king must provide bytes to recv_from_king");
+            assert_eq!(bytes_out.len(), self.n_parties());
+            for (id, bytes) in bytes_out.iter().enumerate() {
+                if id != self.id {
+                    self.stats.bytes_sent += bytes.len() + FRAME_HEADER_LEN;
+                    self.send(id, bytes)?;
+                }
+            }
+            Ok(bytes_out[self.id].clone())
+        } else {
+            let bytes_in = self.recv(king)?;
+            self.stats.bytes_recv += bytes_in.len() + FRAME_HEADER_LEN;
+            Ok(bytes_in)
+        }
+    }
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.stats.bytes_sent += bytes_out.len() + FRAME_HEADER_LEN;
+        self.send(to, bytes_out)
+    }
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        let bytes_in = self.recv(from)?;
+        self.stats.bytes_recv += bytes_in.len() + FRAME_HEADER_LEN;
+        Ok(bytes_in)
+    }
+    fn into_links(self: Box<Self>) -> NetResult<Vec<Option<Link>>> {
+        let id = self.id;
+        Ok(self
+            .to
+            .into_iter()
+            .zip(self.from)
+            .enumerate()
+            .map(|(peer, (to, from))| {
+                (peer != id).then(|| Link::new(End { peer, end: from }, End { peer, end: to }))
+            })
+            .collect())
+    }
+}
+
+/// Run `f` as each of `n` parties, one thread per party, over a [LocalTransport] mesh.
+///
+/// Each thread enters its own [MpcSession], so `f` can use [crate::MpcMultiNet] (and the share
+/// types built on it) as if it were one process of a real run. Returns the outputs ordered by
+/// party id. If any party panics, the panic is propagated.
+pub fn simulate<R: Send>(n: usize, f: impl Fn(usize) -> R + Sync) -> Vec<R> {
+    let f = &f;
+    std::thread::scope(|s| {
+        let handles: Vec<_> = LocalTransport::mesh(n)
+            .into_iter()
+            .map(|t| {
+                let session = Arc::new(MpcSession::from_transport(t));
+                s.spawn(move || session.enter(|| f(session.party_id())))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MpcMultiNet as Net, MpcNet};
+
+    #[test]
+    fn king_compute() {
+        let outputs = simulate(4, |id| {
+            Net::king_compute(&[id as u8; 3], |all| {
+                let sum: u8 = all.iter().map(|b| b[0]).sum();
+                (0..all.len()).map(|i| vec![sum, i as u8]).collect()
+            })
+        });
+        for (id, out) in outputs.into_iter().enumerate() {
+            assert_eq!(out, vec![6, id as u8]);
+        }
+    }
+
+    #[test]
+    fn variable_length() {
+        let outputs = simulate(3, |id| {
+            let all = Net::broadcast_bytes(&vec![id as u8; id]);
+            let at_king = Net::send_bytes_to_king(&vec![id as u8; 2 * id]);
+            (all, at_king)
+        });
+        for (id, (all, at_king)) in outputs.into_iter().enumerate() {
+            assert_eq!(all, (0..3).map(|i| vec![i as u8; i]).collect::<Vec<_>>());
+            assert_eq!(
+                at_king,
+                (id == 0).then(|| (0..3).map(|i| vec![i as u8; 2 * i]).collect())
+            );
+        }
+    }
+
+    #[test]
+    fn point_to_point() {
+        let outputs = simulate(4, |id| {
+            // Pass a token around the ring, then swap with the opposite party.
+            let (next, prev) = ((id + 1) % 4, (id + 3) % 4);
+            if id == 0 {
+                Net::send_bytes_to(next, &[0]);
+            }
+            let mut token = Net::recv_bytes_from(prev);
+            token.push(id as u8);
+            if id != 0 {
+                Net::send_bytes_to(next, &token);
+            }
+            (token, Net::exchange_bytes((id + 2) % 4, &[id as u8]))
+        });
+        assert_eq!(outputs[0].0, vec![0, 1, 2, 3, 0]);
+        for (id, (_, swapped)) in outputs.iter().enumerate() {
+            assert_eq!(swapped, &vec![((id + 2) % 4) as u8]);
+        }
+    }
+
+    #[test]
+    fn traffic_balances() {
+        let stats = simulate(4, |id| {
+            Net::broadcast_bytes(&vec![id as u8; id]);
+            Net::king_compute(&vec![id as u8; 2 * id], |all| all);
+            if id == 0 {
+                Net::send_bytes_to(3, &[0; 7]);
+            } else if id == 3 {
+                Net::recv_bytes_from(0);
+            }
+            Net::exchange_bytes(id ^ 1, &vec![0; 5 * id]);
+            Net::stats()
+        });
+        let sent: usize = stats.iter().map(|s| s.bytes_sent).sum();
+        let recv: usize = stats.iter().map(|s| s.bytes_recv).sum();
+        assert_eq!(sent, recv);
+        // Per phase, what party i sent to j is what j received from i.
+        for (i, from) in stats.iter().enumerate() {
+            for (j, to) in stats.iter().enumerate() {
+                assert_eq!(from.phases[""].bytes_sent[j], to.phases[""].bytes_recv[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_disconnect() {
+        let results = simulate(3, |id| {
+            if id == 2 {
+                // Leaves without taking part.
+                return Ok(vec![]);
+            }
+            Net::try_king_compute(&[id as u8], |all| all)
+        });
+        assert!(matches!(
+            results[0],
+            Err(NetError::Disconnected { peer: 2 })
+        ));
+        assert!(matches!(
+            results[1],
+            Err(NetError::Disconnected { peer: 0 })
+        ));
+    }
+
+    #[test]
+    fn reports_timeout() {
+        let results = simulate(2, |id| {
+            if id == 1 {
+                std::thread::sleep(Duration::from_millis(200));
+                return Ok(());
+            }
+            Net::set_timeouts(Some(Duration::from_millis(20)), None).unwrap();
+            Net::try_broadcast_bytes(&[0]).map(|_| ())
+        });
+        assert!(matches!(results[0], Err(NetError::Timeout { peer: 1 })));
+    }
+
+    #[test]
+    #[should_panic(expected = "party 1")]
+    fn propagates_panics() {
+        simulate(3, |id| {
+            if id == 1 {
+                panic!("party 1 failed");
+            }
+            Net::broadcast_bytes(&[0]);
+        });
+    }
+}
@@ -0,0 +1,813 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use ark_std::{end_timer, start_timer};
+use log::debug;
+use rayon::{
+    prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator},
+    ThreadPool, ThreadPoolBuilder,
+};
+
+use crate::{
+    config::{ConnectPolicy, NetConfig, DEFAULT_MAX_MESSAGE_LEN},
+    mux::{self, FrameRead, FrameWrite},
+    secure::{self, SecureLink, MAX_RECORD_PAYLOAD},
+    NetError, NetResult, Stats, Transport,
+};
+
+#[derive(Debug)]
+struct Peer {
+    id: usize,
+    addr: SocketAddr,
+    /// Where this peer listens, if not at `addr`.
+    listen: Option<SocketAddr>,
+    /// The peer's static public key, if the host file lists one.
+    public_key: Option<Vec<u8>>,
+    stream: Option<TcpStream>,
+    /// Set once a secure handshake over `stream` has succeeded.
+    link: Option<SecureLink>,
+}
+
+impl Default for Peer {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            addr: "127.0.0.1:8000".parse().unwrap(),
+            listen: None,
+            public_key: None,
+            stream: None,
+            link: None,
+        }
+    }
+}
+
+impl Peer {
+    /// Send one message, prefixed by its length as a little-endian `u64`.
+    ///
+    /// Over a secure link, the prefix and the message are sent as encrypted records.
+    fn write_frame(&mut self, bytes: &[u8]) -> NetResult<()> {
+        let id = self.id;
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(NetError::Disconnected { peer: id })?;
+        match &mut self.link {
+            None => write_plain_frame(id, stream, bytes),
+            Some(link) => write_sealed_frame(bytes, |record| link.send(id, stream, record)),
+        }
+    }
+    /// Receive one message written by [Peer::write_frame], of at most `max_len` bytes.
+    fn read_frame(&mut self, max_len: usize) -> NetResult<Vec<u8>> {
+        let id = self.id;
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(NetError::Disconnected { peer: id })?;
+        match &mut self.link {
+            None => read_plain_frame(id, stream, max_len),
+            Some(link) => read_sealed_frame(id, max_len, || link.recv(id, stream)),
+        }
+    }
+}
+
+/// One direction of the connection to `peer`, handed to a [mux::Mux]. Both directions share the
+/// secure link, if any: one only encrypts, the other only decrypts.
+struct Half {
+    peer: usize,
+    stream: TcpStream,
+    link: Option<Arc<Mutex<SecureLink>>>,
+    /// Longest frame we accept.
+    max_len: usize,
+}
+
+impl FrameRead for Half {
+    fn read_frame(&mut self) -> NetResult<Vec<u8>> {
+        let (peer, stream, max_len) = (self.peer, &mut self.stream, self.max_len);
+        match &self.link {
+            None => read_plain_frame(peer, stream, max_len),
+            Some(link) => read_sealed_frame(peer, max_len, || {
+                let record = secure::read_record(peer, stream)?;
+                link.lock()
+                    .expect("Poisoned SecureLink")
+                    .decrypt(peer, &record)
+            }),
+        }
+    }
+}
+
+impl FrameWrite for Half {
+    fn write_frame(&mut self, bytes: &[u8]) -> NetResult<()> {
+        let (peer, stream) = (self.peer, &mut self.stream);
+        match &self.link {
+            None => write_plain_frame(peer, stream, bytes),
+            Some(link) => write_sealed_frame(bytes, |record| {
+                let record = link
+                    .lock()
+                    .expect("Poisoned SecureLink")
+                    .encrypt(peer, record)?;
+                secure::write_record(peer, stream, &record)
+            }),
+        }
+    }
+    /// Only our sending side: the peer reads to the end of what we sent, and closes in turn.
+    fn close(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Write);
+    }
+}
+
+fn write_plain_frame(peer: usize, stream: &mut impl Write, bytes: &[u8]) -> NetResult<()> {
+    stream
+        .write_all(&(bytes.len() as u64).to_le_bytes())
+        .and_then(|_| stream.write_all(bytes))
+        .map_err(|e| NetError::io(peer, e))
+}
+
+fn read_plain_frame(peer: usize, stream: &mut impl Read, max_len: usize) -> NetResult<Vec<u8>> {
+    let mut bytes_size = [0u8; 8];
+    stream
+        .read_exact(&mut bytes_size)
+        .map_err(|e| NetError::io(peer, e))?;
+    let m = check_frame_len(peer, bytes_size, max_len)?;
+    let mut bytes_in = vec![0u8; m];
+    stream
+        .read_exact(&mut bytes_in)
+        .map_err(|e| NetError::io(peer, e))?;
+    Ok(bytes_in)
+}
+
+/// Send a frame as records: the length prefix, then the bytes in chunks that fit a record.
+fn write_sealed_frame(
+    bytes: &[u8],
+    mut send_record: impl FnMut(&[u8]) -> NetResult<()>,
+) -> NetResult<()> {
+    send_record(&(bytes.len() as u64).to_le_bytes())?;
+    bytes.chunks(MAX_RECORD_PAYLOAD).try_for_each(send_record)
+}
+
+/// Receive a frame written by [write_sealed_frame], from `peer`.
+fn read_sealed_frame(
+    peer: usize,
+    max_len: usize,
+    mut recv_record: impl FnMut() -> NetResult<Vec<u8>>,
+) -> NetResult<Vec<u8>> {
+    let size: [u8; 8] = recv_record()?
+        .try_into()
+        .map_err(|_| NetError::MalformedFrame {
+            peer,
+            reason: "bad length record".to_owned(),
+        })?;
+    let m = check_frame_len(peer, size, max_len)?;
+    let mut bytes_in = Vec::with_capacity(m);
+    while bytes_in.len() < m {
+        let chunk = recv_record()?;
+        if chunk.is_empty() || bytes_in.len() + chunk.len() > m {
+            return Err(NetError::MalformedFrame {
+                peer,
+                reason: "records do not add up to the announced length".to_owned(),
+            });
+        }
+        bytes_in.extend_from_slice(&chunk);
+    }
+    Ok(bytes_in)
+}
+
+/// Decode a frame's length prefix, rejecting lengths over `max_len` before we allocate for them.
+pub(crate) fn check_frame_len(
+    peer: usize,
+    bytes_size: [u8; 8],
+    max_len: usize,
+) -> NetResult<usize> {
+    let m = u64::from_le_bytes(bytes_size);
+    if m > max_len as u64 {
+        return Err(NetError::MalformedFrame {
+            peer,
+            reason: format!("announced length {} exceeds the limit of {}", m, max_len),
+        });
+    }
+    Ok(m as usize)
+}
+
+#[derive(Default, Debug)]
+pub(crate) struct Connections {
+    pub(crate) id: usize,
+    peers: Vec<Peer>,
+    pub(crate) stats: Stats,
+    /// One worker per peer, so that concurrent sessions never wait on each other's blocking I/O.
+    pool: Option<ThreadPool>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    /// Our static private key, if links must be authenticated and encrypted.
+    private_key: Option<Vec<u8>>,
+    connect_policy: ConnectPolicy,
+    /// Longest frame a peer may send us; [DEFAULT_MAX_MESSAGE_LEN] if unset.
+    max_message_len: Option<usize>,
+}
+
+impl Connections {
+    /// Authenticate and encrypt every link with the given static private key. The host file must
+    /// then list a public key for every party.
+    pub(crate) fn set_private_key(&mut self, private_key: &[u8]) {
+        self.private_key = Some(private_key.to_vec());
+    }
+    /// Given a path and the `id` of oneself, initialize the structure.
+    ///
+    /// Each line holds `HOST:PORT`, optionally followed by the party's hex-encoded public key.
+    pub(crate) fn init_from_path(&mut self, path: &str, id: usize) -> NetResult<()> {
+        let f = BufReader::new(
+            File::open(path).map_err(|e| NetError::Config(format!("{}: {}", path, e)))?,
+        );
+        let mut peer_id = 0;
+        for line in f.lines() {
+            let line = line.map_err(|e| NetError::Config(format!("{}: {}", path, e)))?;
+            let trimmed = line.trim();
+            if !trimmed.is_empty() {
+                let mut fields = trimmed.split_whitespace();
+                let addr = fields.next().unwrap_or_default();
+                let addr: SocketAddr = addr.parse().map_err(|e| {
+                    NetError::Config(format!("bad socket address: {}: {}", addr, e))
+                })?;
+                let public_key = fields.next().map(secure::from_hex).transpose()?;
+                let peer = Peer {
+                    id: peer_id,
+                    addr,
+                    public_key,
+                    ..Default::default()
+                };
+                self.peers.push(peer);
+                peer_id += 1;
+            }
+        }
+        self.finish_init(id, path)
+    }
+    /// Initialize the structure from a validated config, as party `id`.
+    pub(crate) fn init_from_config(&mut self, config: &NetConfig, id: usize) -> NetResult<()> {
+        if let Some(private_key) = config.private_key(id)? {
+            self.set_private_key(&private_key);
+        }
+        self.read_timeout = config.read_timeout();
+        self.write_timeout = config.write_timeout();
+        self.connect_policy = config.connect_policy();
+        self.max_message_len = Some(config.max_message_len());
+        self.peers = config
+            .resolve()?
+            .into_iter()
+            .enumerate()
+            .map(|(peer_id, p)| Peer {
+                id: peer_id,
+                addr: p.address,
+                listen: Some(p.listen),
+                public_key: p.public_key,
+                ..Default::default()
+            })
+            .collect();
+        self.finish_init(id, "the config")
+    }
+    /// Check the peer list read from `source` against our id and key, and get ready to connect.
+    fn finish_init(&mut self, id: usize, source: &str) -> NetResult<()> {
+        if id >= self.peers.len() {
+            return Err(NetError::Config(format!(
+                "party {} does not exist; {} lists {} parties",
+                id,
+                source,
+                self.peers.len()
+            )));
+        }
+        let keyed = self.peers.iter().filter(|p| p.public_key.is_some()).count();
+        if self.private_key.is_some() && keyed < self.peers.len() {
+            return Err(NetError::Config(format!(
+                "a private key was given, but {} does not list a public key for every party",
+                source
+            )));
+        }
+        if self.private_key.is_none() && keyed > 0 {
+            return Err(NetError::Config(format!(
+                "{} lists public keys, but no private key was given",
+                source
+            )));
+        }
+        self.id = id;
+        self.pool = Some(
+            ThreadPoolBuilder::new()
+                .num_threads(self.peers.len())
+                .build()
+                .expect("rayon thread pool"),
+        );
+        Ok(())
+    }
+    /// Connect to every other party, all at once.
+    ///
+    /// Each party dials the parties above it and accepts connections from those below it, in
+    /// whatever order they arrive: the dialer opens with a hello naming both ends, so the acceptor
+    /// knows who it is talking to. Links are then secured if we hold a private key, and a final
+    /// round with the king makes sure everyone is ready.
+    pub(crate) fn connect_to_all(&mut self) -> NetResult<()> {
+        let timer = start_timer!(|| "Connecting");
+        let connector = Connector {
+            id: self.id,
+            peers: &self.peers,
+            private_key: self.private_key.as_deref(),
+            policy: self.connect_policy,
+            deadline: Instant::now() + self.connect_policy.timeout,
+        };
+        let me = &self.peers[self.id];
+        let listener = if self.id > 0 {
+            Some(
+                TcpListener::bind(me.listen.unwrap_or(me.addr))
+                    .map_err(|e| NetError::io(self.id, e))?,
+            )
+        } else {
+            None
+        };
+        let links = std::thread::scope(|s| {
+            let connector = &connector;
+            let dialed: Vec<_> = (self.id + 1..self.peers.len())
+                .map(|peer| s.spawn(move || connector.dial(peer)))
+                .collect();
+            let accepted = match &listener {
+                Some(listener) => connector.accept_all(listener),
+                None => Ok(vec![]),
+            };
+            let dialed: NetResult<Vec<_>> = dialed
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect();
+            accepted.and_then(|mut links| {
+                links.extend(dialed?);
+                Ok(links)
+            })
+        })?;
+        for (peer, stream, link) in links {
+            self.peers[peer].stream = Some(self.configure(peer, stream)?);
+            self.peers[peer].link = link;
+        }
+        // Do a round with the king, to be sure everyone is ready
+        let from_all = self.send_to_king(0, &[self.id as u8])?;
+        if let Some(from_all) = &from_all {
+            for (id, bytes) in from_all.iter().enumerate() {
+                if bytes[..] != [id as u8] {
+                    return Err(NetError::WrongPartyId {
+                        expected: id,
+                        got: bytes.first().copied().unwrap_or_default() as usize,
+                    });
+                }
+            }
+        }
+        self.recv_from_king(0, from_all)?;
+        for peer in &self.peers {
+            if peer.id != self.id {
+                assert!(peer.stream.is_some());
+            }
+        }
+        end_timer!(timer);
+        Ok(())
+    }
+    /// Apply socket options and deadlines to a fresh stream to `peer`.
+    fn configure(&self, peer: usize, stream: TcpStream) -> NetResult<TcpStream> {
+        stream
+            .set_nodelay(true)
+            .and_then(|_| stream.set_read_timeout(self.read_timeout))
+            .and_then(|_| stream.set_write_timeout(self.write_timeout))
+            .map_err(|e| NetError::io(peer, e))?;
+        Ok(stream)
+    }
+    /// Give up the stream to each party, with its secure link if any, in order of party id. Our
+    /// own entry has no stream.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn take_streams(&mut self) -> Vec<(Option<TcpStream>, Option<SecureLink>)> {
+        self.peers
+            .iter_mut()
+            .map(|p| (p.stream.take(), p.link.take()))
+            .collect()
+    }
+    /// Longest frame a peer may send us.
+    pub(crate) fn max_message_len(&self) -> usize {
+        self.max_message_len.unwrap_or(DEFAULT_MAX_MESSAGE_LEN)
+    }
+    /// Run `f` on the peers inside this session's thread pool.
+    fn with_peers<R: Send>(&mut self, f: impl FnOnce(&mut Vec<Peer>) -> R + Send) -> R {
+        let pool = self.pool.as_ref().expect("Connections not initialized");
+        pool.install(|| f(&mut self.peers))
+    }
+}
+
+/// Opens every connection, followed by the dialer's and the acceptor's ids as little-endian `u64`s.
+/// The acceptor answers with the magic and its own id.
+const HELLO_MAGIC: [u8; 4] = *b"MPC\x01";
+const HELLO_LEN: usize = 4 + 8 + 8;
+/// How often to poll for incoming connections.
+const ACCEPT_POLL: Duration = Duration::from_millis(5);
+
+/// A fresh connection to a peer, after the hello and the secure handshake if any.
+type Link = (usize, TcpStream, Option<SecureLink>);
+
+/// What the connecting threads of [Connections::connect_to_all] share.
+struct Connector<'a> {
+    id: usize,
+    peers: &'a [Peer],
+    private_key: Option<&'a [u8]>,
+    policy: ConnectPolicy,
+    deadline: Instant,
+}
+
+impl Connector<'_> {
+    /// Connect to `peer`, retrying while it is not listening yet.
+    fn dial(&self, peer: usize) -> NetResult<Link> {
+        let mut stream = dial(self.peers[peer].addr, &self.policy, self.deadline)
+            .map_err(|e| NetError::io(peer, e))?;
+        self.bound(peer, &stream)?;
+        let mut hello = HELLO_MAGIC.to_vec();
+        hello.extend_from_slice(&(self.id as u64).to_le_bytes());
+        hello.extend_from_slice(&(peer as u64).to_le_bytes());
+        stream
+            .write_all(&hello)
+            .map_err(|e| NetError::io(peer, e))?;
+        let mut reply = [0u8; 12];
+        stream
+            .read_exact(&mut reply)
+            .map_err(|e| NetError::io(peer, e))?;
+        if reply[..4] != HELLO_MAGIC {
+            return Err(not_a_hello(peer));
+        }
+        let got = u64::from_le_bytes(reply[4..].try_into().unwrap()) as usize;
+        if got != peer {
+            return Err(NetError::WrongPartyId {
+                expected: peer,
+                got,
+            });
+        }
+        let link = self.secure(peer, &mut stream, true)?;
+        Ok((peer, stream, link))
+    }
+
+    /// Accept a connection from each party below us, greeting them in parallel. Connections that
+    /// do not open with a hello from a party we still wait for, e.g. port scans, are dropped, and
+    /// we keep accepting until every party below us is greeted or the deadline passes.
+    fn accept_all(&self, listener: &TcpListener) -> NetResult<Vec<Link>> {
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| NetError::io(self.id, e))?;
+        let greeted = Mutex::new(vec![false; self.id]);
+        let missing = || {
+            let greeted = greeted.lock().unwrap();
+            greeted.iter().position(|g| !g).unwrap_or_default()
+        };
+        let all_greeted = || greeted.lock().unwrap().iter().all(|g| *g);
+        std::thread::scope(|s| {
+            let mut greeters = vec![];
+            let accepted = loop {
+                if all_greeted() {
+                    break Ok(());
+                }
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let pending = match stream.try_clone() {
+                            Ok(pending) => pending,
+                            Err(e) => break Err(NetError::io(missing(), e)),
+                        };
+                        let identified = Arc::new(AtomicBool::new(false));
+                        let (greeted, missing) = (&greeted, &missing);
+                        let ours = identified.clone();
+                        let greeter = s.spawn(move || self.greet(stream, greeted, missing, &ours));
+                        greeters.push((greeter, pending, identified));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        if Instant::now() >= self.deadline {
+                            break Err(NetError::Timeout { peer: missing() });
+                        }
+                        std::thread::sleep(ACCEPT_POLL);
+                    }
+                    Err(e) => break Err(NetError::io(self.id, e)),
+                }
+            };
+            // Whoever has not said who they are by now is a stray.
+            for (_, pending, identified) in &greeters {
+                if !identified.load(Ordering::SeqCst) {
+                    let _ = pending.shutdown(Shutdown::Both);
+                }
+            }
+            accepted?;
+            let mut links = vec![];
+            for (greeter, _, _) in greeters {
+                let link = greeter
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))?;
+                links.extend(link);
+            }
+            Ok(links)
+        })
+    }
+
+    /// Read the hello on an accepted stream and answer it. A stream without a valid hello from a
+    /// party we still wait for is dropped, as `None`; once the hello has named the peer, and we
+    /// mark it `identified`, errors are blamed on it.
+    fn greet(
+        &self,
+        mut stream: TcpStream,
+        greeted: &Mutex<Vec<bool>>,
+        missing: &dyn Fn() -> usize,
+        identified: &AtomicBool,
+    ) -> NetResult<Option<Link>> {
+        let suspect = missing();
+        stream
+            .set_nonblocking(false)
+            .map_err(|e| NetError::io(suspect, e))?;
+        self.bound(suspect, &stream)?;
+        let stray = |reason: String| {
+            debug!("Party {} drops a connection: {}", self.id, reason);
+            Ok(None)
+        };
+        let mut hello = [0u8; HELLO_LEN];
+        if let Err(e) = stream.read_exact(&mut hello) {
+            return stray(format!("no hello ({})", e));
+        }
+        if hello[..4] != HELLO_MAGIC {
+            return stray(not_a_hello(suspect).to_string());
+        }
+        let from = u64::from_le_bytes(hello[4..12].try_into().unwrap()) as usize;
+        let to = u64::from_le_bytes(hello[12..].try_into().unwrap()) as usize;
+        if to != self.id {
+            return stray(format!("hello for party {}", to));
+        }
+        {
+            let mut greeted = greeted.lock().unwrap();
+            match greeted.get_mut(from) {
+                Some(g) if !*g => *g = true,
+                _ => return stray(format!("unexpected hello from party {}", from)),
+            }
+            identified.store(true, Ordering::SeqCst);
+        }
+        let mut reply = HELLO_MAGIC.to_vec();
+        reply.extend_from_slice(&(self.id as u64).to_le_bytes());
+        stream
+            .write_all(&reply)
+            .map_err(|e| NetError::io(from, e))?;
+        let link = self.secure(from, &mut stream, false)?;
+        Ok(Some((from, stream, link)))
+    }
+
+    /// If we hold a private key, authenticate the fresh link to `peer`.
+    fn secure(
+        &self,
+        peer: usize,
+        stream: &mut TcpStream,
+        initiator: bool,
+    ) -> NetResult<Option<SecureLink>> {
+        let Some(private_key) = self.private_key else {
+            return Ok(None);
+        };
+        let remote = self.peers[peer]
+            .public_key
+            .as_ref()
+            .expect("checked in finish_init");
+        secure::handshake(stream, peer, initiator, private_key, remote).map(Some)
+    }
+
+    /// Time left until the deadline, or a timeout blamed on `peer`.
+    fn time_left(&self, peer: usize) -> NetResult<Duration> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(NetError::Timeout { peer });
+        }
+        Ok(left)
+    }
+
+    /// Make blocking I/O on `stream` give up at the deadline.
+    fn bound(&self, peer: usize, stream: &TcpStream) -> NetResult<()> {
+        let left = Some(self.time_left(peer)?);
+        stream
+            .set_read_timeout(left)
+            .and_then(|_| stream.set_write_timeout(left))
+            .map_err(|e| NetError::io(peer, e))
+    }
+}
+
+/// Connect to `addr`, retrying per `policy` while nobody listens there yet. Fails with
+/// [ErrorKind::TimedOut] at `deadline`.
+pub(crate) fn dial(
+    addr: SocketAddr,
+    policy: &ConnectPolicy,
+    deadline: Instant,
+) -> std::io::Result<TcpStream> {
+    let mut backoff = policy.initial_backoff;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        match TcpStream::connect_timeout(&addr, left) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if is_transient(&e) => {
+                debug!("{} not reachable ({}); retrying in {:?}", addr, e, backoff);
+                std::thread::sleep(backoff.min(left));
+                backoff = (backoff * 2).min(policy.max_backoff);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Errors worth retrying a connection after: the peer is not (yet) listening.
+fn is_transient(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::TimedOut
+    )
+}
+
+fn not_a_hello(peer: usize) -> NetError {
+    NetError::MalformedFrame {
+        peer,
+        reason: "connection did not open with an mpc-net hello".to_owned(),
+    }
+}
+
+/// Bytes on the wire for `msgs`, length prefixes included, not counting our own message.
+fn received(msgs: &[Vec<u8>], own_id: usize) -> usize {
+    msgs.iter()
+        .enumerate()
+        .filter(|(id, _)| *id != own_id)
+        .map(|(_, b)| b.len() + 8)
+        .sum()
+}
+
+impl Transport for Connections {
+    fn party_id(&self) -> usize {
+        self.id
+    }
+    fn n_parties(&self) -> usize {
+        self.peers.len()
+    }
+    fn is_init(&self) -> bool {
+        self.peers
+            .first()
+            .map(|p| p.stream.is_some())
+            .unwrap_or(false)
+    }
+    fn stats(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+    fn set_timeouts(&mut self, read: Option<Duration>, write: Option<Duration>) -> NetResult<()> {
+        self.read_timeout = read;
+        self.write_timeout = write;
+        for peer in &mut self.peers {
+            if let Some(stream) = &peer.stream {
+                stream
+                    .set_read_timeout(read)
+                    .and_then(|_| stream.set_write_timeout(write))
+                    .map_err(|e| NetError::io(peer.id, e))?;
+            }
+        }
+        Ok(())
+    }
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let timer = start_timer!(|| format!("Broadcast {}", bytes_out.len()));
+        let m = bytes_out.len();
+        let (own_id, max_len) = (self.id, self.max_message_len());
+        self.stats.bytes_sent += (self.peers.len() - 1) * (m + 8);
+        self.stats.broadcasts += 1;
+        let r: Vec<Vec<u8>> = self.with_peers(|peers| {
+            peers
+                .par_iter_mut()
+                .enumerate()
+                .map(|(id, peer)| match id.cmp(&own_id) {
+                    std::cmp::Ordering::Less => {
+                        let bytes_in = peer.read_frame(max_len)?;
+                        peer.write_frame(bytes_out)?;
+                        Ok(bytes_in)
+                    }
+                    std::cmp::Ordering::Equal => Ok(bytes_out.to_vec()),
+                    std::cmp::Ordering::Greater => {
+                        peer.write_frame(bytes_out)?;
+                        peer.read_frame(max_len)
+                    }
+                })
+                .collect::<NetResult<_>>()
+        })?;
+        self.stats.bytes_recv += received(&r, own_id);
+        end_timer!(timer);
+        Ok(r)
+    }
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        let timer = start_timer!(|| format!("To king {}", bytes_out.len()));
+        let m = bytes_out.len();
+        let (own_id, max_len) = (self.id, self.max_message_len());
+        self.stats.to_king += 1;
+        let r = if own_id == king {
+            let r: Vec<Vec<u8>> = self.with_peers(|peers| {
+                peers
+                    .par_iter_mut()
+                    .enumerate()
+                    .map(|(id, peer)| {
+                        if id == own_id {
+                            Ok(bytes_out.to_vec())
+                        } else {
+                            peer.read_frame(max_len)
+                        }
+                    })
+                    .collect::<NetResult<_>>()
+            })?;
+            self.stats.bytes_recv += received(&r, own_id);
+            Some(r)
+        } else {
+            self.stats.bytes_sent += m + 8;
+            self.peers[king].write_frame(bytes_out)?;
+            None
+        };
+        end_timer!(timer);
+        Ok(r)
+    }
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        let own_id = self.id;
+        self.stats.from_king += 1;
+        if own_id == king {
+            let bytes_out = bytes_out.expect("the king must provide bytes to recv_from_king");
+            assert_eq!(bytes_out.len(), self.peers.len());
+            let timer = start_timer!(|| format!("From king {}", bytes_out[0].len()));
+            self.stats.bytes_sent += bytes_out
+                .iter()
+                .enumerate()
+                .filter(|(id, _)| *id != own_id)
+                .map(|(_, b)| b.len() + 8)
+                .sum::<usize>();
+            self.with_peers(|peers| {
+                peers
+                    .par_iter_mut()
+                    .enumerate()
+                    .filter(|p| p.0 != own_id)
+                    .try_for_each(|(id, peer)| peer.write_frame(&bytes_out[id]))
+            })?;
+            end_timer!(timer);
+            Ok(bytes_out[own_id].clone())
+        } else {
+            let max_len = self.max_message_len();
+            let bytes_in = self.peers[king].read_frame(max_len)?;
+            self.stats.bytes_recv += bytes_in.len() + 8;
+            Ok(bytes_in)
+        }
+    }
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.stats.bytes_sent += bytes_out.len() + 8;
+        self.peers[to].write_frame(bytes_out)
+    }
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        let max_len = self.max_message_len();
+        let bytes_in = self.peers[from].read_frame(max_len)?;
+        self.stats.bytes_recv += bytes_in.len() + 8;
+        Ok(bytes_in)
+    }
+    fn into_links(mut self: Box<Self>) -> NetResult<Vec<Option<mux::Link>>> {
+        let (own, max_len) = (self.id, self.max_message_len());
+        std::mem::take(&mut self.peers)
+            .into_iter()
+            .map(|peer| {
+                if peer.id == own {
+                    return Ok(None);
+                }
+                let id = peer.id;
+                let stream = peer.stream.ok_or(NetError::Disconnected { peer: id })?;
+                // The reader waits for whichever channel's message comes next; channels apply
+                // their own read timeouts.
+                let reader = stream
+                    .try_clone()
+                    .and_then(|r| r.set_read_timeout(None).map(|_| r))
+                    .map_err(|e| NetError::io(id, e))?;
+                let link = peer.link.map(|l| Arc::new(Mutex::new(l)));
+                Ok(Some(mux::Link::new(
+                    Half {
+                        peer: id,
+                        stream: reader,
+                        link: link.clone(),
+                        max_len,
+                    },
+                    Half {
+                        peer: id,
+                        stream,
+                        link,
+                        max_len,
+                    },
+                )))
+            })
+            .collect()
+    }
+    fn uninit(&mut self) {
+        for p in &mut self.peers {
+            p.stream = None;
+            p.link = None;
+        }
+    }
+}
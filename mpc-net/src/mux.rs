@@ -0,0 +1,497 @@
+//! Independent logical channels over one session's connections.
+//!
+//! The messages a session sends to a peer form a single ordered stream, so two sub-protocols
+//! cannot run at once without their bytes interleaving. [MpcSession::into_mux] turns a connected
+//! session into a [Mux], from which channels are opened by id. Each channel is an [MpcSession] of
+//! its own, with its own rounds, labels and [Stats]; its messages reach only the channel with the
+//! same id at the other parties, in order. Each can be driven by a thread of its own:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use mpc_net::{MpcMultiNet as Net, MpcNet, MpcSession};
+//!
+//! let mux = MpcSession::init_from_file("data/4.txt", 0).into_mux().unwrap();
+//! let preprocessing = Arc::new(mux.channel(1));
+//! let online = Arc::new(mux.channel(2));
+//! std::thread::scope(|s| {
+//!     s.spawn(|| preprocessing.enter(|| Net::broadcast_bytes(b"triples")));
+//!     online.enter(|| Net::broadcast_bytes(b"inputs"));
+//! });
+//! ```
+//!
+//! All parties must use the same channel ids, but need not open them in the same order: messages
+//! for a channel that is not open yet are held until it is. One thread per peer reads from the
+//! connection and hands each message to its channel.
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+use crate::{
+    session::{Attachments, State},
+    MpcSession, NetError, NetResult, Stats, Transport,
+};
+
+/// Every frame starts with the id of its channel, as a little-endian `u32`.
+const CHANNEL_ID_LEN: usize = 4;
+/// The framing counted in [Stats] per message: a TCP length prefix and the channel id.
+const FRAME_HEADER_LEN: usize = 8 + CHANNEL_ID_LEN;
+
+/// The receiving half of a connection to a peer.
+pub(crate) trait FrameRead: Send {
+    /// Block until the next frame arrives.
+    fn read_frame(&mut self) -> NetResult<Vec<u8>>;
+}
+
+/// The sending half of a connection to a peer.
+pub(crate) trait FrameWrite: Send {
+    fn write_frame(&mut self, bytes: &[u8]) -> NetResult<()>;
+    /// Tell the peer no more frames are coming, once they have all been sent.
+    fn close(&mut self) {}
+}
+
+/// A connection to one peer, taken out of a [Transport] to be shared by channels; see
+/// [Transport::into_links].
+pub struct Link {
+    reader: Box<dyn FrameRead>,
+    writer: Box<dyn FrameWrite>,
+}
+
+impl Link {
+    pub(crate) fn new(reader: impl FrameRead + 'static, writer: impl FrameWrite + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+        }
+    }
+}
+
+impl Debug for Link {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Link")
+    }
+}
+
+type Inbox = Receiver<NetResult<Vec<u8>>>;
+
+/// Where the frames of each channel go.
+#[derive(Debug)]
+struct Routes {
+    channels: HashMap<u32, Route>,
+    /// `failed[j]` is why the connection to party `j` broke, if it did.
+    failed: Vec<Option<NetError>>,
+}
+
+#[derive(Debug)]
+struct Route {
+    /// `to[j]` carries the frames from party `j`, until its connection fails.
+    to: Vec<Option<Sender<NetResult<Vec<u8>>>>>,
+    /// The receiving ends, until the channel is opened.
+    inboxes: Option<Vec<Inbox>>,
+}
+
+impl Routes {
+    /// The route of `channel`, created if it is not open and nothing has arrived for it yet.
+    fn route(&mut self, channel: u32) -> &mut Route {
+        let failed = &self.failed;
+        self.channels.entry(channel).or_insert_with(|| {
+            let (to, inboxes) = failed
+                .iter()
+                .map(|failed| {
+                    let (tx, rx) = mpsc::channel();
+                    match failed {
+                        Some(e) => {
+                            let _ = tx.send(Err(e.clone()));
+                            (None, rx)
+                        }
+                        None => (Some(tx), rx),
+                    }
+                })
+                .unzip();
+            Route {
+                to,
+                inboxes: Some(inboxes),
+            }
+        })
+    }
+
+    /// The connection to `peer` broke with `e`: every channel's next read from it fails.
+    fn fail(&mut self, peer: usize, e: NetError) {
+        for route in self.channels.values_mut() {
+            if let Some(tx) = route.to[peer].take() {
+                let _ = tx.send(Err(e.clone()));
+            }
+        }
+        self.failed[peer] = Some(e);
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().expect("Poisoned Mux")
+}
+
+/// Read frames from `peer` and hand them to their channels, until the connection breaks.
+fn serve(peer: usize, mut reader: Box<dyn FrameRead>, routes: Arc<Mutex<Routes>>) {
+    loop {
+        let frame = reader.read_frame().and_then(|mut frame| {
+            if frame.len() < CHANNEL_ID_LEN {
+                return Err(NetError::MalformedFrame {
+                    peer,
+                    reason: "no channel id".to_owned(),
+                });
+            }
+            let bytes = frame.split_off(CHANNEL_ID_LEN);
+            Ok((u32::from_le_bytes(frame.try_into().unwrap()), bytes))
+        });
+        let mut routes = lock(&routes);
+        match frame {
+            Ok((channel, bytes)) => {
+                if let Some(tx) = &routes.route(channel).to[peer] {
+                    let _ = tx.send(Ok(bytes));
+                }
+            }
+            Err(e) => return routes.fail(peer, e),
+        }
+    }
+}
+
+/// What the channels of a [Mux] share: the sending halves, and the routes the readers fill.
+struct Shared {
+    id: usize,
+    writers: Vec<Option<Mutex<Box<dyn FrameWrite>>>>,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shared")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Shared {
+    fn send(&self, to: usize, channel: u32, bytes: &[u8]) -> NetResult<()> {
+        let writer = self.writers[to]
+            .as_ref()
+            .ok_or(NetError::Disconnected { peer: to })?;
+        let mut frame = Vec::with_capacity(CHANNEL_ID_LEN + bytes.len());
+        frame.extend_from_slice(&channel.to_le_bytes());
+        frame.extend_from_slice(bytes);
+        lock(writer).write_frame(&frame)
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        for writer in self.writers.iter_mut().flatten() {
+            if let Ok(writer) = writer.get_mut() {
+                writer.close();
+            }
+        }
+    }
+}
+
+/// A session's connections, split into channels; see the [module docs](self).
+///
+/// The connections stay open as long as the [Mux] or any of its channels does.
+#[derive(Debug)]
+pub struct Mux {
+    shared: Arc<Shared>,
+    /// What every channel starts from.
+    state: State,
+    attachments: Attachments,
+}
+
+impl Mux {
+    pub(crate) fn new(
+        id: usize,
+        links: Vec<Option<Link>>,
+        state: State,
+        attachments: Attachments,
+    ) -> Self {
+        let routes = Arc::new(Mutex::new(Routes {
+            channels: HashMap::new(),
+            failed: vec![None; links.len()],
+        }));
+        let writers = links
+            .into_iter()
+            .enumerate()
+            .map(|(peer, link)| {
+                link.map(|Link { reader, writer }| {
+                    let routes = routes.clone();
+                    std::thread::spawn(move || serve(peer, reader, routes));
+                    Mutex::new(writer)
+                })
+            })
+            .collect();
+        Self {
+            shared: Arc::new(Shared {
+                id,
+                writers,
+                routes,
+            }),
+            state,
+            attachments,
+        }
+    }
+
+    /// What is my party number (0 to n-1)?
+    pub fn party_id(&self) -> usize {
+        self.shared.id
+    }
+
+    /// How many parties are there?
+    pub fn n_parties(&self) -> usize {
+        self.shared.writers.len()
+    }
+
+    /// Open channel `id`, as a session with the id, king and attachments of the one this came
+    /// from. Once it is dropped, `id` can be opened again.
+    ///
+    /// Panics if channel `id` is already open.
+    pub fn channel(&self, id: u32) -> MpcSession {
+        let from = lock(&self.shared.routes)
+            .route(id)
+            .inboxes
+            .take()
+            .unwrap_or_else(|| panic!("channel {} is already open", id));
+        let channel = Channel {
+            id,
+            shared: self.shared.clone(),
+            from,
+            stats: Stats::default(),
+            read_timeout: None,
+            connected: true,
+        };
+        MpcSession::from_parts(
+            Box::new(channel),
+            self.state.clone(),
+            self.attachments.clone(),
+        )
+    }
+}
+
+/// One channel of a [Mux].
+#[derive(Debug)]
+struct Channel {
+    id: u32,
+    shared: Arc<Shared>,
+    /// `from[j]` carries this channel's messages from party `j`.
+    from: Vec<Inbox>,
+    stats: Stats,
+    read_timeout: Option<Duration>,
+    connected: bool,
+}
+
+impl Channel {
+    fn send(&self, to: usize, bytes: &[u8]) -> NetResult<()> {
+        self.shared.send(to, self.id, bytes)
+    }
+
+    /// Receive one message from every other party, with `own` in our slot.
+    fn recv_all(&mut self, own: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let r = (0..self.n_parties())
+            .map(|id| {
+                if id == self.party_id() {
+                    Ok(own.to_vec())
+                } else {
+                    self.recv(id)
+                }
+            })
+            .collect::<NetResult<Vec<_>>>()?;
+        self.stats.bytes_recv +=
+            (r.iter().map(Vec::len).sum::<usize>() - own.len()) + (r.len() - 1) * FRAME_HEADER_LEN;
+        Ok(r)
+    }
+
+    fn recv(&self, from: usize) -> NetResult<Vec<u8>> {
+        let rx = &self.from[from];
+        match self.read_timeout {
+            None => rx.recv().map_err(|_| NetError::Disconnected { peer: from }),
+            Some(t) => rx.recv_timeout(t).map_err(|e| match e {
+                RecvTimeoutError::Timeout => NetError::Timeout { peer: from },
+                RecvTimeoutError::Disconnected => NetError::Disconnected { peer: from },
+            }),
+        }?
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        if let Ok(mut routes) = self.shared.routes.lock() {
+            routes.channels.remove(&self.id);
+        }
+    }
+}
+
+impl Transport for Channel {
+    fn party_id(&self) -> usize {
+        self.shared.id
+    }
+    fn n_parties(&self) -> usize {
+        self.shared.writers.len()
+    }
+    fn is_init(&self) -> bool {
+        self.connected
+    }
+    fn uninit(&mut self) {
+        self.connected = false;
+    }
+    fn stats(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+    /// Peers always drain their connections, so only the read deadline applies.
+    fn set_timeouts(&mut self, read: Option<Duration>, _write: Option<Duration>) -> NetResult<()> {
+        self.read_timeout = read;
+        Ok(())
+    }
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let n = self.n_parties();
+        self.stats.bytes_sent += (n - 1) * (bytes_out.len() + FRAME_HEADER_LEN);
+        self.stats.broadcasts += 1;
+        for id in (0..n).filter(|&id| id != self.party_id()) {
+            self.send(id, bytes_out)?;
+        }
+        self.recv_all(bytes_out)
+    }
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        self.stats.to_king += 1;
+        if self.party_id() == king {
+            self.recv_all(bytes_out).map(Some)
+        } else {
+            self.stats.bytes_sent += bytes_out.len() + FRAME_HEADER_LEN;
+            self.send(king, bytes_out)?;
+            Ok(None)
+        }
+    }
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        self.stats.from_king += 1;
+        if self.party_id() == king {
+            let bytes_out = bytes_out.expect("the king must provide bytes to recv_from_king");
+            assert_eq!(bytes_out.len(), self.n_parties());
+            for (id, bytes) in bytes_out.iter().enumerate() {
+                if id != self.party_id() {
+                    self.stats.bytes_sent += bytes.len() + FRAME_HEADER_LEN;
+                    self.send(id, bytes)?;
+                }
+            }
+            Ok(bytes_out[self.party_id()].clone())
+        } else {
+            let bytes_in = self.recv(king)?;
+            self.stats.bytes_recv += bytes_in.len() + FRAME_HEADER_LEN;
+            Ok(bytes_in)
+        }
+    }
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.stats.bytes_sent += bytes_out.len() + FRAME_HEADER_LEN;
+        self.send(to, bytes_out)
+    }
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        let bytes_in = self.recv(from)?;
+        self.stats.bytes_recv += bytes_in.len() + FRAME_HEADER_LEN;
+        Ok(bytes_in)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{local::LocalTransport, MpcMultiNet as Net, MpcNet};
+
+    /// One mux per party, over an in-process mesh.
+    fn muxes(n: usize) -> Vec<Mux> {
+        LocalTransport::mesh(n)
+            .into_iter()
+            .map(|t| MpcSession::from_transport(t).into_mux().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn channels_run_concurrently() {
+        let outputs: Vec<Vec<Vec<u8>>> = std::thread::scope(|s| {
+            let handles: Vec<_> = muxes(3)
+                .into_iter()
+                .map(|mux| {
+                    s.spawn(move || {
+                        let id = mux.party_id() as u8;
+                        // Parties open the channels in different orders, and each runs its own
+                        // rounds on a thread of its own.
+                        let mut ids = vec![1, 2];
+                        if id == 1 {
+                            ids.reverse();
+                        }
+                        let channels: Vec<_> = ids
+                            .into_iter()
+                            .map(|c| (c, Arc::new(mux.channel(c))))
+                            .collect();
+                        std::thread::scope(|s| {
+                            let handles: Vec<_> = channels
+                                .iter()
+                                .map(|(c, session)| {
+                                    s.spawn(move || {
+                                        session.enter(|| {
+                                            (0..20u8)
+                                                .map(|r| {
+                                                    let all =
+                                                        Net::broadcast_bytes(&[*c as u8, r, id]);
+                                                    assert!(
+                                                        all.iter()
+                                                            .enumerate()
+                                                            .all(|(j, b)| b
+                                                                == &[*c as u8, r, j as u8])
+                                                    );
+                                                    Net::king_compute(&[r], |all| all)
+                                                })
+                                                .collect::<Vec<_>>()
+                                        })
+                                    })
+                                })
+                                .collect();
+                            let mut out: Vec<_> = handles
+                                .into_iter()
+                                .map(|h| h.join().unwrap())
+                                .zip(channels.iter().map(|(c, _)| *c))
+                                .collect();
+                            out.sort_by_key(|(_, c)| *c);
+                            out.into_iter().map(|(o, _)| o.concat()).collect()
+                        })
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for out in outputs {
+            assert_eq!(out.len(), 2);
+            assert!(out.iter().all(|o| o == &(0..20u8).collect::<Vec<_>>()));
+        }
+    }
+
+    #[test]
+    fn messages_wait_for_their_channel() {
+        let mut muxes = muxes(2);
+        let b = muxes.pop().unwrap();
+        let a = muxes.pop().unwrap();
+        let a7 = a.channel(7);
+        a7.send_bytes_to(1, b"early");
+        a.channel(8).send_bytes_to(1, b"other");
+        let b7 = b.channel(7);
+        assert_eq!(b7.recv_bytes_from(0), b"early");
+        assert_eq!(b.channel(8).recv_bytes_from(0), b"other");
+
+        // A broken connection fails the channels open on it, and those opened later.
+        drop((a, a7));
+        assert!(b7.try_recv_bytes_from(0).is_err());
+        assert!(b.channel(9).try_recv_bytes_from(0).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//! A star topology: every party keeps a single connection, to a relay that forwards all traffic.
+//!
+//! Parties behind NATs can only dial out, and a mesh of `n` parties needs `n(n-1)/2` sockets with
+//! every party reachable by every other. Through a [Relay], each party only dials the relay (see
+//! [crate::MpcSession::try_init_via_relay], or `relay` in a [crate::config] file) and sees the
+//! same behaviour as over the mesh: broadcasts, king operations and point-to-point messages, with
+//! the messages from each peer delivered in order.
+//!
+//! The relay sees every message in the clear; authenticated, encrypted channels are only
+//! available on the mesh.
+//!
+//! On the wire, a party opens with `[RELAY_MAGIC, id, n]` and the relay answers `RELAY_MAGIC`
+//! once all `n` parties are in. A party then sends `[to, len, payload]` (`to` is [TO_ALL] for a
+//! broadcast) and receives `[from, len, payload]`, where `len` is [GONE] if `from` has left.
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    config::DEFAULT_MAX_MESSAGE_LEN,
+    multi::{check_frame_len, dial},
+    ConnectPolicy, NetError, NetResult, Stats, Transport,
+};
+
+const RELAY_MAGIC: [u8; 4] = *b"MPR\x01";
+/// Destination of a broadcast.
+const TO_ALL: u64 = u64::MAX;
+/// Length announcing that a party has left.
+const GONE: u64 = u64::MAX;
+/// How long the relay waits for the hello of a fresh connection.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A relay serving one session at a time.
+#[derive(Debug)]
+pub struct Relay {
+    listener: TcpListener,
+}
+
+impl Relay {
+    /// Listen for parties on `addr`.
+    pub fn bind(addr: impl ToSocketAddrs) -> NetResult<Self> {
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| NetError::Relay(format!("cannot listen: {}", e)))?;
+        Ok(Self { listener })
+    }
+
+    /// Where the relay listens.
+    pub fn local_addr(&self) -> NetResult<SocketAddr> {
+        self.listener
+            .local_addr()
+            .map_err(|e| NetError::Relay(e.to_string()))
+    }
+
+    /// Wait for all parties of a session, then forward their messages until they have all left.
+    pub fn serve_session(&self) -> NetResult<()> {
+        let parties = self.gather()?;
+        let n = parties.len();
+        let mut writers = Vec::with_capacity(n);
+        for (id, mut stream) in parties.iter().enumerate() {
+            stream
+                .write_all(&RELAY_MAGIC)
+                .and_then(|_| stream.try_clone())
+                .map(|s| writers.push(s))
+                .map_err(|e| NetError::Relay(format!("party {}: {}", id, e)))?;
+        }
+        debug!("Relaying between {} parties", n);
+        // Each party gets a queue, drained by its own writer, so a reader never blocks on a
+        // party that is busy writing.
+        let (queues, outboxes): (Vec<_>, Vec<_>) = (0..n).map(|_| mpsc::channel()).unzip();
+        std::thread::scope(|s| {
+            for (writer, outbox) in writers.into_iter().zip(outboxes) {
+                s.spawn(move || write_out(writer, outbox));
+            }
+            for (id, reader) in parties.into_iter().enumerate() {
+                let queues = queues.clone();
+                s.spawn(move || read_in(id, reader, &queues));
+            }
+            drop(queues);
+        });
+        debug!("Session over");
+        Ok(())
+    }
+
+    /// Accept parties until one per id of the session has said hello.
+    fn gather(&self) -> NetResult<Vec<TcpStream>> {
+        let mut parties: Vec<Option<TcpStream>> = vec![];
+        while parties.is_empty() || parties.iter().any(Option::is_none) {
+            let (mut stream, addr) = self
+                .listener
+                .accept()
+                .map_err(|e| NetError::Relay(format!("cannot accept: {}", e)))?;
+            let mut hello = [0u8; 20];
+            let read = stream
+                .set_read_timeout(Some(HELLO_TIMEOUT))
+                .and_then(|_| stream.read_exact(&mut hello))
+                .and_then(|_| stream.set_read_timeout(None))
+                .and_then(|_| stream.set_nodelay(true));
+            let id = u64::from_le_bytes(hello[4..12].try_into().unwrap()) as usize;
+            let n = u64::from_le_bytes(hello[12..].try_into().unwrap()) as usize;
+            let problem = if let Err(e) = read {
+                Some(e.to_string())
+            } else if hello[..4] != RELAY_MAGIC {
+                Some("not an mpc-net relay hello".to_owned())
+            } else if parties.is_empty() && n == 0 {
+                Some("a session needs parties".to_owned())
+            } else if !parties.is_empty() && n != parties.len() {
+                Some(format!("{} parties, not {}", n, parties.len()))
+            } else if id >= n {
+                Some(format!("party {} of {}", id, n))
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                warn!("Turning away {}: {}", addr, problem);
+                continue;
+            }
+            if parties.is_empty() {
+                parties.resize_with(n, || None);
+            }
+            if parties[id].is_some() {
+                warn!("Turning away {}: party {} is already here", addr, id);
+                continue;
+            }
+            debug!("Party {} is {}", id, addr);
+            parties[id] = Some(stream);
+        }
+        Ok(parties.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+/// Forward the messages of party `from` until it leaves, then tell the others.
+fn read_in(from: usize, mut stream: TcpStream, queues: &[mpsc::Sender<Arc<Vec<u8>>>]) {
+    let forward = |to: usize, msg: &Arc<Vec<u8>>| {
+        // A party that has left no longer drains its queue; that is fine.
+        let _ = queues[to].send(msg.clone());
+    };
+    loop {
+        let mut header = [0u8; 16];
+        if let Err(e) = stream.read_exact(&mut header) {
+            if e.kind() != ErrorKind::UnexpectedEof {
+                warn!("Party {}: {}", from, e);
+            }
+            break;
+        }
+        let to = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let len = match check_frame_len(
+            from,
+            header[8..].try_into().unwrap(),
+            DEFAULT_MAX_MESSAGE_LEN,
+        ) {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("{}", e);
+                break;
+            }
+        };
+        let mut msg = Vec::with_capacity(16 + len);
+        msg.extend_from_slice(&(from as u64).to_le_bytes());
+        msg.extend_from_slice(&header[8..]);
+        msg.resize(16 + len, 0);
+        if let Err(e) = stream.read_exact(&mut msg[16..]) {
+            warn!("Party {}: {}", from, e);
+            break;
+        }
+        let msg = Arc::new(msg);
+        match to {
+            TO_ALL => (0..queues.len())
+                .filter(|to| *to != from)
+                .for_each(|to| forward(to, &msg)),
+            to if (to as usize) < queues.len() && to as usize != from => forward(to as usize, &msg),
+            to => {
+                warn!("Party {} sent to party {}, which does not exist", from, to);
+                break;
+            }
+        }
+    }
+    debug!("Party {} left", from);
+    let mut gone = (from as u64).to_le_bytes().to_vec();
+    gone.extend_from_slice(&GONE.to_le_bytes());
+    let gone = Arc::new(gone);
+    (0..queues.len())
+        .filter(|to| *to != from)
+        .for_each(|to| forward(to, &gone));
+}
+
+/// Write out the queue of one party, until every reader is done.
+fn write_out(mut stream: TcpStream, outbox: mpsc::Receiver<Arc<Vec<u8>>>) {
+    let mut open = true;
+    for msg in outbox {
+        if open {
+            open = stream.write_all(&msg).is_ok();
+        }
+    }
+}
+
+/// One party's link to a [Relay].
+///
+/// [Stats] count what crosses this link: a broadcast is sent once, with a 16-byte header.
+#[derive(Debug)]
+pub(crate) struct RelayConnection {
+    id: usize,
+    n: usize,
+    stream: Option<TcpStream>,
+    /// Messages that arrived while we were waiting for another party, by sender.
+    inbox: Vec<VecDeque<Vec<u8>>>,
+    /// Parties that have left.
+    gone: Vec<bool>,
+    stats: Stats,
+    /// Longest message we accept.
+    pub(crate) max_message_len: usize,
+}
+
+impl RelayConnection {
+    /// Join the session of `n` parties at the relay at `relay` as party `id`. Returns once every
+    /// party has joined, or fails after `policy.timeout`.
+    pub(crate) fn connect(
+        relay: SocketAddr,
+        id: usize,
+        n: usize,
+        policy: &ConnectPolicy,
+    ) -> NetResult<Self> {
+        if id >= n {
+            return Err(NetError::Config(format!(
+                "party {} does not exist; the session has {} parties",
+                id, n
+            )));
+        }
+        let deadline = Instant::now() + policy.timeout;
+        let relay_error = |e: io::Error| NetError::Relay(format!("{}: {}", relay, e));
+        let mut stream = dial(relay, policy, deadline).map_err(relay_error)?;
+        let mut hello = RELAY_MAGIC.to_vec();
+        hello.extend_from_slice(&(id as u64).to_le_bytes());
+        hello.extend_from_slice(&(n as u64).to_le_bytes());
+        let mut ready = [0u8; 4];
+        let left = deadline.saturating_duration_since(Instant::now());
+        stream
+            .set_nodelay(true)
+            .and_then(|_| stream.set_read_timeout(Some(left.max(Duration::from_millis(1)))))
+            .and_then(|_| stream.write_all(&hello))
+            .and_then(|_| stream.read_exact(&mut ready))
+            .and_then(|_| stream.set_read_timeout(None))
+            .map_err(|e| match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                    NetError::Relay(format!("{}: not every party joined in time", relay))
+                }
+                _ => relay_error(e),
+            })?;
+        if ready != RELAY_MAGIC {
+            return Err(NetError::Relay(format!(
+                "{} is not an mpc-net relay",
+                relay
+            )));
+        }
+        Ok(Self {
+            id,
+            n,
+            stream: Some(stream),
+            inbox: vec![VecDeque::new(); n],
+            gone: vec![false; n],
+            stats: Stats::default(),
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+        })
+    }
+
+    fn stream(&mut self) -> NetResult<&mut TcpStream> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| NetError::Relay("not connected".to_owned()))
+    }
+
+    /// Hand `bytes` to the relay for party `to`, or [TO_ALL].
+    fn post(&mut self, to: u64, bytes: &[u8]) -> NetResult<()> {
+        let mut msg = Vec::with_capacity(16 + bytes.len());
+        msg.extend_from_slice(&to.to_le_bytes());
+        msg.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        msg.extend_from_slice(bytes);
+        self.stats.bytes_sent += msg.len();
+        self.stream()?
+            .write_all(&msg)
+            .map_err(|e| NetError::Relay(format!("cannot write to the relay: {}", e)))
+    }
+
+    /// The next message from party `from`, reading (and setting aside) those of others until it
+    /// arrives.
+    fn collect(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        let n = self.n;
+        loop {
+            if let Some(msg) = self.inbox[from].pop_front() {
+                // As the relay delivered it: with a sender and a length.
+                self.stats.bytes_recv += 16 + msg.len();
+                return Ok(msg);
+            }
+            if self.gone[from] {
+                return Err(NetError::Disconnected { peer: from });
+            }
+            let read_error = |e: io::Error| match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => NetError::Timeout { peer: from },
+                _ => NetError::Relay(format!("cannot read from the relay: {}", e)),
+            };
+            let max_len = self.max_message_len;
+            let stream = self.stream()?;
+            let mut header = [0u8; 16];
+            stream.read_exact(&mut header).map_err(read_error)?;
+            let sender = u64::from_le_bytes(header[..8].try_into().unwrap()) as usize;
+            if sender >= n {
+                return Err(NetError::Relay(format!(
+                    "message from party {}, which does not exist",
+                    sender
+                )));
+            }
+            if u64::from_le_bytes(header[8..].try_into().unwrap()) == GONE {
+                self.gone[sender] = true;
+                continue;
+            }
+            let size = header[8..].try_into().unwrap();
+            let mut msg = vec![0u8; check_frame_len(sender, size, max_len)?];
+            stream.read_exact(&mut msg).map_err(read_error)?;
+            self.inbox[sender].push_back(msg);
+        }
+    }
+
+    /// The next message of every other party, and `own` for ourselves.
+    fn collect_all(&mut self, own: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        (0..self.n)
+            .map(|id| {
+                if id == self.id {
+                    Ok(own.to_vec())
+                } else {
+                    self.collect(id)
+                }
+            })
+            .collect()
+    }
+}
+
+impl Transport for RelayConnection {
+    fn party_id(&self) -> usize {
+        self.id
+    }
+    fn n_parties(&self) -> usize {
+        self.n
+    }
+    fn is_init(&self) -> bool {
+        self.stream.is_some()
+    }
+    fn stats(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+    fn set_timeouts(&mut self, read: Option<Duration>, write: Option<Duration>) -> NetResult<()> {
+        let stream = self.stream()?;
+        stream
+            .set_read_timeout(read)
+            .and_then(|_| stream.set_write_timeout(write))
+            .map_err(|e| NetError::Relay(e.to_string()))
+    }
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        self.stats.broadcasts += 1;
+        self.post(TO_ALL, bytes_out)?;
+        self.collect_all(bytes_out)
+    }
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        self.stats.to_king += 1;
+        if self.id == king {
+            self.collect_all(bytes_out).map(Some)
+        } else {
+            self.post(king as u64, bytes_out).map(|()| None)
+        }
+    }
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        self.stats.from_king += 1;
+        if self.id != king {
+            return self.collect(king);
+        }
+        let bytes_out = bytes_out.expect("the king must provide bytes to recv_from_king");
+        assert_eq!(bytes_out.len(), self.n);
+        for (id, bytes) in bytes_out.iter().enumerate() {
+            if id != self.id {
+                self.post(id as u64, bytes)?;
+            }
+        }
+        Ok(bytes_out[self.id].clone())
+    }
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.post(to as u64, bytes_out)
+    }
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        self.collect(from)
+    }
+    fn uninit(&mut self) {
+        self.stream = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MpcSession;
+
+    /// Run a relay for one session, and `f` as each of `n` parties joining through it.
+    fn through_relay<R: Send + 'static>(
+        n: usize,
+        f: impl Fn(MpcSession) -> R + Send + Sync + 'static,
+    ) -> Vec<R> {
+        let relay = Relay::bind("127.0.0.1:0").unwrap();
+        let addr = relay.local_addr().unwrap().to_string();
+        let relay = std::thread::spawn(move || relay.serve_session());
+        let f = Arc::new(f);
+        let parties: Vec<_> = (0..n)
+            .map(|id| {
+                let (addr, f) = (addr.clone(), f.clone());
+                std::thread::spawn(move || f(MpcSession::try_init_via_relay(&addr, id, n).unwrap()))
+            })
+            .collect();
+        let outputs = parties.into_iter().map(|p| p.join().unwrap()).collect();
+        relay.join().unwrap().unwrap();
+        outputs
+    }
+
+    #[test]
+    fn behaves_like_the_mesh() {
+        let outputs = through_relay(4, |session| {
+            let id = session.party_id();
+            session
+                .set_timeouts(Some(Duration::from_secs(5)), None)
+                .unwrap();
+            let all = session.broadcast_bytes(&vec![id as u8; 1000 * id]);
+            let sum = session.king_compute(&[id as u8], |all| {
+                let sum = all.iter().map(|b| b[0]).sum();
+                vec![vec![sum]; all.len()]
+            });
+            // Pass a token around the ring, in both directions.
+            let next = (id + 1) % 4;
+            let prev = (id + 3) % 4;
+            session.send_bytes_to(next, &[id as u8]);
+            let from_prev = session.exchange_bytes(prev, &[id as u8]);
+            let from_next = session.recv_bytes_from(next);
+            (all, sum, from_prev, from_next)
+        });
+        for (id, (all, sum, from_prev, from_next)) in outputs.into_iter().enumerate() {
+            for (i, bytes) in all.iter().enumerate() {
+                assert_eq!(bytes, &vec![i as u8; 1000 * i]);
+            }
+            assert_eq!(sum, vec![6]);
+            assert_eq!(from_prev, vec![((id + 3) % 4) as u8]);
+            assert_eq!(from_next, vec![((id + 1) % 4) as u8]);
+        }
+    }
+
+    #[test]
+    fn reports_departed_party() {
+        let outputs = through_relay(3, |session| {
+            if session.party_id() == 2 {
+                return Ok(vec![]);
+            }
+            session.try_broadcast_bytes(&[0])
+        });
+        for r in &outputs[..2] {
+            assert!(matches!(r, Err(NetError::Disconnected { peer: 2 })));
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Authenticated, encrypted links between parties.
+//!
+//! Each party has a static X25519 keypair. When every line of the host file carries a public key
+//! after the address,
+//!
+//! ```text
+//! 127.0.0.1:8000 4c1f...e2
+//! 127.0.0.1:8001 9a07...5d
+//! ```
+//!
+//! and the party passes its own private key to [crate::MpcSession::try_init_secure_from_file],
+//! every link runs a Noise `KK` handshake (both sides know each other's static key in advance),
+//! and all later messages are sent as ChaCha20-Poly1305 records. A peer that does not hold the
+//! private key for its line, or a man in the middle, fails the handshake with
+//! [NetError::Authentication]; tampered records fail the same way.
+use std::io::{Read, Write};
+
+use snow::{params::NoiseParams, Builder, HandshakeState, TransportState};
+
+use crate::{NetError, NetResult};
+
+const NOISE_PARAMS: &str = "Noise_KK_25519_ChaChaPoly_BLAKE2s";
+/// Binds the handshake to this protocol, so keys cannot be replayed into another one.
+const PROLOGUE: &[u8] = b"mpc-net secure channel v1";
+/// Noise limits a message to 64 KiB, including the AEAD tag.
+const MAX_RECORD_LEN: usize = u16::MAX as usize;
+const TAG_LEN: usize = 16;
+/// Largest plaintext that fits in one record.
+pub(crate) const MAX_RECORD_PAYLOAD: usize = MAX_RECORD_LEN - TAG_LEN;
+
+/// A party's static keypair.
+#[derive(Clone)]
+pub struct Keypair {
+    pub private: Vec<u8>,
+    pub public: Vec<u8>,
+}
+
+impl std::fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keypair")
+            .field("public", &to_hex(&self.public))
+            .finish_non_exhaustive()
+    }
+}
+
+fn params() -> NoiseParams {
+    NOISE_PARAMS.parse().expect("valid noise parameters")
+}
+
+/// Generate a fresh static keypair.
+pub fn generate_keypair() -> Keypair {
+    let kp = Builder::new(params())
+        .generate_keypair()
+        .expect("keypair generation");
+    Keypair {
+        private: kp.private,
+        public: kp.public,
+    }
+}
+
+/// Lower-case hex encoding, as used for keys in host and key files.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse a hex-encoded key.
+pub fn from_hex(s: &str) -> NetResult<Vec<u8>> {
+    let s = s.trim();
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(NetError::Config(format!("bad hex key: {}", s)));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|_| NetError::Config(format!("bad hex key: {}", s)))
+        })
+        .collect()
+}
+
+fn auth_error(peer: usize, e: snow::Error) -> NetError {
+    NetError::Authentication {
+        peer,
+        reason: e.to_string(),
+    }
+}
+
+pub(crate) fn write_record(peer: usize, stream: &mut impl Write, record: &[u8]) -> NetResult<()> {
+    stream
+        .write_all(&(record.len() as u16).to_le_bytes())
+        .and_then(|_| stream.write_all(record))
+        .map_err(|e| NetError::io(peer, e))
+}
+
+pub(crate) fn read_record(peer: usize, stream: &mut impl Read) -> NetResult<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream
+        .read_exact(&mut len)
+        .map_err(|e| NetError::io(peer, e))?;
+    let mut record = vec![0u8; u16::from_le_bytes(len) as usize];
+    stream
+        .read_exact(&mut record)
+        .map_err(|e| NetError::io(peer, e))?;
+    Ok(record)
+}
+
+/// Run the handshake with `peer` over `stream`. The party that opened the connection initiates.
+pub(crate) fn handshake<S: Read + Write>(
+    stream: &mut S,
+    peer: usize,
+    initiator: bool,
+    local_private: &[u8],
+    remote_public: &[u8],
+) -> NetResult<SecureLink> {
+    let builder = Builder::new(params())
+        .prologue(PROLOGUE)
+        .local_private_key(local_private)
+        .remote_public_key(remote_public);
+    let mut hs: HandshakeState = if initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    }
+    .map_err(|e| auth_error(peer, e))?;
+    let mut buf = vec![0u8; MAX_RECORD_LEN];
+    // KK is one message each way: -> e, es, ss; <- e, ee, se.
+    let mut my_turn = initiator;
+    while !hs.is_handshake_finished() {
+        if my_turn {
+            let len = hs
+                .write_message(&[], &mut buf)
+                .map_err(|e| auth_error(peer, e))?;
+            write_record(peer, stream, &buf[..len])?;
+        } else {
+            let record = read_record(peer, stream)?;
+            hs.read_message(&record, &mut buf)
+                .map_err(|e| auth_error(peer, e))?;
+        }
+        my_turn = !my_turn;
+    }
+    Ok(SecureLink {
+        state: hs.into_transport_mode().map_err(|e| auth_error(peer, e))?,
+    })
+}
+
+/// The encryption state of one link after a successful handshake.
+pub(crate) struct SecureLink {
+    state: TransportState,
+}
+
+impl std::fmt::Debug for SecureLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecureLink")
+    }
+}
+
+impl SecureLink {
+    /// Encrypt and send `bytes` (at most [MAX_RECORD_PAYLOAD]) as one record.
+    pub(crate) fn send(
+        &mut self,
+        peer: usize,
+        stream: &mut impl Write,
+        bytes: &[u8],
+    ) -> NetResult<()> {
+        let record = self.encrypt(peer, bytes)?;
+        write_record(peer, stream, &record)
+    }
+
+    /// Receive and decrypt one record.
+    pub(crate) fn recv(&mut self, peer: usize, stream: &mut impl Read) -> NetResult<Vec<u8>> {
+        let record = read_record(peer, stream)?;
+        self.decrypt(peer, &record)
+    }
+
+    /// Encrypt `bytes` (at most [MAX_RECORD_PAYLOAD]) into a record, without its length prefix.
+    pub(crate) fn encrypt(&mut self, peer: usize, bytes: &[u8]) -> NetResult<Vec<u8>> {
+        let mut buf = vec![0u8; bytes.len() + TAG_LEN];
+        let len = self
+            .state
+            .write_message(bytes, &mut buf)
+            .map_err(|e| auth_error(peer, e))?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Decrypt a record received from `peer`.
+    pub(crate) fn decrypt(&mut self, peer: usize, record: &[u8]) -> NetResult<Vec<u8>> {
+        let mut buf = vec![0u8; record.len()];
+        let len = self
+            .state
+            .read_message(record, &mut buf)
+            .map_err(|e| auth_error(peer, e))?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
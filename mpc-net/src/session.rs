@@ -0,0 +1,1438 @@
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    net::ToSocketAddrs,
+    panic::Location,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    emulate::{Emulated, NetworkModel},
+    multi::Connections,
+    mux::Mux,
+    relay::RelayConnection,
+    tag::{self, Op, Tag},
+    transcript::{Recorder, Replay},
+    ConnectPolicy, NetConfig, NetError, NetResult, PhaseStats, Stats, Transport,
+};
+
+thread_local! {
+    /// The session bound to this thread by [MpcSession::enter], if any.
+    static CURRENT_SESSION: RefCell<Option<Arc<MpcSession>>> = const { RefCell::new(None) };
+}
+
+/// How many [MpcSession::enter] calls are running, on any thread.
+pub(crate) static ENTERED: AtomicUsize = AtomicUsize::new(0);
+
+/// An owned handle on one MPC session: a party id, a set of peers and the connections to them.
+///
+/// Unlike the static [crate::MpcMultiNet] API, several sessions (with different party sets) can
+/// live in one process. Code written against [crate::MpcNet] (e.g. the share types in
+/// `mpc-algebra`) is pointed at a session by running it inside [MpcSession::enter].
+///
+/// Every message is tagged with the session id, a round counter and the current protocol label
+/// (see [MpcSession::with_label]); a party that receives a message from a different point of the
+/// protocol gets [crate::NetError::Desync].
+#[derive(Debug)]
+pub struct MpcSession {
+    inner: Mutex<Inner>,
+    attachments: Mutex<Attachments>,
+    checks: Mutex<Checks>,
+}
+
+/// The checks registered with [MpcSession::on_checkpoint], in order.
+#[derive(Clone, Default)]
+struct Checks(Vec<Arc<dyn Fn() -> NetResult<()> + Send + Sync>>);
+
+impl fmt::Debug for Checks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Checks({})", self.0.len())
+    }
+}
+
+/// Per-session values of protocols built on the network, one per type; see
+/// [MpcSession::attach].
+#[derive(Clone, Default)]
+pub(crate) struct Attachments(HashMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+impl fmt::Debug for Attachments {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Attachments({})", self.0.len())
+    }
+}
+
+/// Which party is king, i.e. collects messages in [MpcSession::send_bytes_to_king] and answers
+/// in [MpcSession::recv_bytes_from_king].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct King {
+    /// The king of the first king operation.
+    pub first: usize,
+    pub rotate: KingRotation,
+}
+
+/// How the king moves on during a session, to spread its extra work over the parties.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KingRotation {
+    /// Always [King::first].
+    #[default]
+    Never,
+    /// The next party after each king call: a send to the king with the reply to it, or a reply
+    /// on its own.
+    PerCall,
+    /// Party `first + r` (mod n), where `r` is [MpcSession::round] when the king call starts.
+    PerRound,
+}
+
+/// Whether a session may take shortcuts that are only sound in tests.
+///
+/// The network layer carries this for the protocols built on it: e.g. the SPDZ shares of
+/// `mpc-algebra` fabricate MACs with a global key in an [Security::Insecure] session, and refuse to
+/// in a [Security::Production] one. It is fixed when the session is created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Security {
+    /// Testing and benchmarking: shortcuts that leak secrets are allowed.
+    #[default]
+    Insecure,
+    /// A real deployment: anything that would break security fails instead.
+    Production,
+}
+
+impl King {
+    /// Always party `id`.
+    pub fn fixed(id: usize) -> Self {
+        Self {
+            first: id,
+            rotate: KingRotation::Never,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    transport: Box<dyn Transport>,
+    state: State,
+}
+
+impl Inner {
+    /// The stats of the phase `tag` belongs to, charged with an operation begun at `started`.
+    fn phase(&mut self, tag: &Tag, started: Instant) -> &mut PhaseStats {
+        let n = self.transport.n_parties();
+        self.transport
+            .stats()
+            .phase(&tag.label, n)
+            .op(tag.op, started)
+    }
+}
+
+/// Where a session is in the protocol, and who is king; everything but the transport.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct State {
+    session: u64,
+    /// Collective operations done so far.
+    round: u64,
+    label: String,
+    king: King,
+    /// King calls finished so far.
+    king_calls: u64,
+    /// The king of the call in progress, between sending to the king and its reply.
+    current_king: Option<usize>,
+    #[serde(default)]
+    security: Security,
+    /// Warn at the first operation if the session is still [Security::Insecure]; set for sessions
+    /// over a real network.
+    #[serde(skip)]
+    warn_if_insecure: bool,
+}
+
+impl State {
+    /// The session id and king from `config`.
+    pub(crate) fn from_config(config: &NetConfig) -> Self {
+        Self {
+            session: config.session.id.as_deref().map_or(0, tag::session_id),
+            king: King {
+                first: config.session.king,
+                rotate: config.session.rotate_king,
+            },
+            security: config.session.security,
+            warn_if_insecure: true,
+            ..Default::default()
+        }
+    }
+
+    /// The state of a fresh session over a real network.
+    pub(crate) fn networked() -> Self {
+        Self {
+            warn_if_insecure: true,
+            ..Default::default()
+        }
+    }
+
+    /// Where a new channel of this session starts: the same id, king and security, but no rounds
+    /// yet.
+    pub(crate) fn fresh(&self) -> Self {
+        Self {
+            session: self.session,
+            king: self.king,
+            security: self.security,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn set_session_id(&mut self, id: &str) {
+        self.session = tag::session_id(id);
+    }
+
+    /// Set the security mode, before the first collective operation.
+    pub(crate) fn set_security(&mut self, security: Security) {
+        assert!(
+            self.round == 0 || security == self.security,
+            "Cannot change the security mode of a session in use (round {})",
+            self.round
+        );
+        self.security = security;
+    }
+
+    /// The king of the call in progress, or of the next one, among `n` parties.
+    pub(crate) fn king(&self, n: usize) -> usize {
+        self.current_king.unwrap_or_else(|| {
+            let step = match self.king.rotate {
+                KingRotation::Never => 0,
+                KingRotation::PerCall => self.king_calls,
+                KingRotation::PerRound => self.round,
+            };
+            ((self.king.first as u64 + step) % n.max(1) as u64) as usize
+        })
+    }
+
+    pub(crate) fn set_king(&mut self, king: King, n: usize) {
+        assert!(king.first < n.max(1), "king {} does not exist", king.first);
+        self.king = king;
+        self.king_calls = 0;
+        self.current_king = None;
+    }
+
+    /// The tag of the next `op`. Collective operations start a new round.
+    pub(crate) fn tag(&mut self, op: Op) -> Tag {
+        if std::mem::take(&mut self.warn_if_insecure) && self.security == Security::Insecure {
+            warn!(
+                "INSECURE SESSION: running over the network in insecure mode, where shortcuts \
+                 that leak secrets are allowed. Set `security = \"production\"` in the session \
+                 config, or call MpcSession::with_security, in any real deployment."
+            );
+        }
+        let tag = Tag {
+            session: self.session,
+            round: self.round,
+            op,
+            label: self.label.clone(),
+        };
+        if op != Op::Direct {
+            self.round += 1;
+        }
+        tag
+    }
+
+    /// Start a send to the king: the king stays put until its reply.
+    pub(crate) fn begin_to_king(&mut self, n: usize) -> (usize, Tag) {
+        let king = self.king(n);
+        self.current_king = Some(king);
+        (king, self.tag(Op::ToKing))
+    }
+
+    /// Start a reply from the king, finishing the king call.
+    pub(crate) fn begin_from_king(&mut self, n: usize) -> (usize, Tag) {
+        let king = self.king(n);
+        self.current_king = None;
+        self.king_calls += 1;
+        (king, self.tag(Op::FromKing))
+    }
+}
+
+/// A session dropped while still connected runs its checkpoint first, on a session made of its
+/// parts, since checks talk through the current one. Failures can only be logged here: close the
+/// session with [MpcSession::try_deinit] to see them.
+impl Drop for MpcSession {
+    fn drop(&mut self) {
+        let checks = std::mem::take(self.checks.get_mut().expect("Poisoned MpcSession"));
+        if checks.0.is_empty() || !self.is_init() {
+            return;
+        }
+        if std::thread::panicking() {
+            warn!("Party {} skips its checkpoint, unwinding", self.party_id());
+            return;
+        }
+        let (transport, state) = {
+            let inner = self.inner.get_mut().expect("Poisoned MpcSession");
+            let transport =
+                std::mem::replace(&mut inner.transport, Box::new(Connections::default()));
+            (transport, std::mem::take(&mut inner.state))
+        };
+        let session = Arc::new(Self::from_parts(transport, state, self.attachments()));
+        let id = session.party_id();
+        *session.checks.lock().expect("Poisoned MpcSession") = checks;
+        if let Err(e) = session.try_deinit() {
+            error!(
+                "Party {} dropped a session that failed its checkpoint: {}",
+                id, e
+            );
+        }
+    }
+}
+
+impl Default for MpcSession {
+    /// An unconnected session.
+    fn default() -> Self {
+        Self::from_transport(Connections::default())
+    }
+}
+
+impl MpcSession {
+    /// Create a session from a host file and connect to all peers.
+    ///
+    /// The file should contain one HOST:PORT setting per line, corresponding to the addresses of
+    /// the parties in increasing order. Parties are zero-indexed.
+    pub fn try_init_from_file(path: &str, party_id: usize) -> NetResult<Self> {
+        let mut conns = Connections::default();
+        conns.init_from_path(path, party_id)?;
+        conns.connect_to_all()?;
+        Ok(Self::from_parts(
+            Box::new(conns),
+            State::networked(),
+            Default::default(),
+        ))
+    }
+
+    /// Like [MpcSession::try_init_from_file], but every link is authenticated and encrypted (see
+    /// [crate::secure]). The host file must list each party's public key, and `private_key` must
+    /// be ours.
+    pub fn try_init_secure_from_file(
+        path: &str,
+        party_id: usize,
+        private_key: &[u8],
+    ) -> NetResult<Self> {
+        let mut conns = Connections::default();
+        conns.set_private_key(private_key);
+        conns.init_from_path(path, party_id)?;
+        conns.connect_to_all()?;
+        Ok(Self::from_parts(
+            Box::new(conns),
+            State::networked(),
+            Default::default(),
+        ))
+    }
+
+    /// Create a session from a TOML or JSON config file (see [crate::config]) and connect to all
+    /// peers. Links are authenticated and encrypted if the config lists keys.
+    pub fn try_init_from_config(path: &str, party_id: usize) -> NetResult<Self> {
+        Self::try_from_config(&NetConfig::load(path)?, party_id)
+    }
+
+    /// Create a session of `n_parties` parties that all connect to the [crate::relay::Relay] at
+    /// `relay` (`HOST:PORT`), instead of to each other. Returns once every party has joined.
+    pub fn try_init_via_relay(relay: &str, party_id: usize, n_parties: usize) -> NetResult<Self> {
+        let addr = resolve_relay(relay)?;
+        let conn = RelayConnection::connect(addr, party_id, n_parties, &ConnectPolicy::default())?;
+        Ok(Self::from_parts(
+            Box::new(conn),
+            State::networked(),
+            Default::default(),
+        ))
+    }
+
+    /// Like [MpcSession::try_init_from_config], from an already-loaded config.
+    pub fn try_from_config(config: &NetConfig, party_id: usize) -> NetResult<Self> {
+        let transport: Box<dyn Transport> = if let Some(relay) = &config.session.relay {
+            let mut conn = RelayConnection::connect(
+                resolve_relay(relay)?,
+                party_id,
+                config.parties.len(),
+                &config.connect_policy(),
+            )?;
+            conn.set_timeouts(config.read_timeout(), config.write_timeout())?;
+            conn.max_message_len = config.max_message_len();
+            Box::new(conn)
+        } else {
+            let mut conns = Connections::default();
+            conns.init_from_config(config, party_id)?;
+            conns.connect_to_all()?;
+            Box::new(conns)
+        };
+        let transport: Box<dyn Transport> = match &config.session.emulate {
+            Some(model) => Box::new(Emulated::new(transport, model.clone())),
+            None => transport,
+        };
+        let session = Self::from_parts(transport, State::from_config(config), Default::default());
+        match config.transcript_path(party_id) {
+            Some(path) => session.record_to(path),
+            None => Ok(session),
+        }
+    }
+
+    /// Rerun the party that recorded the transcript at `path` (see [crate::transcript]), without
+    /// its peers. The session starts where the recording did, with the same id and king.
+    pub fn try_replay(path: impl AsRef<Path>) -> NetResult<Self> {
+        let mut replay = Replay::open(path)?;
+        let state = replay.take_state();
+        Ok(Self::from_parts(
+            Box::new(replay),
+            state,
+            Default::default(),
+        ))
+    }
+
+    /// Like [MpcSession::try_init_from_file], but panics on failure.
+    pub fn init_from_file(path: &str, party_id: usize) -> Self {
+        Self::try_init_from_file(path, party_id)
+            .unwrap_or_else(|e| panic!("Could not initialize the network: {}", e))
+    }
+
+    /// Create a session over an already-connected transport.
+    pub fn from_transport(transport: impl Transport + 'static) -> Self {
+        Self::from_parts(Box::new(transport), State::default(), Default::default())
+    }
+
+    pub(crate) fn from_parts(
+        transport: Box<dyn Transport>,
+        state: State,
+        attachments: Attachments,
+    ) -> Self {
+        Self {
+            inner: Mutex::new(Inner { transport, state }),
+            attachments: Mutex::new(attachments),
+            checks: Mutex::new(Checks::default()),
+        }
+    }
+
+    /// Record every message this session sends or receives from now on to a transcript at
+    /// `path`; see [crate::transcript].
+    pub fn record_to(self, path: impl AsRef<Path>) -> NetResult<Self> {
+        {
+            let mut inner = self.inner();
+            let transport =
+                std::mem::replace(&mut inner.transport, Box::new(Connections::default()));
+            let recorder = Recorder::start(transport, path.as_ref(), Some(&inner.state))?;
+            inner.transport = Box::new(recorder);
+        }
+        Ok(self)
+    }
+
+    /// Delay every message as on the network `model` describes, on an emulated clock; see
+    /// [crate::emulate]. All parties must use the same model.
+    pub fn emulate(self, model: NetworkModel) -> Self {
+        {
+            let mut inner = self.inner();
+            let transport =
+                std::mem::replace(&mut inner.transport, Box::new(Connections::default()));
+            inner.transport = Box::new(Emulated::new(transport, model));
+        }
+        self
+    }
+
+    /// Split this session's connections into independent channels; see [crate::mux]. Fails if
+    /// the transport cannot be split, e.g. a relay or a replay.
+    pub fn into_mux(self) -> NetResult<Mux> {
+        let attachments = self.attachments();
+        let (transport, state) = {
+            let mut inner = self.inner();
+            let transport =
+                std::mem::replace(&mut inner.transport, Box::new(Connections::default()));
+            (transport, std::mem::take(&mut inner.state))
+        };
+        let id = transport.party_id();
+        Ok(Mux::new(
+            id,
+            transport.into_links()?,
+            state.fresh(),
+            attachments,
+        ))
+    }
+
+    /// Name this session. All parties must use the same name; messages from a session with a
+    /// different name are rejected.
+    pub fn with_session_id(self, id: &str) -> Self {
+        self.inner().state.set_session_id(id);
+        self
+    }
+
+    /// Set the session's [Security] mode. Sessions over a network log a warning at their first
+    /// operation if still [Security::Insecure].
+    ///
+    /// # Panics
+    ///
+    /// If the mode changes after the session's first collective operation.
+    pub fn with_security(self, security: Security) -> Self {
+        self.inner().state.set_security(security);
+        self
+    }
+
+    /// The session's [Security] mode.
+    pub fn security(&self) -> Security {
+        self.inner().state.security
+    }
+
+    /// Attach `value` to this session, replacing any earlier value of the same type. Protocols
+    /// built on the network keep their per-session state here, e.g. the SPDZ key material of
+    /// `mpc-algebra`. Channels of a [Mux] start with the values attached when it was made.
+    pub fn attach<T: Any + Send + Sync>(&self, value: T) {
+        self.attachments
+            .lock()
+            .expect("Poisoned MpcSession")
+            .0
+            .insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// The value of type `T` attached with [MpcSession::attach], if any.
+    pub fn attached<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let value = self
+            .attachments
+            .lock()
+            .expect("Poisoned MpcSession")
+            .0
+            .get(&TypeId::of::<T>())?
+            .clone();
+        Some(
+            value
+                .downcast()
+                .expect("attachment stored under the wrong type"),
+        )
+    }
+
+    /// Register `check`, which protocols built on the network must pass before anything they
+    /// computed leaves the session, e.g. the MAC check of SPDZ in `mpc-algebra`. It runs, inside
+    /// the session, at every [MpcSession::try_checkpoint], when the session is closed with
+    /// [MpcSession::try_deinit], and when it is dropped while still connected. Checks run in the
+    /// order registered, on every party at the same point; they are not inherited by channels.
+    pub fn on_checkpoint(&self, check: impl Fn() -> NetResult<()> + Send + Sync + 'static) {
+        self.checks
+            .lock()
+            .expect("Poisoned MpcSession")
+            .0
+            .push(Arc::new(check));
+    }
+
+    /// Run the checks registered with [MpcSession::on_checkpoint], e.g. before revealing a
+    /// result to the application or writing it out. Fails with the first check that fails.
+    pub fn try_checkpoint(self: &Arc<Self>) -> NetResult<()> {
+        let checks = self.checks.lock().expect("Poisoned MpcSession").clone();
+        self.enter(|| checks.0.iter().try_for_each(|check| check()))
+    }
+
+    fn attachments(&self) -> Attachments {
+        self.attachments
+            .lock()
+            .expect("Poisoned MpcSession")
+            .clone()
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("Poisoned MpcSession")
+    }
+
+    /// What is my party number (0 to n-1)?
+    pub fn party_id(&self) -> usize {
+        self.inner().transport.party_id()
+    }
+
+    /// How many parties are there?
+    pub fn n_parties(&self) -> usize {
+        self.inner().transport.n_parties()
+    }
+
+    /// Am I the king of the next king operation?
+    pub fn am_king(&self) -> bool {
+        self.party_id() == self.king()
+    }
+
+    /// The king of the next king operation.
+    pub fn king(&self) -> usize {
+        let inner = self.inner();
+        inner.state.king(inner.transport.n_parties())
+    }
+
+    /// The king of the first king operation; see [King::first].
+    pub fn first_king(&self) -> usize {
+        self.inner().state.king.first
+    }
+
+    /// Change how the king is chosen. All parties must do this at the same point.
+    pub fn set_king(&self, king: King) {
+        let mut inner = self.inner();
+        let n = inner.transport.n_parties();
+        inner.state.set_king(king, n);
+    }
+
+    /// Like [MpcSession::set_king], when creating the session.
+    pub fn with_king(self, king: King) -> Self {
+        self.set_king(king);
+        self
+    }
+
+    /// Is this session connected?
+    pub fn is_init(&self) -> bool {
+        self.inner().transport.is_init()
+    }
+
+    /// Run the checks registered with [MpcSession::on_checkpoint], then close all connections,
+    /// even if a check failed.
+    pub fn try_deinit(self: &Arc<Self>) -> NetResult<()> {
+        let checked = self.try_checkpoint();
+        self.inner().transport.uninit();
+        checked
+    }
+
+    /// Like [MpcSession::try_deinit], but panics if a check failed.
+    pub fn deinit(self: &Arc<Self>) {
+        self.try_deinit()
+            .unwrap_or_else(|e| panic!("A check failed when closing the session: {}", e))
+    }
+
+    /// Set statistics to zero.
+    pub fn reset_stats(&self) {
+        *self.inner().transport.stats() = Stats::default();
+    }
+
+    /// Get statistics.
+    pub fn stats(&self) -> Stats {
+        self.inner().transport.stats().clone()
+    }
+
+    /// Number of collective operations (broadcasts, sends to and from the king) done so far.
+    pub fn round(&self) -> u64 {
+        self.inner().state.round
+    }
+
+    /// Run `f` with every message it sends labelled `label`, e.g. the name of a sub-protocol.
+    /// If parties disagree on the label of a message, they get [crate::NetError::Desync] naming
+    /// it. Labels nest; the previous label is restored afterwards. Only the first 255 bytes of a
+    /// label are sent.
+    pub fn with_label<R>(&self, label: &str, f: impl FnOnce() -> R) -> R {
+        struct Restore<'a>(&'a MpcSession, Option<String>);
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                self.0.inner().state.label = self.1.take().unwrap();
+            }
+        }
+        let label = tag::truncate_label(label).to_owned();
+        let prev = std::mem::replace(&mut self.inner().state.label, label);
+        let _restore = Restore(self, Some(prev));
+        f()
+    }
+
+    /// Bound how long a single read from (or write to) a peer may block. `None` waits forever.
+    pub fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> NetResult<()> {
+        self.inner().transport.set_timeouts(read, write)
+    }
+
+    /// All parties send bytes to each other.
+    #[track_caller]
+    pub fn try_broadcast_bytes(&self, bytes: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        let at = Location::caller();
+        let mut inner = self.inner();
+        let own = inner.transport.party_id();
+        let tag = inner.state.tag(Op::Broadcast);
+        let frame = tag.seal(bytes);
+        let started = Instant::now();
+        let frames = inner.transport.broadcast(&frame)?;
+        inner
+            .phase(&tag, started)
+            .sent_to_all(own, frame.len())
+            .recv_all(own, &frames);
+        open_all(&tag, frames, at)
+    }
+
+    /// All parties send bytes to the king.
+    #[track_caller]
+    pub fn try_send_bytes_to_king(&self, bytes: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        let at = Location::caller();
+        let mut inner = self.inner();
+        let (own, n) = (inner.transport.party_id(), inner.transport.n_parties());
+        let (king, tag) = inner.state.begin_to_king(n);
+        let frame = tag.seal(bytes);
+        let started = Instant::now();
+        let frames = inner.transport.send_to_king(king, &frame)?;
+        let phase = inner.phase(&tag, started);
+        match &frames {
+            Some(frames) => phase.recv_all(own, frames),
+            None => phase.sent(king, frame.len()),
+        };
+        frames.map(|frames| open_all(&tag, frames, at)).transpose()
+    }
+
+    /// All parties recv bytes from the king.
+    /// Provide bytes iff you're the king!
+    #[track_caller]
+    pub fn try_recv_bytes_from_king(&self, bytes: Option<Vec<Vec<u8>>>) -> NetResult<Vec<u8>> {
+        let at = Location::caller();
+        let mut inner = self.inner();
+        let (own, n) = (inner.transport.party_id(), inner.transport.n_parties());
+        let (king, tag) = inner.state.begin_from_king(n);
+        let bytes: Option<Vec<Vec<u8>>> =
+            bytes.map(|all| all.iter().map(|b| tag.seal(b)).collect());
+        let sent: Vec<usize> = bytes.iter().flatten().map(Vec::len).collect();
+        let started = Instant::now();
+        let frame = inner.transport.recv_from_king(king, bytes)?;
+        let phase = inner.phase(&tag, started);
+        if king == own {
+            for (to, len) in sent.into_iter().enumerate().filter(|(to, _)| *to != own) {
+                phase.sent(to, len);
+            }
+        } else {
+            phase.recv(king, frame.len());
+        }
+        tag.open(king, frame, at)
+    }
+
+    /// Send bytes to party `to` only.
+    #[track_caller]
+    pub fn try_send_bytes_to(&self, to: usize, bytes: &[u8]) -> NetResult<()> {
+        let mut inner = self.inner();
+        assert_ne!(to, inner.transport.party_id(), "cannot send to oneself");
+        let tag = inner.state.tag(Op::Direct);
+        let frame = tag.seal(bytes);
+        let started = Instant::now();
+        inner.transport.send_to(to, &frame)?;
+        inner.phase(&tag, started).sent(to, frame.len());
+        Ok(())
+    }
+
+    /// Receive the next message that party `from` sent us.
+    #[track_caller]
+    pub fn try_recv_bytes_from(&self, from: usize) -> NetResult<Vec<u8>> {
+        let at = Location::caller();
+        let mut inner = self.inner();
+        assert_ne!(
+            from,
+            inner.transport.party_id(),
+            "cannot receive from oneself"
+        );
+        let tag = inner.state.tag(Op::Direct);
+        let started = Instant::now();
+        let frame = inner.transport.recv_from(from)?;
+        inner.phase(&tag, started).recv(from, frame.len());
+        tag.open(from, frame, at)
+    }
+
+    /// Swap bytes with party `peer`.
+    #[track_caller]
+    pub fn try_exchange_bytes(&self, peer: usize, bytes: &[u8]) -> NetResult<Vec<u8>> {
+        let at = Location::caller();
+        let mut inner = self.inner();
+        assert_ne!(
+            peer,
+            inner.transport.party_id(),
+            "cannot exchange with oneself"
+        );
+        let tag = inner.state.tag(Op::Direct);
+        let frame_out = tag.seal(bytes);
+        let started = Instant::now();
+        let frame = inner.transport.exchange(peer, &frame_out)?;
+        inner
+            .phase(&tag, started)
+            .sent(peer, frame_out.len())
+            .recv(peer, frame.len());
+        tag.open(peer, frame, at)
+    }
+
+    /// Everyone sends bytes to the king, who runs `f` on them and redistributes the result.
+    #[track_caller]
+    pub fn try_king_compute(
+        &self,
+        bytes: &[u8],
+        f: impl Fn(Vec<Vec<u8>>) -> Vec<Vec<u8>>,
+    ) -> NetResult<Vec<u8>> {
+        let king_response = self.try_send_bytes_to_king(bytes)?.map(f);
+        self.try_recv_bytes_from_king(king_response)
+    }
+
+    /// Like [MpcSession::try_broadcast_bytes], but panics on failure.
+    #[track_caller]
+    pub fn broadcast_bytes(&self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.try_broadcast_bytes(bytes)
+            .unwrap_or_else(|e| panic!("broadcast failed: {}", e))
+    }
+
+    /// Like [MpcSession::try_send_bytes_to_king], but panics on failure.
+    #[track_caller]
+    pub fn send_bytes_to_king(&self, bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
+        self.try_send_bytes_to_king(bytes)
+            .unwrap_or_else(|e| panic!("send to king failed: {}", e))
+    }
+
+    /// Like [MpcSession::try_recv_bytes_from_king], but panics on failure.
+    #[track_caller]
+    pub fn recv_bytes_from_king(&self, bytes: Option<Vec<Vec<u8>>>) -> Vec<u8> {
+        self.try_recv_bytes_from_king(bytes)
+            .unwrap_or_else(|e| panic!("receive from king failed: {}", e))
+    }
+
+    /// Like [MpcSession::try_send_bytes_to], but panics on failure.
+    #[track_caller]
+    pub fn send_bytes_to(&self, to: usize, bytes: &[u8]) {
+        self.try_send_bytes_to(to, bytes)
+            .unwrap_or_else(|e| panic!("send to party {} failed: {}", to, e))
+    }
+
+    /// Like [MpcSession::try_recv_bytes_from], but panics on failure.
+    #[track_caller]
+    pub fn recv_bytes_from(&self, from: usize) -> Vec<u8> {
+        self.try_recv_bytes_from(from)
+            .unwrap_or_else(|e| panic!("receive from party {} failed: {}", from, e))
+    }
+
+    /// Like [MpcSession::try_exchange_bytes], but panics on failure.
+    #[track_caller]
+    pub fn exchange_bytes(&self, peer: usize, bytes: &[u8]) -> Vec<u8> {
+        self.try_exchange_bytes(peer, bytes)
+            .unwrap_or_else(|e| panic!("exchange with party {} failed: {}", peer, e))
+    }
+
+    /// Like [MpcSession::try_king_compute], but panics on failure.
+    #[track_caller]
+    pub fn king_compute(&self, bytes: &[u8], f: impl Fn(Vec<Vec<u8>>) -> Vec<Vec<u8>>) -> Vec<u8> {
+        self.try_king_compute(bytes, f)
+            .unwrap_or_else(|e| panic!("king compute failed: {}", e))
+    }
+
+    /// Run `f` with this session bound to the current thread.
+    ///
+    /// Inside `f`, every call through [crate::MpcMultiNet] (and so every share operation in
+    /// `mpc-algebra`) talks to this session. The previous binding is restored afterwards, even if
+    /// `f` panics.
+    ///
+    /// The binding is per thread: work that `f` hands to other threads, e.g. a rayon pool, runs
+    /// on the default session instead, and logs a warning when it first does so. Wrap such work
+    /// in [MpcSession::propagate].
+    pub fn enter<R>(self: &Arc<Self>, f: impl FnOnce() -> R) -> R {
+        struct Restore(Option<Arc<MpcSession>>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                CURRENT_SESSION.with(|c| *c.borrow_mut() = prev);
+                ENTERED.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        ENTERED.fetch_add(1, Ordering::SeqCst);
+        let prev = CURRENT_SESSION.with(|c| c.borrow_mut().replace(self.clone()));
+        let _restore = Restore(prev);
+        f()
+    }
+
+    /// `f`, bound to the session of the calling thread, if any: wherever it runs, it talks to that
+    /// session, as if called inside [MpcSession::enter]. Use it to hand protocol work to another
+    /// thread.
+    pub fn propagate<R>(f: impl FnOnce() -> R + Send) -> impl FnOnce() -> R + Send {
+        let session = Self::current();
+        move || match session {
+            Some(session) => session.enter(f),
+            None => f(),
+        }
+    }
+
+    /// The session bound to the current thread, if any.
+    pub fn current() -> Option<Arc<MpcSession>> {
+        CURRENT_SESSION.with(|c| c.borrow().clone())
+    }
+}
+
+fn resolve_relay(relay: &str) -> NetResult<std::net::SocketAddr> {
+    relay
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| NetError::Config(format!("relay address {} does not resolve", relay)))
+}
+
+/// Check and strip the tags of one message from each party.
+pub(crate) fn open_all(
+    tag: &Tag,
+    frames: Vec<Vec<u8>>,
+    at: &Location<'_>,
+) -> NetResult<Vec<Vec<u8>>> {
+    frames
+        .into_iter()
+        .enumerate()
+        .map(|(peer, frame)| tag.open(peer, frame, at))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{local::LocalTransport, tag::HEADER_LEN, MpcMultiNet, MpcNet};
+    use std::io::Write;
+
+    /// Write a host file for `n` parties on localhost, starting at `port`.
+    fn host_file(name: &str, n: usize, port: u16) -> String {
+        keyed_host_file(name, port, &vec![None; n])
+    }
+
+    /// Like [host_file], listing a public key (if given) for each party.
+    fn keyed_host_file(name: &str, port: u16, keys: &[Option<&[u8]>]) -> String {
+        let path = std::env::temp_dir().join(format!("mpc-net-{}-{}", name, std::process::id()));
+        let mut f = std::fs::File::create(&path).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            write!(f, "127.0.0.1:{}", port + i as u16).unwrap();
+            if let Some(key) = key {
+                write!(f, " {}", crate::secure::to_hex(key)).unwrap();
+            }
+            writeln!(f).unwrap();
+        }
+        path.to_str().unwrap().to_owned()
+    }
+
+    /// Run one secure session per private key over the host file at `path`.
+    fn run_secure(path: &str, private_keys: Vec<Vec<u8>>) -> Vec<NetResult<Vec<Vec<u8>>>> {
+        let handles: Vec<_> = private_keys
+            .into_iter()
+            .enumerate()
+            .map(|(id, key)| {
+                let path = path.to_owned();
+                std::thread::spawn(move || {
+                    let session = MpcSession::try_init_secure_from_file(&path, id, &key)?;
+                    session.set_timeouts(Some(std::time::Duration::from_secs(5)), None)?;
+                    // Larger than one record.
+                    session.try_broadcast_bytes(&vec![id as u8; 100_000 + id])
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    }
+
+    #[test]
+    fn concurrent_sessions() {
+        let sessions = [("a", 2, 17310), ("b", 2, 17320)];
+        let handles: Vec<_> = sessions
+            .iter()
+            .flat_map(|&(name, n, port)| {
+                let path = host_file(name, n, port);
+                (0..n).map(move |id| {
+                    let path = path.clone();
+                    std::thread::spawn(move || {
+                        let session = Arc::new(MpcSession::init_from_file(&path, id));
+                        session.enter(|| {
+                            let all = MpcMultiNet::broadcast_bytes(&[id as u8; 4]);
+                            let r = MpcMultiNet::send_bytes_to_king(&[id as u8]);
+                            let from_king = MpcMultiNet::recv_bytes_from_king(r);
+                            (MpcMultiNet::n_parties(), all, from_king)
+                        })
+                    })
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        for (i, (n, all, from_king)) in results.iter().enumerate() {
+            let id = i % 2;
+            assert_eq!(*n, 2);
+            assert_eq!(all, &(0..2u8).map(|i| vec![i; 4]).collect::<Vec<_>>());
+            assert_eq!(from_king, &vec![id as u8]);
+        }
+        assert!(MpcSession::current().is_none());
+    }
+
+    #[test]
+    fn secure_channels() {
+        let keys: Vec<_> = (0..3).map(|_| crate::secure::generate_keypair()).collect();
+        let public: Vec<_> = keys.iter().map(|k| Some(&k.public[..])).collect();
+        let path = keyed_host_file("secure", 17340, &public);
+        let results = run_secure(&path, keys.iter().map(|k| k.private.clone()).collect());
+        for r in results {
+            let all = r.unwrap();
+            for (i, bytes) in all.iter().enumerate() {
+                assert_eq!(bytes, &vec![i as u8; 100_000 + i]);
+            }
+        }
+    }
+
+    #[test]
+    fn secure_channels_reject_impostor() {
+        let keys: Vec<_> = (0..2).map(|_| crate::secure::generate_keypair()).collect();
+        let impostor = crate::secure::generate_keypair();
+        let public: Vec<_> = keys.iter().map(|k| Some(&k.public[..])).collect();
+        let path = keyed_host_file("impostor", 17350, &public);
+        // Party 1 does not hold the key the host file lists for it, so it cannot read party 0's
+        // first handshake message. Party 0 then sees the link drop.
+        let results = run_secure(&path, vec![keys[0].private.clone(), impostor.private]);
+        assert!(results[0].is_err());
+        assert!(matches!(
+            results[1],
+            Err(NetError::Authentication { peer: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn mux_over_secure_channels() {
+        let keys: Vec<_> = (0..2).map(|_| crate::secure::generate_keypair()).collect();
+        let public: Vec<_> = keys.iter().map(|k| Some(&k.public[..])).collect();
+        let path = keyed_host_file("secure-mux", 17420, &public);
+        let handles: Vec<_> = keys
+            .into_iter()
+            .enumerate()
+            .map(|(id, key)| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let session = MpcSession::try_init_secure_from_file(&path, id, &key.private)?;
+                    let mux = session.into_mux()?;
+                    let channels: Vec<_> = (0..2).map(|c| mux.channel(c)).collect();
+                    std::thread::scope(|s| {
+                        let handles: Vec<_> = channels
+                            .iter()
+                            .map(|channel| {
+                                s.spawn(move || {
+                                    channel.set_timeouts(Some(Duration::from_secs(5)), None)?;
+                                    (0..3)
+                                        .map(|r| {
+                                            channel.try_exchange_bytes(1 - id, &vec![r; 70_000])
+                                        })
+                                        .collect::<NetResult<Vec<_>>>()
+                                })
+                            })
+                            .collect();
+                        handles
+                            .into_iter()
+                            .map(|h| h.join().unwrap())
+                            .collect::<NetResult<Vec<_>>>()
+                    })
+                })
+            })
+            .collect();
+        for h in handles {
+            let out: Vec<Vec<Vec<u8>>> = h.join().unwrap().unwrap();
+            for rounds in out {
+                assert_eq!(rounds, (0..3).map(|r| vec![r; 70_000]).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn security_is_fixed_once_in_use() {
+        let session = MpcSession::from_transport(LocalTransport::mesh(1).pop().unwrap())
+            .with_security(Security::Production)
+            .with_security(Security::Insecure);
+        session.broadcast_bytes(&[0]);
+        let session = session.with_security(Security::Insecure);
+        let changed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            session.with_security(Security::Production)
+        }));
+        assert!(changed.is_err());
+    }
+
+    #[test]
+    fn propagate_carries_the_binding() {
+        let ids = crate::local::simulate(3, |id| {
+            let work = MpcSession::propagate(MpcMultiNet::party_id);
+            (id, std::thread::spawn(work).join().unwrap())
+        });
+        for (id, theirs) in ids {
+            assert_eq!(theirs, id);
+        }
+    }
+
+    #[test]
+    fn checkpoints_run_until_the_session_closes() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        std::thread::scope(|s| {
+            for t in LocalTransport::mesh(2) {
+                let runs = runs.clone();
+                s.spawn(move || {
+                    let session = Arc::new(MpcSession::from_transport(t));
+                    session.on_checkpoint(move || {
+                        MpcMultiNet::try_broadcast_bytes(&[1])?;
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        Ok(())
+                    });
+                    session.try_checkpoint().unwrap();
+                    // Once more as it is dropped.
+                });
+            }
+        });
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+
+        let session = Arc::new(MpcSession::from_transport(
+            LocalTransport::mesh(1).pop().unwrap(),
+        ));
+        session.on_checkpoint(|| Err(NetError::MacCheck { index: 0 }));
+        assert!(matches!(
+            session.try_deinit(),
+            Err(NetError::MacCheck { index: 0 })
+        ));
+        assert!(!session.is_init());
+    }
+
+    #[test]
+    fn attachments_follow_channels() {
+        let session = MpcSession::from_transport(LocalTransport::mesh(1).pop().unwrap());
+        assert!(session.attached::<u64>().is_none());
+        session.attach(7u64);
+        session.attach(String::from("key"));
+        session.attach(8u64);
+        let mux = session.into_mux().unwrap();
+        let channel = mux.channel(0);
+        assert_eq!(*channel.attached::<u64>().unwrap(), 8);
+        assert_eq!(*channel.attached::<String>().unwrap(), "key");
+        // Values attached to a channel stay with it.
+        channel.attach(9u64);
+        assert_eq!(*mux.channel(1).attached::<u64>().unwrap(), 8);
+    }
+
+    #[test]
+    fn secure_channels_need_all_keys() {
+        let key = crate::secure::generate_keypair();
+        let path = keyed_host_file("partial", 17360, &[Some(&key.public[..]), None]);
+        assert!(matches!(
+            MpcSession::try_init_secure_from_file(&path, 0, &key.private),
+            Err(NetError::Config(_))
+        ));
+        assert!(matches!(
+            MpcSession::try_init_from_file(&path, 0),
+            Err(NetError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn connects_in_any_order() {
+        let path = host_file("order", 4, 17380);
+        // Higher parties come up first, so every dialer finds its peer already listening and
+        // connections from below arrive in no particular order.
+        let handles: Vec<_> = (0..4)
+            .map(|id| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(50 * (3 - id as u64)));
+                    let session = MpcSession::init_from_file(&path, id);
+                    session.broadcast_bytes(&[id as u8])
+                })
+            })
+            .collect();
+        for h in handles {
+            assert_eq!(h.join().unwrap(), vec![vec![0], vec![1], vec![2], vec![3]]);
+        }
+    }
+
+    #[test]
+    fn strays_are_dropped() {
+        let path = host_file("strays", 2, 17440);
+        let party = |id: usize| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let session = MpcSession::try_init_from_file(&path, id)?;
+                session.try_broadcast_bytes(&[id as u8])
+            })
+        };
+        let one = party(1);
+        let stray = || loop {
+            if let Ok(stream) = std::net::TcpStream::connect("127.0.0.1:17441") {
+                break stream;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        // Before party 0 shows up, party 1 gets a connection that sends garbage, one that hangs
+        // up and one that stays silent.
+        stray().write_all(&[0xff; 20]).unwrap();
+        drop(stray());
+        let _silent = stray();
+        std::thread::sleep(Duration::from_millis(50));
+        let zero = party(0);
+        for h in [zero, one] {
+            assert_eq!(h.join().unwrap().unwrap(), vec![vec![0], vec![1]]);
+        }
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let config = NetConfig::from_toml_str(
+            "[session]\nconnect_timeout_ms = 300\nconnect_backoff_ms = 20\n\
+             [[parties]]\nid = 0\naddress = \"127.0.0.1:17390\"\n\
+             [[parties]]\nid = 1\naddress = \"127.0.0.1:17391\"\n",
+        )
+        .unwrap();
+        // Alone, party 0 gives up dialing party 1, and party 1 gives up waiting for party 0.
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            MpcSession::try_from_config(&config, 0),
+            Err(NetError::Timeout { peer: 1 })
+        ));
+        assert!(matches!(
+            MpcSession::try_from_config(&config, 1),
+            Err(NetError::Timeout { peer: 0 })
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected() {
+        let config = NetConfig::from_toml_str(
+            "[session]\nmax_message_len = 1024\n\
+             [[parties]]\nid = 0\naddress = \"127.0.0.1:17430\"\n\
+             [[parties]]\nid = 1\naddress = \"127.0.0.1:17431\"\n",
+        )
+        .unwrap();
+        let handles: Vec<_> = (0..2)
+            .map(|id| {
+                let config = config.clone();
+                std::thread::spawn(move || {
+                    let session = MpcSession::try_from_config(&config, id)?;
+                    match id {
+                        0 => session.try_send_bytes_to(1, &[0; 2000]).map(|_| vec![]),
+                        _ => session.try_recv_bytes_from(0),
+                    }
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            &results[1],
+            Err(NetError::MalformedFrame { peer: 0, reason }) if reason.contains("limit of 1024")
+        ));
+    }
+
+    #[test]
+    fn from_config() {
+        let keys: Vec<_> = (0..2).map(|_| crate::secure::generate_keypair()).collect();
+        let dir = std::env::temp_dir().join(format!("mpc-net-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut toml = "[session]\nread_timeout_ms = 5000\n".to_owned();
+        for (id, key) in keys.iter().enumerate() {
+            let key_file = dir.join(format!("{}.key", id));
+            std::fs::write(&key_file, crate::secure::to_hex(&key.private)).unwrap();
+            toml += &format!(
+                "[[parties]]\nid = {}\naddress = \"localhost:{}\"\nlisten = \"127.0.0.1:{}\"\n\
+                 public_key = \"{}\"\nprivate_key_file = {:?}\n",
+                id,
+                17370 + id,
+                17370 + id,
+                crate::secure::to_hex(&key.public),
+                key_file,
+            );
+        }
+        let path = dir.join("hosts.toml");
+        std::fs::write(&path, toml).unwrap();
+        let path = path.to_str().unwrap().to_owned();
+        let handles: Vec<_> = (0..2)
+            .map(|id| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    MpcSession::try_init_from_config(&path, id)?.try_broadcast_bytes(&[id as u8])
+                })
+            })
+            .collect();
+        for h in handles {
+            assert_eq!(h.join().unwrap().unwrap(), vec![vec![0], vec![1]]);
+        }
+    }
+
+    /// Run `f` as each party of an in-process session named `id(party)`.
+    fn run_local<R: Send>(
+        n: usize,
+        id: impl Fn(usize) -> &'static str + Sync,
+        f: impl Fn(&MpcSession) -> R + Sync,
+    ) -> Vec<R> {
+        std::thread::scope(|s| {
+            let handles: Vec<_> = LocalTransport::mesh(n)
+                .into_iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    let session = MpcSession::from_transport(t).with_session_id(id(i));
+                    let f = &f;
+                    s.spawn(move || f(&session))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    #[test]
+    fn desync_names_call_site() {
+        let results = run_local(
+            2,
+            |_| "s",
+            |session| {
+                session.try_broadcast_bytes(&[0])?;
+                if session.party_id() == 0 {
+                    session.try_broadcast_bytes(&[1]).map(|_| ())
+                } else {
+                    // Party 1 has taken another path.
+                    session.try_send_bytes_to_king(&[1])?;
+                    session.try_recv_bytes_from(0).map(|_| ())
+                }
+            },
+        );
+        assert!(matches!(results[1], Err(NetError::Desync { peer: 0, .. })));
+        let err = results[0].as_ref().unwrap_err();
+        assert!(matches!(err, NetError::Desync { peer: 1, .. }));
+        let msg = err.to_string();
+        assert!(msg.contains("broadcast in round 1"), "{}", msg);
+        assert!(msg.contains("send to king in round 1"), "{}", msg);
+        assert!(msg.contains(file!()), "{}", msg);
+    }
+
+    #[test]
+    fn desync_on_label_and_session() {
+        let labels = run_local(
+            2,
+            |_| "s",
+            |session| {
+                let label = if session.party_id() == 0 {
+                    "mul"
+                } else {
+                    "open"
+                };
+                session.with_label(label, || session.try_broadcast_bytes(&[0]))
+            },
+        );
+        for r in labels {
+            assert!(matches!(r, Err(NetError::Desync { .. })));
+        }
+        let sessions = run_local(
+            2,
+            |i| ["a", "b"][i],
+            |session| session.try_broadcast_bytes(&[0]),
+        );
+        for r in sessions {
+            assert!(matches!(r, Err(NetError::Desync { .. })));
+        }
+        let agreed = run_local(
+            2,
+            |_| "s",
+            |session| session.with_label("mul", || session.try_broadcast_bytes(&[7])),
+        );
+        for r in agreed {
+            assert_eq!(r.unwrap(), vec![vec![7], vec![7]]);
+        }
+    }
+
+    #[test]
+    fn rotating_king() {
+        let kings = |king: King| {
+            run_local(
+                3,
+                |_| "s",
+                move |session| {
+                    session.set_king(king);
+                    let mut kings = vec![];
+                    for _ in 0..4 {
+                        // Everyone learns who the king was from the king itself.
+                        let r = session.send_bytes_to_king(&[session.party_id() as u8]);
+                        let id = session.party_id() as u8;
+                        let reply = r.map(|all| all.iter().map(|_| vec![id]).collect());
+                        kings.push(session.recv_bytes_from_king(reply)[0]);
+                    }
+                    kings
+                },
+            )
+        };
+        let fixed = kings(King::fixed(2));
+        let per_call = kings(King {
+            first: 1,
+            rotate: KingRotation::PerCall,
+        });
+        let per_round = kings(King {
+            first: 0,
+            rotate: KingRotation::PerRound,
+        });
+        for id in 0..3 {
+            assert_eq!(fixed[id], vec![2, 2, 2, 2]);
+            assert_eq!(per_call[id], vec![1, 2, 0, 1]);
+            // Each iteration takes two rounds.
+            assert_eq!(per_round[id], vec![0, 2, 1, 0]);
+        }
+    }
+
+    #[test]
+    fn stats_per_phase() {
+        let stats = run_local(
+            3,
+            |_| "s",
+            |session| {
+                session.broadcast_bytes(&[0; 10]);
+                session.with_label("mul", || {
+                    let r = session.send_bytes_to_king(&[0; 20]);
+                    session.recv_bytes_from_king(r.map(|all| vec![vec![0; 30]; all.len()]));
+                });
+                if session.party_id() < 2 {
+                    session.with_label("swap", || {
+                        session.exchange_bytes(1 - session.party_id(), &[0; 5])
+                    });
+                }
+                session.stats()
+            },
+        );
+        let at = |id: usize, label: &str| stats[id].phases[label].clone();
+        let header = HEADER_LEN + 3;
+        for id in 0..3 {
+            let open = at(id, "");
+            assert_eq!((open.rounds, open.messages), (1, 0));
+            let others = |len: usize| -> Vec<usize> {
+                (0..3).map(|j| if j == id { 0 } else { len }).collect()
+            };
+            assert_eq!(open.bytes_sent, others(HEADER_LEN + 10));
+            assert_eq!(open.bytes_recv, others(HEADER_LEN + 10));
+            assert_eq!(at(id, "mul").rounds, 2);
+        }
+        // Party 0 is king: it hears 20 bytes from each and answers with 30.
+        assert_eq!(at(0, "mul").bytes_recv, vec![0, header + 20, header + 20]);
+        assert_eq!(at(0, "mul").bytes_sent, vec![0, header + 30, header + 30]);
+        assert_eq!(at(2, "mul").bytes_sent, vec![header + 20, 0, 0]);
+        assert_eq!(at(2, "mul").bytes_recv, vec![header + 30, 0, 0]);
+        let swap = at(1, "swap");
+        assert_eq!((swap.rounds, swap.messages), (0, 1));
+        assert_eq!(swap.bytes_sent, vec![HEADER_LEN + 4 + 5, 0, 0]);
+        assert!(!stats[2].phases.contains_key("swap"));
+
+        let json = stats[1].to_json();
+        assert!(json.contains("\"blocked_secs\""), "{}", json);
+        let back: Stats = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.bytes_sent, stats[1].bytes_sent);
+        assert_eq!(back.phases["swap"].bytes_sent, swap.bytes_sent);
+    }
+
+    #[test]
+    fn framing() {
+        let path = host_file("framing", 2, 17330);
+        let handles: Vec<_> = (0..2)
+            .map(|id| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let session = MpcSession::init_from_file(&path, id);
+                    session.reset_stats();
+                    // Different lengths from each party, including an empty message.
+                    let all = session.broadcast_bytes(&vec![id as u8; 3 * id]);
+                    let at_king = session.send_bytes_to_king(&vec![7; id + 1]);
+                    let from_king = session.recv_bytes_from_king(
+                        at_king.as_ref().map(|_| vec![vec![], vec![9; 1000]]),
+                    );
+                    let swapped =
+                        session.exchange_bytes(1 - id, &vec![id as u8; 100_000 * (id + 1)]);
+                    (all, at_king, from_king, swapped, session.stats())
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        for (id, r) in results.iter().enumerate() {
+            assert_eq!(r.3, vec![1 - id as u8; 100_000 * (2 - id)]);
+        }
+        let (all, at_king, from_king, _, stats) = &results[0];
+        assert_eq!(all, &vec![vec![], vec![1; 3]]);
+        assert_eq!(at_king, &Some(vec![vec![7], vec![7; 2]]));
+        assert!(from_king.is_empty());
+        // Three messages from party 1, each with a header.
+        assert_eq!(stats.bytes_recv, 3 + 2 + 200_000 + 3 * (8 + HEADER_LEN));
+        let (all, at_king, from_king, _, stats) = &results[1];
+        assert_eq!(all, &vec![vec![], vec![1; 3]]);
+        assert!(at_king.is_none());
+        assert_eq!(from_king, &vec![9; 1000]);
+        assert_eq!(stats.bytes_recv, 1000 + 100_000 + 3 * (8 + HEADER_LEN));
+    }
+}
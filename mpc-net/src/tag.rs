@@ -0,0 +1,134 @@
+//! Headers that let a party notice it is no longer in step with its peers.
+//!
+//! [crate::MpcSession] prefixes every message with the session id, the number of collective
+//! operations (broadcasts, sends to and from the king) done so far, the kind of operation and the
+//! current protocol label. The receiver expects exactly its own values, so if two parties take
+//! different code paths the first message that crosses the divergence fails with
+//! [NetError::Desync], instead of being deserialized as something else.
+use std::{fmt, panic::Location};
+
+use crate::{NetError, NetResult};
+
+/// What kind of operation a message belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Op {
+    Broadcast = 0,
+    ToKing = 1,
+    FromKing = 2,
+    Direct = 3,
+}
+
+impl Op {
+    fn from_u8(b: u8) -> Option<Self> {
+        [Op::Broadcast, Op::ToKing, Op::FromKing, Op::Direct]
+            .into_iter()
+            .find(|op| *op as u8 == b)
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Op::Broadcast => "broadcast",
+            Op::ToKing => "send to king",
+            Op::FromKing => "receive from king",
+            Op::Direct => "point-to-point message",
+        })
+    }
+}
+
+/// The header of one message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Tag {
+    pub(crate) session: u64,
+    pub(crate) round: u64,
+    pub(crate) op: Op,
+    /// At most [MAX_LABEL_LEN] bytes.
+    pub(crate) label: String,
+}
+
+pub(crate) const MAX_LABEL_LEN: usize = u8::MAX as usize;
+
+/// session, round, op, label length
+pub(crate) const HEADER_LEN: usize = 8 + 8 + 1 + 1;
+
+impl Tag {
+    /// Prefix `bytes` with this tag.
+    pub(crate) fn seal(&self, bytes: &[u8]) -> Vec<u8> {
+        let label = self.label.as_bytes();
+        let mut out = Vec::with_capacity(HEADER_LEN + label.len() + bytes.len());
+        out.extend_from_slice(&self.session.to_le_bytes());
+        out.extend_from_slice(&self.round.to_le_bytes());
+        out.push(self.op as u8);
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    /// Strip the tag from a message of `peer`, checking it matches ours. `at` is where we are in
+    /// the code, for the error message.
+    pub(crate) fn open(
+        &self,
+        peer: usize,
+        mut frame: Vec<u8>,
+        at: &Location<'_>,
+    ) -> NetResult<Vec<u8>> {
+        let theirs = Self::decode(&frame).ok_or_else(|| NetError::MalformedFrame {
+            peer,
+            reason: "missing message header".to_owned(),
+        })?;
+        if theirs.session != self.session
+            || theirs.round != self.round
+            || theirs.op != self.op
+            || theirs.label != self.label
+        {
+            return Err(NetError::Desync {
+                peer,
+                ours: format!("{} (at {})", self, at),
+                theirs: theirs.to_string(),
+            });
+        }
+        frame.drain(..HEADER_LEN + self.label.len());
+        Ok(frame)
+    }
+
+    pub(crate) fn decode(frame: &[u8]) -> Option<Self> {
+        let header = frame.get(..HEADER_LEN)?;
+        let label_len = header[17] as usize;
+        let label = frame.get(HEADER_LEN..HEADER_LEN + label_len)?;
+        Some(Self {
+            session: u64::from_le_bytes(header[..8].try_into().unwrap()),
+            round: u64::from_le_bytes(header[8..16].try_into().unwrap()),
+            op: Op::from_u8(header[16])?,
+            label: String::from_utf8(label.to_vec()).ok()?,
+        })
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in round {}", self.op, self.round)?;
+        if !self.label.is_empty() {
+            write!(f, " of '{}'", self.label)?;
+        }
+        write!(f, ", session {:016x}", self.session)
+    }
+}
+
+/// A stable 64-bit id for a session name (FNV-1a), so every build agrees on it.
+pub(crate) fn session_id(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Cut `label` to at most [MAX_LABEL_LEN] bytes, on a character boundary.
+pub(crate) fn truncate_label(label: &str) -> &str {
+    let mut end = label.len().min(MAX_LABEL_LEN);
+    while !label.is_char_boundary(end) {
+        end -= 1;
+    }
+    &label[..end]
+}
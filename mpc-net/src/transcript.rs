@@ -0,0 +1,460 @@
+//! Per-party transcripts of a session, and replaying them.
+//!
+//! A [Recorder] wraps a party's transport and writes every message it sends or receives to a file,
+//! one JSON object per line. The first line names the party and says where its session was when
+//! recording started; each further line looks like
+//!
+//! ```text
+//! {"dir":"recv","peer":2,"round":7,"op":"broadcast","label":"mul","data":"a3f1..."}
+//! ```
+//!
+//! where `data` is the whole hex-encoded frame, tag included, and `peer` is missing for a
+//! broadcast we sent. Set `transcript` in a host config (see [crate::config]) or call
+//! [MpcSession::record_to] to get one.
+//!
+//! A [Replay] plays a transcript back to the same party: it answers every receive with the
+//! recorded message, so that party's computation can be rerun alone, e.g. under a debugger. The
+//! rerun must take the same steps as the recorded one; the first message it sends in a different
+//! round, operation or label than recorded fails with [NetError::Transcript]. Payloads are allowed
+//! to differ, since fresh randomness changes them.
+//!
+//! [MpcSession::record_to]: crate::MpcSession::record_to
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    secure::{from_hex, to_hex},
+    session::State,
+    tag::Tag,
+    NetError, NetResult, Stats, Transport,
+};
+
+/// The first line of a transcript.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    party: usize,
+    n_parties: usize,
+    /// The session's state when recording started, if it was recorded through a session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<State>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Dir {
+    Sent,
+    Recv,
+}
+
+/// One message. The tag fields are decoded from `data` for the reader's benefit.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    dir: Dir,
+    /// The other end; `None` for a broadcast we sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peer: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    round: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    op: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    data: String,
+}
+
+fn transcript_error(path: &Path, e: impl std::fmt::Display) -> NetError {
+    NetError::Transcript(format!("{}: {}", path.display(), e))
+}
+
+/// A transport that writes everything passing through it to a transcript file.
+#[derive(Debug)]
+pub struct Recorder {
+    inner: Box<dyn Transport>,
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Record what `inner` sends and receives to a new file at `path`.
+    pub fn create(inner: Box<dyn Transport>, path: impl AsRef<Path>) -> NetResult<Self> {
+        Self::start(inner, path.as_ref(), None)
+    }
+
+    /// Like [Recorder::create], noting where the session driving `inner` is.
+    pub(crate) fn start(
+        inner: Box<dyn Transport>,
+        path: &Path,
+        state: Option<&State>,
+    ) -> NetResult<Self> {
+        let file = File::create(path).map_err(|e| transcript_error(path, e))?;
+        let mut recorder = Self {
+            out: BufWriter::new(file),
+            inner,
+        };
+        let header = Header {
+            party: recorder.inner.party_id(),
+            n_parties: recorder.inner.n_parties(),
+            state: state.cloned(),
+        };
+        recorder.write_line(&header)?;
+        recorder.flush()?;
+        Ok(recorder)
+    }
+
+    fn write_line(&mut self, line: &impl Serialize) -> NetResult<()> {
+        serde_json::to_writer(&mut self.out, line)
+            .map_err(|e| NetError::Transcript(e.to_string()))?;
+        self.out
+            .write_all(b"\n")
+            .map_err(|e| NetError::Transcript(e.to_string()))
+    }
+
+    fn record(&mut self, dir: Dir, peer: Option<usize>, frame: &[u8]) -> NetResult<()> {
+        let tag = Tag::decode(frame);
+        self.write_line(&Entry {
+            dir,
+            peer,
+            round: tag.as_ref().map(|t| t.round),
+            op: tag.as_ref().map(|t| t.op.to_string()),
+            label: tag.map(|t| t.label).filter(|l| !l.is_empty()),
+            data: to_hex(frame),
+        })
+    }
+
+    /// Record one message from every other party.
+    fn record_all(&mut self, frames: &[Vec<u8>]) -> NetResult<()> {
+        let id = self.inner.party_id();
+        for (peer, frame) in frames.iter().enumerate().filter(|(peer, _)| *peer != id) {
+            self.record(Dir::Recv, Some(peer), frame)?;
+        }
+        Ok(())
+    }
+
+    /// Written after every operation, so a crash loses nothing.
+    fn flush(&mut self) -> NetResult<()> {
+        self.out
+            .flush()
+            .map_err(|e| NetError::Transcript(e.to_string()))
+    }
+}
+
+impl Transport for Recorder {
+    fn party_id(&self) -> usize {
+        self.inner.party_id()
+    }
+    fn n_parties(&self) -> usize {
+        self.inner.n_parties()
+    }
+    fn is_init(&self) -> bool {
+        self.inner.is_init()
+    }
+    fn uninit(&mut self) {
+        self.inner.uninit()
+    }
+    fn stats(&mut self) -> &mut Stats {
+        self.inner.stats()
+    }
+    fn set_timeouts(&mut self, read: Option<Duration>, write: Option<Duration>) -> NetResult<()> {
+        self.inner.set_timeouts(read, write)
+    }
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        self.record(Dir::Sent, None, bytes_out)?;
+        let bytes_in = self.inner.broadcast(bytes_out)?;
+        self.record_all(&bytes_in)?;
+        self.flush()?;
+        Ok(bytes_in)
+    }
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        if king != self.party_id() {
+            self.record(Dir::Sent, Some(king), bytes_out)?;
+        }
+        let bytes_in = self.inner.send_to_king(king, bytes_out)?;
+        if let Some(bytes_in) = &bytes_in {
+            self.record_all(bytes_in)?;
+        }
+        self.flush()?;
+        Ok(bytes_in)
+    }
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        let id = self.party_id();
+        if let Some(bytes_out) = &bytes_out {
+            for (peer, frame) in bytes_out.iter().enumerate().filter(|(peer, _)| *peer != id) {
+                self.record(Dir::Sent, Some(peer), frame)?;
+            }
+        }
+        let bytes_in = self.inner.recv_from_king(king, bytes_out)?;
+        if king != id {
+            self.record(Dir::Recv, Some(king), &bytes_in)?;
+        }
+        self.flush()?;
+        Ok(bytes_in)
+    }
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.record(Dir::Sent, Some(to), bytes_out)?;
+        self.inner.send_to(to, bytes_out)?;
+        self.flush()
+    }
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        let bytes_in = self.inner.recv_from(from)?;
+        self.record(Dir::Recv, Some(from), &bytes_in)?;
+        self.flush()?;
+        Ok(bytes_in)
+    }
+    fn exchange(&mut self, peer: usize, bytes_out: &[u8]) -> NetResult<Vec<u8>> {
+        self.record(Dir::Sent, Some(peer), bytes_out)?;
+        let bytes_in = self.inner.exchange(peer, bytes_out)?;
+        self.record(Dir::Recv, Some(peer), &bytes_in)?;
+        self.flush()?;
+        Ok(bytes_in)
+    }
+}
+
+/// A transport that plays a [Recorder]'s transcript back to the party that recorded it.
+#[derive(Debug)]
+pub struct Replay {
+    id: usize,
+    n: usize,
+    /// What we sent, in order, to check the rerun against.
+    sent: VecDeque<Entry>,
+    /// `inbox[j]` holds the messages received from party `j`, in order.
+    inbox: Vec<VecDeque<Vec<u8>>>,
+    stats: Stats,
+    connected: bool,
+    state: Option<State>,
+}
+
+impl Replay {
+    /// Load the transcript at `path`.
+    pub fn open(path: impl AsRef<Path>) -> NetResult<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| transcript_error(path, e))?;
+        let mut lines = BufReader::new(file).lines();
+        let mut next_line = || {
+            lines
+                .next()
+                .transpose()
+                .map_err(|e| transcript_error(path, e))
+        };
+        let header: Header = match next_line()? {
+            Some(line) => serde_json::from_str(&line).map_err(|e| transcript_error(path, e))?,
+            None => return Err(transcript_error(path, "empty file")),
+        };
+        let mut replay = Self {
+            id: header.party,
+            n: header.n_parties,
+            sent: VecDeque::new(),
+            inbox: vec![VecDeque::new(); header.n_parties],
+            stats: Stats::default(),
+            connected: true,
+            state: header.state,
+        };
+        while let Some(line) = next_line()? {
+            let entry: Entry =
+                serde_json::from_str(&line).map_err(|e| transcript_error(path, e))?;
+            match (entry.dir, entry.peer) {
+                (Dir::Sent, _) => replay.sent.push_back(entry),
+                (Dir::Recv, Some(peer)) if peer < replay.n && peer != replay.id => {
+                    let frame = from_hex(&entry.data).map_err(|e| transcript_error(path, e))?;
+                    replay.inbox[peer].push_back(frame);
+                }
+                (Dir::Recv, peer) => {
+                    return Err(transcript_error(
+                        path,
+                        format!("message from unknown party {:?}", peer),
+                    ))
+                }
+            }
+        }
+        Ok(replay)
+    }
+
+    /// Where the recorded session was when recording started.
+    pub(crate) fn take_state(&mut self) -> State {
+        self.state.take().unwrap_or_default()
+    }
+
+    /// Check that the rerun sends `frame` to `peer` where the recording did.
+    fn send(&mut self, peer: Option<usize>, frame: &[u8]) -> NetResult<()> {
+        let ours = Tag::decode(frame);
+        let describe = |tag: Option<&Tag>, peer: Option<usize>| {
+            let to = peer.map_or("everyone".to_owned(), |p| format!("party {}", p));
+            match tag {
+                Some(tag) => format!("{} to {}", tag, to),
+                None => format!("an untagged message to {}", to),
+            }
+        };
+        let entry = self.sent.pop_front().ok_or_else(|| {
+            NetError::Transcript(format!(
+                "sent {}, past the end of the transcript",
+                describe(ours.as_ref(), peer)
+            ))
+        })?;
+        let recorded = from_hex(&entry.data).map_err(|e| NetError::Transcript(e.to_string()))?;
+        let theirs = Tag::decode(&recorded);
+        if entry.peer != peer || ours != theirs {
+            return Err(NetError::Transcript(format!(
+                "sent {}, but the transcript has {}",
+                describe(ours.as_ref(), peer),
+                describe(theirs.as_ref(), entry.peer)
+            )));
+        }
+        if recorded != frame {
+            debug!(
+                "sent {} with a different payload",
+                describe(ours.as_ref(), peer)
+            );
+        }
+        self.stats.bytes_sent += frame.len();
+        Ok(())
+    }
+
+    fn recv(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        let frame = self.inbox[from]
+            .pop_front()
+            .ok_or_else(|| NetError::Transcript(format!("no more messages from party {}", from)))?;
+        self.stats.bytes_recv += frame.len();
+        Ok(frame)
+    }
+
+    /// The recorded message from every other party, with `own` in our slot.
+    fn recv_all(&mut self, own: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        (0..self.n)
+            .map(|id| {
+                if id == self.id {
+                    Ok(own.to_vec())
+                } else {
+                    self.recv(id)
+                }
+            })
+            .collect()
+    }
+}
+
+impl Transport for Replay {
+    fn party_id(&self) -> usize {
+        self.id
+    }
+    fn n_parties(&self) -> usize {
+        self.n
+    }
+    fn is_init(&self) -> bool {
+        self.connected
+    }
+    fn uninit(&mut self) {
+        self.connected = false;
+    }
+    fn stats(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+    /// Nothing ever blocks.
+    fn set_timeouts(&mut self, _read: Option<Duration>, _write: Option<Duration>) -> NetResult<()> {
+        Ok(())
+    }
+    fn broadcast(&mut self, bytes_out: &[u8]) -> NetResult<Vec<Vec<u8>>> {
+        self.stats.broadcasts += 1;
+        self.send(None, bytes_out)?;
+        self.recv_all(bytes_out)
+    }
+    fn send_to_king(&mut self, king: usize, bytes_out: &[u8]) -> NetResult<Option<Vec<Vec<u8>>>> {
+        self.stats.to_king += 1;
+        if self.id == king {
+            self.recv_all(bytes_out).map(Some)
+        } else {
+            self.send(Some(king), bytes_out)?;
+            Ok(None)
+        }
+    }
+    fn recv_from_king(
+        &mut self,
+        king: usize,
+        bytes_out: Option<Vec<Vec<u8>>>,
+    ) -> NetResult<Vec<u8>> {
+        self.stats.from_king += 1;
+        if self.id == king {
+            let bytes_out = bytes_out.expect("the king must provide bytes to recv_from_king");
+            assert_eq!(bytes_out.len(), self.n);
+            for (id, bytes) in bytes_out.iter().enumerate() {
+                if id != self.id {
+                    self.send(Some(id), bytes)?;
+                }
+            }
+            Ok(bytes_out[self.id].clone())
+        } else {
+            self.recv(king)
+        }
+    }
+    fn send_to(&mut self, to: usize, bytes_out: &[u8]) -> NetResult<()> {
+        self.send(Some(to), bytes_out)
+    }
+    fn recv_from(&mut self, from: usize) -> NetResult<Vec<u8>> {
+        self.recv(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{local::LocalTransport, MpcMultiNet as Net, MpcNet, MpcSession};
+
+    /// A little of everything: a broadcast, a king call, a ring of direct messages and an
+    /// exchange between parties 0 and 1.
+    fn protocol(id: usize, n: usize, secret: u8) -> Vec<u8> {
+        Net::with_label("sum", || {
+            let all = Net::broadcast_bytes(&[secret]);
+            let total = Net::king_compute(&[all.iter().map(|b| b[0]).sum::<u8>()], |xs| {
+                vec![vec![xs.iter().map(|x| x[0]).max().unwrap()]; xs.len()]
+            });
+            Net::send_bytes_to((id + 1) % n, &[id as u8]);
+            let prev = Net::recv_bytes_from((id + n - 1) % n);
+            let swapped = if id < 2 {
+                Net::exchange_bytes(1 - id, &[secret])
+            } else {
+                vec![]
+            };
+            [total, prev, swapped].concat()
+        })
+    }
+
+    #[test]
+    fn replay_reruns_one_party() {
+        let dir = std::env::temp_dir().join(format!("mpc-transcript-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |id: usize| dir.join(format!("{}.jsonl", id));
+        let outputs: Vec<Vec<u8>> = std::thread::scope(|s| {
+            let handles: Vec<_> = LocalTransport::mesh(3)
+                .into_iter()
+                .enumerate()
+                .map(|(id, t)| {
+                    let session = MpcSession::from_transport(t).record_to(path(id)).unwrap();
+                    let session = Arc::new(session);
+                    s.spawn(move || session.enter(|| protocol(id, 3, 10 + id as u8)))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let replayed = Arc::new(MpcSession::try_replay(path(1)).unwrap());
+        assert_eq!(replayed.enter(|| protocol(1, 3, 11)), outputs[1]);
+
+        // A rerun that strays from the recording is stopped at the first difference.
+        let strayed = Arc::new(MpcSession::try_replay(path(1)).unwrap());
+        let err = strayed
+            .enter(|| Net::try_broadcast_bytes(&[11]))
+            .unwrap_err();
+        assert!(matches!(err, NetError::Transcript(_)), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
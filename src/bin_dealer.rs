@@ -0,0 +1,55 @@
+use ark_bls12_377::Fr;
+use mpc_algebra::dealer::deal_to_stores;
+use mpc_algebra::store::Counts;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Deal SPDZ preprocessing material into each party's store.
+///
+/// INSECURE: the dealer knows the MAC key and every mask. For development only.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "dealer")]
+struct Opt {
+    /// Number of parties
+    #[structopt(long = "num-players")]
+    num_players: usize,
+
+    /// Beaver triples to deal
+    #[structopt(long, default_value = "0")]
+    triples: usize,
+
+    /// Inverse pairs to deal
+    #[structopt(long = "inv-pairs", default_value = "0")]
+    inv_pairs: usize,
+
+    /// Random bits to deal
+    #[structopt(long, default_value = "0")]
+    bits: usize,
+
+    /// Input masks to deal to each party
+    #[structopt(long = "input-masks", default_value = "0")]
+    input_masks: usize,
+
+    /// Directory of the stores, created if missing. Existing stores are appended to.
+    #[structopt(long, parse(from_os_str), default_value = "./preprocessing")]
+    dir: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opt = Opt::from_args();
+    eprintln!("WARNING: the trusted dealer is insecure and only meant for development");
+
+    let counts = Counts {
+        triples: opt.triples,
+        inv_pairs: opt.inv_pairs,
+        bits: opt.bits,
+        input_masks: opt.input_masks,
+    };
+    std::fs::create_dir_all(&opt.dir)?;
+    let stores =
+        deal_to_stores::<Fr, _>(&opt.dir, opt.num_players, counts, &mut rand::thread_rng())?;
+    for store in stores {
+        println!("{}: {:?} left", store.path().display(), store.left());
+    }
+    Ok(())
+}
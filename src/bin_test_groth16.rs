@@ -1,3 +1,5 @@
+use ark_bls12_377::Fr;
+use mpc_algebra::store::PreprocessingOpt;
 use mpc_net::{MpcMultiNet as Net, MpcNet};
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -15,10 +17,16 @@ struct Opt {
     /// Input file
     #[structopt(parse(from_os_str))]
     input: PathBuf,
+
+    #[structopt(flatten)]
+    preprocessing: PreprocessingOpt,
 }
 
 fn main() {
     let opt = Opt::from_args();
     Net::init_from_file(opt.input.to_str().unwrap(), opt.id);
+    opt.preprocessing
+        .load::<Fr>()
+        .expect("Failed to load preprocessing");
     // groth16::mpc_test_prove_and_verify(1);
 }
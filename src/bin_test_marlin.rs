@@ -1,3 +1,5 @@
+use ark_bls12_377::Fr;
+use mpc_algebra::store::PreprocessingOpt;
 use mpc_net::{MpcMultiNet as Net, MpcNet};
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -15,13 +17,43 @@ struct Opt {
     /// Input file
     #[structopt(parse(from_os_str))]
     input: PathBuf,
+
+    #[structopt(flatten)]
+    preprocessing: PreprocessingOpt,
 }
 
 fn main() {
     let opt = Opt::from_args();
     Net::init_from_file(opt.input.to_str().unwrap(), opt.id);
+    opt.preprocessing
+        .load::<Fr>()
+        .expect("Failed to load preprocessing");
     marlin::mpc_test_prove_and_verify(1);
     marlin::mpc_test_prove_and_verify_pedersen(1);
     marlin::test_equality_zero(1);
     marlin::test_bit_decomposition(1);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::marlin;
+    use ark_bls12_377::Fr;
+    use mpc_algebra::store::load_preprocessing_or_dummy;
+    use mpc_net::local::simulate;
+
+    #[test]
+    fn test_prove_and_verify() {
+        simulate(3, |_| {
+            load_preprocessing_or_dummy::<Fr>(None, None).unwrap();
+            marlin::mpc_test_prove_and_verify(1)
+        });
+    }
+
+    #[test]
+    fn test_bit_decomposition() {
+        simulate(3, |_| {
+            load_preprocessing_or_dummy::<Fr>(None, None).unwrap();
+            marlin::test_bit_decomposition(1)
+        });
+    }
+}
@@ -13,14 +13,16 @@ use ark_std::UniformRand;
 use circuits::{DivinationCircuit, ElGamalLocalOrMPC, KeyPublicizeCircuit};
 use core::panic;
 
+use mpc_algebra::channel::{catch_abort, checkpoint};
 use mpc_algebra::malicious_majority::*;
+use mpc_algebra::store::PreprocessingOpt;
 use mpc_algebra::Reveal;
 use serde::Deserialize;
 use serialize::{write_r, write_to_file};
 use std::{fs::File, path::PathBuf};
 use structopt::StructOpt;
 
-use mpc_net::{MpcMultiNet as Net, MpcNet};
+use mpc_net::{MpcMultiNet as Net, MpcNet, NetError};
 
 mod marlin;
 use marlin::*;
@@ -54,6 +56,9 @@ struct Opt {
     // Input address file
     #[structopt(parse(from_os_str))]
     input: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    preprocessing: PreprocessingOpt,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -75,12 +80,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Preprocessing mode");
             // preprocessing calculation of werewolf game
 
-            preprocessing_werewolf(&opt)?;
+            catch_abort(|| preprocessing_werewolf(&opt)).map_err(report_abort)??;
         }
         "night" => {
             println!("Night mode");
             // run the night phase
-            night_werewolf(&opt)?;
+            catch_abort(|| night_werewolf(&opt)).map_err(report_abort)??;
         }
         _ => {
             Err(std::io::Error::new(
@@ -93,6 +98,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Tell the players why the MPC gave up, and who cheated if a check could tell.
+fn report_abort(e: NetError) -> NetError {
+    match e.cheater() {
+        Some(party) => println!("Player {} cheated: {}", party, e),
+        None => println!("Aborted: {}", e),
+    }
+    e
+}
+
 fn initialize_game(opt: &Opt) -> Result<(), std::io::Error> {
     // public.json
     let file_path = "./werewolf/public.json";
@@ -181,6 +195,9 @@ fn preprocessing_werewolf(opt: &Opt) -> Result<(), std::io::Error> {
         opt.input.clone().unwrap().to_str().unwrap(),
         opt.id.unwrap(),
     );
+    opt.preprocessing
+        .load::<Fr>()
+        .expect("Failed to load preprocessing");
 
     // TODO: changable
     let num_players = 3;
@@ -257,6 +274,7 @@ fn preprocessing_werewolf(opt: &Opt) -> Result<(), std::io::Error> {
     // verify
     let is_valid = LocalMarlin::verify(&index_vk, &inputs, &proof, rng).unwrap();
     assert!(is_valid);
+    checkpoint();
 
     // save to file
     if Net::party_id() == 0 {
@@ -286,6 +304,9 @@ fn night_werewolf(opt: &Opt) -> Result<(), std::io::Error> {
         opt.input.clone().unwrap().to_str().unwrap(),
         opt.id.unwrap(),
     );
+    opt.preprocessing
+        .load::<Fr>()
+        .expect("Failed to load preprocessing");
 
     let self_role = get_my_role();
 
@@ -453,6 +474,7 @@ fn multi_divination(_opt: &Opt) -> Result<(), std::io::Error> {
     // verify
     let is_valid = LocalMarlin::verify(&index_vk, &inputs, &proof, rng).unwrap();
     assert!(is_valid);
+    checkpoint();
 
     // save divination reesult
     let file_path = format!("./werewolf/{}/secret_key.json", 0);
@@ -5,6 +5,8 @@ use ark_marlin::IndexProverKey;
 use ark_serialize::{CanonicalDeserialize, Read};
 use ark_std::test_rng;
 
+use mpc_algebra::channel::checkpoint;
+use mpc_algebra::store::PreprocessingOpt;
 use mpc_algebra::Reveal;
 use mpc_net::{MpcMultiNet as Net, MpcNet};
 
@@ -35,6 +37,9 @@ struct Opt {
     // Input address file
     #[structopt(parse(from_os_str))]
     input: PathBuf,
+
+    #[structopt(flatten)]
+    preprocessing: PreprocessingOpt,
 }
 
 #[derive(Debug, Deserialize)]
@@ -107,6 +112,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // init
     Net::init_from_file(opt.input.to_str().unwrap(), opt.id);
+    opt.preprocessing.load::<Fr>()?;
 
     let mut file = File::open(opt.input_file_path).expect("Failed to open file");
     let mut contents = String::new();
@@ -298,6 +304,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             inputs.push(peculiar_a_commitment.y.reveal());
             inputs.push(peculiar_b_commitment.x.reveal());
             inputs.push(peculiar_b_commitment.y.reveal());
+            checkpoint();
 
             assert!(LocalMarlin::verify(&index_vk, &inputs, &proof, rng).unwrap());
         }